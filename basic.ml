@@ -52,7 +52,7 @@ type SList =
 	| End
 
 main_slists () =
-	let list = SList.Cons ("mor", SList.Cons ("din", SList.End ()))
+	let list = SList.Cons ("mor", SList.Cons ("din", SList.End))
 	list
 
 
@@ -103,10 +103,10 @@ map f list =
 	| Cons a ->
 		List.Cons (f a.0, map f a.1)
 	| Nil ->
-		List.Nil ()
+		List.Nil
 
 main () =
-	let someList = List.Cons (7, List.Cons (5, List.Nil ()))
+	let someList = List.Cons (7, List.Cons (5, List.Nil))
 	map (\x -> printi x) someList
 
 
@@ -51,7 +51,7 @@ count_substr sub haystack =
 		0
 	else
 		let split = String_get_first haystack
-		if split.0 == sub then
+		if Char_to_string split.0 == sub then
 			1 + count_substr sub split.1
 		else
 			count_substr sub split.1
@@ -76,7 +76,7 @@ fold_chars_impl f init str i =
 		f (
 			fold_chars_impl f init split.1 (i + 1),
 			i,
-			split.0)
+			Char_to_string split.0)
 
 fold_chars :: ((Bool, Int, String) -> Bool) -> Bool -> String -> Bool
 fold_chars f init str =
@@ -16,7 +16,7 @@ parse_row input =
 		Row.Nil
 	else
 		let split = String_get_first input
-		Row.Cons (split.0 == "#", parse_row split.1)
+		Row.Cons (Char_to_string split.0 == "#", parse_row split.1)
 
 parse :: String -> Map
 parse input =
@@ -1,6 +1,12 @@
-use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::{Debug, Display},
+    rc::Rc,
+};
 
 use super::untyped;
+use crate::parser::Span;
 
 #[derive(Debug, Clone)]
 pub enum TypeDefinition {
@@ -67,6 +73,45 @@ pub struct TypeHandle {
     pub environment: Rc<RefCell<TypeEnvironment>>,
 }
 
+impl TypeHandle {
+    /// Looks up a type declared by the prelude, which is declared ahead of any user types
+    pub fn prelude(environment: &Rc<RefCell<TypeEnvironment>>, name: &str) -> TypeHandle {
+        let index = environment
+            .borrow()
+            .types
+            .iter()
+            .position(|t| t.qualified_name().as_str() == name)
+            .expect(&format!("prelude type not found: {}", name));
+
+        TypeHandle {
+            index,
+            environment: environment.clone(),
+        }
+    }
+
+    pub fn variant_index(&self, name: &str) -> usize {
+        match &self.environment.borrow().types[self.index] {
+            TypeDefinition::Sum { variants, .. } => variants
+                .iter()
+                .position(|(n, _)| n == name)
+                .expect(&format!("variant not found: {}", name)),
+            _ => panic!(),
+        }
+    }
+
+    /// How many fields the variant carries, a tuple payload declares one per element
+    pub fn variant_arity(&self, vi: usize) -> usize {
+        match &self.environment.borrow().types[self.index] {
+            TypeDefinition::Sum { variants, .. } => match &variants[vi].1 {
+                Type::ConstructedType(TypeConstructor::Unit, _) => 0,
+                Type::ConstructedType(TypeConstructor::Tuple(n), _) => *n,
+                _ => 1,
+            },
+            _ => panic!(),
+        }
+    }
+}
+
 impl Debug for TypeHandle {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!(
@@ -100,12 +145,16 @@ pub struct TypeClassImplItem {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TypeConstructor {
     Tuple(usize),
+    List,
+    Ref,
+    Map,
     UserType(TypeHandle),
     Function,
     Int,
     Float,
     String,
     Bool,
+    Char,
     Unit,
 }
 
@@ -115,9 +164,11 @@ impl TypeConstructor {
 
         match self {
             Tuple(n) => *n,
+            List | Ref => 1,
+            Map => 2,
             UserType(th) => th.environment.borrow().types[th.index].generic_arity(),
             Function => 2,
-            Int | Float | String | Bool | Unit => 0,
+            Int | Float | String | Bool | Char | Unit => 0,
         }
     }
 }
@@ -146,11 +197,73 @@ impl Debug for Type {
     }
 }
 
+/// Written the way types are written in programs
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use TypeConstructor::*;
+
+        let (tc, params) = match self {
+            Type::ErrType => return f.write_str("<error>"),
+            Type::TypeVariable(i) => return write!(f, "'{}", i),
+            Type::ConstructedType(tc, params) => (tc, params),
+        };
+
+        match tc {
+            Int => f.write_str("Int"),
+            Float => f.write_str("Float"),
+            String => f.write_str("String"),
+            Bool => f.write_str("Bool"),
+            Char => f.write_str("Char"),
+            Unit => f.write_str("()"),
+            Tuple(_) => {
+                let elems = params.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+                write!(f, "({})", elems.join(", "))
+            }
+            List => match &params[0] {
+                elem @ Type::ConstructedType(Function, _) => write!(f, "({})[]", elem),
+                elem => write!(f, "{}[]", elem),
+            },
+            Function => match &params[0] {
+                arg @ Type::ConstructedType(Function, _) => write!(f, "({}) -> {}", arg, params[1]),
+                arg => write!(f, "{} -> {}", arg, params[1]),
+            },
+            Ref => {
+                f.write_str("Ref")?;
+                write_params(f, params)
+            }
+            Map => {
+                f.write_str("Map")?;
+                write_params(f, params)
+            }
+            UserType(th) => {
+                let name = th.environment.borrow().types[th.index].qualified_name();
+                f.write_str(&name)?;
+                write_params(f, params)
+            }
+        }
+    }
+}
+
+/// the parameters of a type constructor applied by juxtaposition, as in `Map String Int`
+fn write_params(f: &mut std::fmt::Formatter<'_>, params: &[Type]) -> std::fmt::Result {
+    for param in params {
+        match param {
+            Type::ConstructedType(TypeConstructor::Tuple(_), _)
+            | Type::ConstructedType(TypeConstructor::List, _) => write!(f, " {}", param)?,
+            Type::ConstructedType(_, inner) if !inner.is_empty() => write!(f, " ({})", param)?,
+            _ => write!(f, " {}", param)?,
+        }
+    }
+
+    Ok(())
+}
+
 impl Type {
     pub const INT: Type = Self::primitive(TypeConstructor::Int);
     pub const FLOAT: Type = Self::primitive(TypeConstructor::Float);
     pub const STRING: Type = Self::primitive(TypeConstructor::String);
     pub const BOOL: Type = Self::primitive(TypeConstructor::Bool);
+    pub const CHAR: Type = Self::primitive(TypeConstructor::Char);
     pub const UNIT: Type = Self::primitive(TypeConstructor::Unit);
 
     pub const fn primitive(tc: TypeConstructor) -> Type {
@@ -165,10 +278,34 @@ impl Type {
         Type::ConstructedType(TypeConstructor::Tuple(tys.len()), tys)
     }
 
+    pub fn list(t: Type) -> Type {
+        Type::ConstructedType(TypeConstructor::List, vec![t])
+    }
+
+    pub fn reference(t: Type) -> Type {
+        Type::ConstructedType(TypeConstructor::Ref, vec![t])
+    }
+
+    pub fn map(k: Type, v: Type) -> Type {
+        Type::ConstructedType(TypeConstructor::Map, vec![k, v])
+    }
+
     pub fn user_type(th: TypeHandle, params: Vec<Type>) -> Type {
         Type::ConstructedType(TypeConstructor::UserType(th), params)
     }
 
+    /// Replaces the type parameters of a generic type definition with `params`
+    pub fn substitute(&self, params: &[Type]) -> Type {
+        match self {
+            Type::TypeVariable(i) => params.get(*i as usize).cloned().unwrap_or(self.clone()),
+            Type::ConstructedType(tc, tys) => Type::ConstructedType(
+                tc.clone(),
+                tys.iter().map(|t| t.substitute(params)).collect(),
+            ),
+            Type::ErrType => Type::ErrType,
+        }
+    }
+
     pub fn type_constructor(&self) -> Option<&TypeConstructor> {
         match self {
             Type::ConstructedType(ref tc, _) => Some(tc),
@@ -198,14 +335,130 @@ pub enum BuiltInFn {
     StringSplit,
     StringParseInt,
     StringGetFirst,
+    CharToString,
     Printi,
     Print,
+    IntPopcount,
+    IntBits,
+    Uuid,
+    MemoizeBy,
+    Retry,
+    ListAppend,
+    ListConcat,
+    ListProduct2,
+    ListMap,
+    ListFilter,
+    ListFold,
+    ListIsSorted,
+    ListBinarySearch,
+    ListTranspose,
+    ListSpan,
+    FloatLerp,
+    FloatClamp,
+    FloatRoundSig,
+    StringRepeatJoined,
+    StringCommonPrefix,
+    StringWrap,
+    ListDot,
+    PathNormalize,
+    PathJoin,
+    PathExtension,
+    PathBasename,
+    ReadDir,
+    MakeDir,
+    DeleteFile,
+    FileExists,
+    RefNew,
+    RefGet,
+    RefSet,
+    CopyFile,
+    MoveFile,
+    RollingHash,
+    RleEncode,
+    RleDecode,
+    StringTryParseInt,
+    ListStats,
+    StringTryGetFirst,
+    StringParseFloat,
+    MapNew,
+    MapInsert,
+    MapGet,
+    MapRemove,
+    Memoize,
+    Format,
+    Assert,
+    Exit,
+    StringCharAt,
+    StringToChars,
+    IntAbs,
+    IntMin,
+    IntMax,
+    /// the square root, NaN for negative numbers as in IEEE 754
+    FloatSqrt,
+    FloatFloor,
+    FloatCeil,
+    /// rounds half way cases away from zero
+    FloatRound,
+    FileReadLines,
+    JsonParse,
+    DeepCopy,
+    Equals,
+    /// registered by the host, the index of its entry in the `BuiltinRegistry`
+    Host(usize),
+    /// the kind of a value, bools are integers by then
+    TypeOf,
+    /// the char index of the first occurrence, to go with `StringCharAt`
+    StringIndexOf,
+    /// the chars in `[start, end)`
+    Substring,
+    /// a pair of the same type as a list of its two elements
+    TupleToList,
+    /// a list of exactly two elements as a pair
+    ListToTuple,
+    ListLength,
+    /// the element at an index, None when it is out of bounds
+    ListGet,
+    /// folds over `[start, end)` without building a list of it
+    RangeFold,
+    /// everything left on stdin, or in the input the interpreter was given
+    ReadAll,
+    EPrint,
+    EPrintln,
+    VariantTag,
+    VariantName,
+    Show,
+    RangeList,
+    RangeStep,
+    MapKeys,
+    MapValues,
+    StringFold,
+    FileTryRead,
+    Raise,
+    Catch,
+    CharCode,
+    CharFromCode,
+    TimeIt,
+    StringCount,
+    StackDepth,
+    StringWords,
+    StringRepeat,
+    PadLeft,
+    PadRight,
+    Ignore,
+    BoolToString,
+    StringParseBool,
+    ListMin,
+    ListMax,
+    ListSort,
+    ListSortBy,
 }
 
 impl BuiltInFn {
-    pub fn resolved_type(&self) -> Type {
+    pub fn resolved_type(&self, environment: &Rc<RefCell<TypeEnvironment>>) -> Type {
         use BuiltInFn::*;
 
+        let option = |t| Type::user_type(TypeHandle::prelude(environment, "Option"), vec![t]);
+
         match self {
             FileRead => Type::function(Type::STRING, Type::STRING),
             StringSplit => Type::function(
@@ -213,33 +466,578 @@ impl BuiltInFn {
                 Type::tuple(vec![Type::STRING, Type::STRING]),
             ),
             StringParseInt => Type::function(Type::STRING, Type::INT),
+            StringParseFloat => Type::function(Type::STRING, Type::FLOAT),
             StringGetFirst => {
-                Type::function(Type::STRING, Type::tuple(vec![Type::STRING, Type::STRING]))
+                Type::function(Type::STRING, Type::tuple(vec![Type::CHAR, Type::STRING]))
             }
+            CharToString => Type::function(Type::CHAR, Type::STRING),
             Print => Type::function(Type::STRING, Type::UNIT),
             Printi => Type::function(Type::INT, Type::UNIT),
+            IntPopcount => Type::function(Type::INT, Type::INT),
+            IntBits => Type::function(
+                Type::tuple(vec![Type::INT, Type::INT, Type::INT]),
+                Type::INT,
+            ),
+            Uuid => Type::function(Type::UNIT, Type::STRING),
+            MemoizeBy => {
+                let (a, b, k) = (
+                    Type::TypeVariable(0),
+                    Type::TypeVariable(1),
+                    Type::TypeVariable(2),
+                );
+                Type::function(
+                    Type::tuple(vec![
+                        Type::function(a.clone(), b.clone()),
+                        Type::function(a.clone(), k),
+                    ]),
+                    Type::function(a, b),
+                )
+            }
+            Retry => {
+                let r = Type::TypeVariable(0);
+                Type::function(
+                    Type::tuple(vec![Type::INT, Type::function(Type::UNIT, r.clone())]),
+                    r,
+                )
+            }
+            ListAppend => {
+                let a = Type::TypeVariable(0);
+                Type::function(
+                    Type::tuple(vec![Type::list(a.clone()), a.clone()]),
+                    Type::list(a),
+                )
+            }
+            ListConcat => {
+                let list = Type::list(Type::TypeVariable(0));
+                Type::function(Type::tuple(vec![list.clone(), list.clone()]), list)
+            }
+            ListProduct2 => {
+                let (a, b) = (Type::TypeVariable(0), Type::TypeVariable(1));
+                Type::function(
+                    Type::tuple(vec![Type::list(a.clone()), Type::list(b.clone())]),
+                    Type::list(Type::tuple(vec![a, b])),
+                )
+            }
+            ListMap => {
+                let (a, b) = (Type::TypeVariable(0), Type::TypeVariable(1));
+                Type::function(
+                    Type::tuple(vec![Type::function(a.clone(), b.clone()), Type::list(a)]),
+                    Type::list(b),
+                )
+            }
+            ListFilter => {
+                let a = Type::TypeVariable(0);
+                Type::function(
+                    Type::tuple(vec![
+                        Type::function(a.clone(), Type::BOOL),
+                        Type::list(a.clone()),
+                    ]),
+                    Type::list(a),
+                )
+            }
+            ListFold => {
+                let (acc, a) = (Type::TypeVariable(0), Type::TypeVariable(1));
+                Type::function(
+                    Type::tuple(vec![
+                        Type::function(acc.clone(), Type::function(a.clone(), acc.clone())),
+                        acc.clone(),
+                        Type::list(a),
+                    ]),
+                    acc,
+                )
+            }
+            ListIsSorted => {
+                let a = Type::TypeVariable(0);
+                Type::function(
+                    Type::tuple(vec![
+                        Type::list(a.clone()),
+                        Type::function(a.clone(), Type::function(a, Type::BOOL)),
+                    ]),
+                    Type::BOOL,
+                )
+            }
+            ListBinarySearch => {
+                let a = Type::TypeVariable(0);
+                Type::function(
+                    Type::tuple(vec![
+                        Type::list(a.clone()),
+                        a.clone(),
+                        Type::function(a.clone(), Type::function(a, Type::BOOL)),
+                    ]),
+                    option(Type::INT),
+                )
+            }
+            ListTranspose => {
+                let matrix = Type::list(Type::list(Type::TypeVariable(0)));
+                Type::function(matrix.clone(), matrix)
+            }
+            ListSpan => {
+                let list = Type::list(Type::TypeVariable(0));
+                Type::function(
+                    Type::tuple(vec![
+                        list.clone(),
+                        Type::function(Type::TypeVariable(0), Type::BOOL),
+                    ]),
+                    Type::tuple(vec![list.clone(), list]),
+                )
+            }
+            FloatLerp | FloatClamp => Type::function(
+                Type::tuple(vec![Type::FLOAT, Type::FLOAT, Type::FLOAT]),
+                Type::FLOAT,
+            ),
+            FloatRoundSig => Type::function(Type::tuple(vec![Type::FLOAT, Type::INT]), Type::FLOAT),
+            StringRepeatJoined => Type::function(
+                Type::tuple(vec![Type::STRING, Type::INT, Type::STRING]),
+                Type::STRING,
+            ),
+            StringCommonPrefix => {
+                Type::function(Type::tuple(vec![Type::STRING, Type::STRING]), Type::STRING)
+            }
+            StringWrap => Type::function(Type::tuple(vec![Type::STRING, Type::INT]), Type::STRING),
+            ListDot => Type::function(
+                Type::tuple(vec![Type::list(Type::INT), Type::list(Type::INT)]),
+                Type::INT,
+            ),
+            PathNormalize => Type::function(Type::STRING, Type::STRING),
+            PathJoin => Type::function(Type::list(Type::STRING), Type::STRING),
+            PathExtension => Type::function(Type::STRING, option(Type::STRING)),
+            PathBasename => Type::function(Type::STRING, Type::STRING),
+            ReadDir => Type::function(Type::STRING, Type::list(Type::STRING)),
+            MakeDir => Type::function(Type::STRING, Type::UNIT),
+            DeleteFile => Type::function(Type::STRING, Type::UNIT),
+            FileExists => Type::function(Type::STRING, Type::BOOL),
+            RefNew => {
+                let a = Type::TypeVariable(0);
+                Type::function(a.clone(), Type::reference(a))
+            }
+            RefGet => {
+                let a = Type::TypeVariable(0);
+                Type::function(Type::reference(a.clone()), a)
+            }
+            RefSet => {
+                let a = Type::TypeVariable(0);
+                Type::function(Type::tuple(vec![Type::reference(a.clone()), a]), Type::UNIT)
+            }
+            RollingHash => Type::function(
+                Type::tuple(vec![Type::STRING, Type::INT]),
+                Type::list(Type::INT),
+            ),
+            RleEncode => Type::function(
+                Type::STRING,
+                Type::list(Type::tuple(vec![Type::STRING, Type::INT])),
+            ),
+            RleDecode => Type::function(
+                Type::list(Type::tuple(vec![Type::STRING, Type::INT])),
+                Type::STRING,
+            ),
+            StringTryParseInt => Type::function(Type::STRING, option(Type::INT)),
+            ListStats => Type::function(
+                Type::list(Type::INT),
+                Type::tuple(vec![
+                    Type::INT,
+                    Type::INT,
+                    Type::INT,
+                    Type::INT,
+                    Type::FLOAT,
+                ]),
+            ),
+            StringTryGetFirst => Type::function(
+                Type::STRING,
+                option(Type::tuple(vec![Type::CHAR, Type::STRING])),
+            ),
+            MapNew => {
+                let (k, v) = (Type::TypeVariable(0), Type::TypeVariable(1));
+                Type::function(Type::UNIT, Type::map(k, v))
+            }
+            MapInsert => {
+                let (k, v) = (Type::TypeVariable(0), Type::TypeVariable(1));
+                Type::function(
+                    Type::tuple(vec![Type::map(k.clone(), v.clone()), k, v]),
+                    Type::UNIT,
+                )
+            }
+            MapGet | MapRemove => {
+                let (k, v) = (Type::TypeVariable(0), Type::TypeVariable(1));
+                Type::function(
+                    Type::tuple(vec![Type::map(k.clone(), v.clone()), k]),
+                    option(v),
+                )
+            }
+            Memoize => {
+                let (a, b) = (Type::TypeVariable(0), Type::TypeVariable(1));
+                let f = Type::function(a, b);
+                Type::function(f.clone(), f)
+            }
+            Format => Type::function(
+                Type::tuple(vec![Type::STRING, Type::TypeVariable(0)]),
+                Type::STRING,
+            ),
+            // either a Bool or a (Bool, String) pair with a message, checked at runtime
+            Assert => Type::function(Type::TypeVariable(0), Type::UNIT),
+            Exit => Type::function(Type::INT, Type::TypeVariable(0)),
+            StringCharAt => Type::function(Type::tuple(vec![Type::STRING, Type::INT]), Type::CHAR),
+            StringToChars => Type::function(Type::STRING, Type::list(Type::CHAR)),
+            IntAbs => Type::function(Type::INT, Type::INT),
+            IntMin | IntMax => Type::function(Type::tuple(vec![Type::INT, Type::INT]), Type::INT),
+            FloatSqrt | FloatFloor | FloatCeil | FloatRound => {
+                Type::function(Type::FLOAT, Type::FLOAT)
+            }
+            FileReadLines => Type::function(Type::STRING, Type::list(Type::STRING)),
+            // the shape of the json is only known at runtime
+            JsonParse => Type::function(
+                Type::STRING,
+                Type::user_type(TypeHandle::prelude(environment, "Json"), vec![]),
+            ),
+            DeepCopy => Type::function(Type::TypeVariable(0), Type::TypeVariable(0)),
+            Equals => {
+                let a = Type::TypeVariable(0);
+                Type::function(Type::tuple(vec![a.clone(), a]), Type::BOOL)
+            }
+            TypeOf => Type::function(Type::TypeVariable(0), Type::STRING),
+            StringIndexOf => Type::function(
+                Type::tuple(vec![Type::STRING, Type::STRING]),
+                option(Type::INT),
+            ),
+            Substring => Type::function(
+                Type::tuple(vec![Type::STRING, Type::INT, Type::INT]),
+                Type::STRING,
+            ),
+            TupleToList => {
+                let a = Type::TypeVariable(0);
+                Type::function(Type::tuple(vec![a.clone(), a.clone()]), Type::list(a))
+            }
+            // how long the list is can only be checked at runtime
+            ListToTuple => {
+                let a = Type::TypeVariable(0);
+                Type::function(Type::list(a.clone()), Type::tuple(vec![a.clone(), a]))
+            }
+            ListLength => Type::function(Type::list(Type::TypeVariable(0)), Type::INT),
+            ListGet => {
+                let a = Type::TypeVariable(0);
+                Type::function(Type::tuple(vec![Type::list(a.clone()), Type::INT]), option(a))
+            }
+            RangeFold => {
+                let acc = Type::TypeVariable(0);
+                Type::function(
+                    Type::tuple(vec![
+                        Type::INT,
+                        Type::INT,
+                        acc.clone(),
+                        Type::function(acc.clone(), Type::function(Type::INT, acc.clone())),
+                    ]),
+                    acc,
+                )
+            }
+            ReadAll => Type::function(Type::UNIT, Type::STRING),
+            EPrint | EPrintln => Type::function(Type::STRING, Type::UNIT),
+            VariantTag => Type::function(Type::TypeVariable(0), Type::INT),
+            VariantName => Type::function(Type::TypeVariable(0), Type::STRING),
+            Show => Type::function(Type::TypeVariable(0), Type::UNIT),
+            RangeList => Type::function(
+                Type::tuple(vec![Type::INT, Type::INT]),
+                Type::list(Type::INT),
+            ),
+            RangeStep => Type::function(
+                Type::tuple(vec![Type::INT, Type::INT, Type::INT]),
+                Type::list(Type::INT),
+            ),
+            MapKeys => {
+                let (k, v) = (Type::TypeVariable(0), Type::TypeVariable(1));
+                Type::function(Type::map(k.clone(), v), Type::list(k))
+            }
+            MapValues => {
+                let (k, v) = (Type::TypeVariable(0), Type::TypeVariable(1));
+                Type::function(Type::map(k, v.clone()), Type::list(v))
+            }
+            StringFold => {
+                let acc = Type::TypeVariable(0);
+                Type::function(
+                    Type::tuple(vec![
+                        Type::STRING,
+                        acc.clone(),
+                        Type::function(acc.clone(), Type::function(Type::CHAR, acc.clone())),
+                    ]),
+                    acc,
+                )
+            }
+            FileTryRead => Type::function(
+                Type::STRING,
+                Type::user_type(
+                    TypeHandle::prelude(environment, "Result"),
+                    vec![Type::STRING, Type::STRING],
+                ),
+            ),
+            Raise => Type::function(Type::TypeVariable(0), Type::TypeVariable(1)),
+            Catch => {
+                let r = Type::TypeVariable(0);
+                let failure = Type::user_type(
+                    TypeHandle::prelude(environment, "Failure"),
+                    vec![Type::TypeVariable(1)],
+                );
+                Type::function(
+                    Type::tuple(vec![
+                        Type::function(Type::UNIT, r.clone()),
+                        Type::function(failure, r.clone()),
+                    ]),
+                    r,
+                )
+            }
+            CharCode => Type::function(Type::CHAR, Type::INT),
+            CharFromCode => Type::function(Type::INT, Type::CHAR),
+            TimeIt => {
+                let r = Type::TypeVariable(0);
+                Type::function(
+                    Type::function(Type::UNIT, r.clone()),
+                    Type::tuple(vec![r, Type::INT]),
+                )
+            }
+            StringCount => {
+                Type::function(Type::tuple(vec![Type::STRING, Type::STRING]), Type::INT)
+            }
+            StackDepth => Type::function(Type::UNIT, Type::INT),
+            StringWords => Type::function(Type::STRING, Type::list(Type::STRING)),
+            StringRepeat => {
+                Type::function(Type::tuple(vec![Type::STRING, Type::INT]), Type::STRING)
+            }
+            PadLeft | PadRight => Type::function(
+                Type::tuple(vec![Type::STRING, Type::INT, Type::CHAR]),
+                Type::STRING,
+            ),
+            Ignore => Type::function(Type::TypeVariable(0), Type::UNIT),
+            BoolToString => Type::function(Type::BOOL, Type::STRING),
+            StringParseBool => Type::function(Type::STRING, Type::BOOL),
+            ListMin | ListMax => {
+                let a = Type::TypeVariable(0);
+                Type::function(Type::list(a.clone()), option(a))
+            }
+            ListSort => {
+                let a = Type::TypeVariable(0);
+                Type::function(Type::list(a.clone()), Type::list(a))
+            }
+            ListSortBy => {
+                let a = Type::TypeVariable(0);
+                Type::function(
+                    Type::tuple(vec![
+                        Type::list(a.clone()),
+                        Type::function(a.clone(), Type::function(a.clone(), Type::BOOL)),
+                    ]),
+                    Type::list(a),
+                )
+            }
+            // typed when they are registered
+            Host(_) => unreachable!("host builtins carry their own type"),
+            CopyFile | MoveFile => {
+                Type::function(Type::tuple(vec![Type::STRING, Type::STRING]), Type::UNIT)
+            }
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum PatternT {
+    Wildcard,
+    Binding(String),
+    /// variant index and the pattern for its payload, if any
+    Variant(usize, Option<Box<PatternT>>),
+    Tuple(Vec<PatternT>),
+    Record(Vec<(String, PatternT)>),
+}
+
 #[derive(Debug, Clone)]
 pub enum ExprT {
     Conditional(Box<TypedExpr>, Box<TypedExpr>, Box<TypedExpr>),
-    Lambda(String, Box<TypedExpr>),
-    BinaryOp(untyped::Operator, Box<TypedExpr>, Box<TypedExpr>),
-    MatchSum(Box<TypedExpr>, Vec<(usize, Option<String>, TypedExpr)>),
-    Record(Vec<TypedExpr>),
+    IfThen(Box<TypedExpr>, Box<TypedExpr>),
+    While(Box<TypedExpr>, Box<TypedExpr>),
+    Sequence(Vec<TypedExpr>),
+    /// `e?`
+    Propagate(Box<TypedExpr>, Span),
+    /// the body and a lambda handling what it failed with, if it fails recoverably
+    Try(Box<TypedExpr>, Box<TypedExpr>),
+    /// the parameter, the body and the free variables of the body, which
+    /// are what a closure created from it has to capture. Closures share the body.
+    Lambda(String, Rc<TypedExpr>, Vec<String>),
+    BinaryOp(untyped::Operator, Box<TypedExpr>, Box<TypedExpr>, Span),
+    UnaryOp(untyped::UnaryOperator, Box<TypedExpr>, Span),
+    MatchSum(Box<TypedExpr>, Vec<(PatternT, TypedExpr)>, Span),
+    Record(Vec<(String, TypedExpr)>),
+    RecordUpdate(Box<TypedExpr>, Vec<(String, TypedExpr)>),
     Tuple(Vec<TypedExpr>),
-    Application(Box<TypedExpr>, Vec<TypedExpr>),
+    ListLiteral(Vec<TypedExpr>),
+    Application(Box<TypedExpr>, Vec<TypedExpr>, Span),
     FieldAccess(Box<TypedExpr>, usize),
-    LetBinding(String, Box<TypedExpr>, Box<TypedExpr>),
-    Symbol(String),
+    RecordFieldAccess(Box<TypedExpr>, String),
+    LetBinding(PatternT, Box<TypedExpr>, Box<TypedExpr>),
+    Symbol(String, Span),
     VariantConstructor(TypeHandle, usize),
     StringLiteral(String),
     IntegerLiteral(i64),
+    FloatLiteral(f64),
     BooleanLiteral(bool),
     BuiltInFn(BuiltInFn),
     Unit,
 }
 
 pub type TypedExpr = (ExprT, Type);
+
+impl ExprT {
+    /// A lambda over `body`, the variables it captures are worked out once
+    /// here rather than every time a closure is created from it
+    pub fn lambda(param: String, body: TypedExpr) -> ExprT {
+        let mut captures: Vec<_> = free_variables(&body)
+            .into_iter()
+            .filter(|name| *name != param)
+            .collect();
+        captures.sort();
+
+        ExprT::Lambda(param, Rc::new(body), captures)
+    }
+
+    /// The source span of the expression, for the kinds of expression that keep one
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ExprT::BinaryOp(.., span)
+            | ExprT::UnaryOp(.., span)
+            | ExprT::MatchSum(.., span)
+            | ExprT::Application(.., span)
+            | ExprT::Propagate(.., span)
+            | ExprT::Symbol(.., span) => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// The name of the variant, for reporting how often each kind of expression ran
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            ExprT::Conditional(..) => "Conditional",
+            ExprT::IfThen(..) => "IfThen",
+            ExprT::While(..) => "While",
+            ExprT::Sequence(..) => "Sequence",
+            ExprT::Propagate(..) => "Propagate",
+            ExprT::Try(..) => "Try",
+            ExprT::Lambda(..) => "Lambda",
+            ExprT::BinaryOp(..) => "BinaryOp",
+            ExprT::UnaryOp(..) => "UnaryOp",
+            ExprT::MatchSum(..) => "MatchSum",
+            ExprT::Record(..) => "Record",
+            ExprT::RecordUpdate(..) => "RecordUpdate",
+            ExprT::Tuple(..) => "Tuple",
+            ExprT::ListLiteral(..) => "ListLiteral",
+            ExprT::Application(..) => "Application",
+            ExprT::FieldAccess(..) => "FieldAccess",
+            ExprT::RecordFieldAccess(..) => "RecordFieldAccess",
+            ExprT::LetBinding(..) => "LetBinding",
+            ExprT::Symbol(..) => "Symbol",
+            ExprT::VariantConstructor(..) => "VariantConstructor",
+            ExprT::StringLiteral(..) => "StringLiteral",
+            ExprT::IntegerLiteral(..) => "IntegerLiteral",
+            ExprT::FloatLiteral(..) => "FloatLiteral",
+            ExprT::BooleanLiteral(..) => "BooleanLiteral",
+            ExprT::BuiltInFn(..) => "BuiltInFn",
+            ExprT::Unit => "Unit",
+        }
+    }
+}
+
+impl PatternT {
+    pub fn bound_names<'a>(&'a self, out: &mut Vec<&'a str>) {
+        match self {
+            PatternT::Wildcard => (),
+            PatternT::Binding(name) => out.push(name),
+            PatternT::Variant(_, payload) => {
+                if let Some(p) = payload {
+                    p.bound_names(out);
+                }
+            }
+            PatternT::Tuple(patterns) => patterns.iter().for_each(|p| p.bound_names(out)),
+            PatternT::Record(fields) => fields.iter().for_each(|(_, p)| p.bound_names(out)),
+        }
+    }
+}
+
+/// Collects the symbols `expr` references without binding them itself,
+/// which is what a closure over `expr` has to capture.
+pub fn free_variables(expr: &TypedExpr) -> HashSet<String> {
+    let mut free = HashSet::new();
+    collect_free_variables(expr, &mut Vec::new(), &mut free);
+    free
+}
+
+fn collect_free_variables<'a>(
+    (expr, _): &'a TypedExpr,
+    bound: &mut Vec<&'a str>,
+    free: &mut HashSet<String>,
+) {
+    match expr {
+        ExprT::Symbol(s, _) => {
+            if !bound.contains(&s.as_str()) {
+                free.insert(s.clone());
+            }
+        }
+        ExprT::Lambda(_, _, captures) => {
+            for name in captures {
+                if !bound.contains(&name.as_str()) {
+                    free.insert(name.clone());
+                }
+            }
+        }
+        ExprT::LetBinding(pattern, rhs, body) => {
+            collect_free_variables(rhs, bound, free);
+
+            let depth = bound.len();
+            pattern.bound_names(bound);
+            collect_free_variables(body, bound, free);
+            bound.truncate(depth);
+        }
+        ExprT::MatchSum(matchee, arms, _) => {
+            collect_free_variables(matchee, bound, free);
+
+            for (pattern, body) in arms {
+                let depth = bound.len();
+                pattern.bound_names(bound);
+                collect_free_variables(body, bound, free);
+                bound.truncate(depth);
+            }
+        }
+        ExprT::Conditional(cond, cons, alt) => {
+            collect_free_variables(cond, bound, free);
+            collect_free_variables(cons, bound, free);
+            collect_free_variables(alt, bound, free);
+        }
+        ExprT::IfThen(cond, body) | ExprT::While(cond, body) => {
+            collect_free_variables(cond, bound, free);
+            collect_free_variables(body, bound, free);
+        }
+        ExprT::BinaryOp(_, lhs, rhs, _) | ExprT::Try(lhs, rhs) => {
+            collect_free_variables(lhs, bound, free);
+            collect_free_variables(rhs, bound, free);
+        }
+        ExprT::UnaryOp(_, e, _)
+        | ExprT::Propagate(e, _)
+        | ExprT::FieldAccess(e, _)
+        | ExprT::RecordFieldAccess(e, _) => collect_free_variables(e, bound, free),
+        ExprT::Record(fields) => fields
+            .iter()
+            .for_each(|(_, e)| collect_free_variables(e, bound, free)),
+        ExprT::RecordUpdate(base, fields) => {
+            collect_free_variables(base, bound, free);
+            fields
+                .iter()
+                .for_each(|(_, e)| collect_free_variables(e, bound, free));
+        }
+        ExprT::Tuple(exprs) | ExprT::ListLiteral(exprs) | ExprT::Sequence(exprs) => exprs
+            .iter()
+            .for_each(|e| collect_free_variables(e, bound, free)),
+        ExprT::Application(f, args, _) => {
+            collect_free_variables(f, bound, free);
+            args.iter()
+                .for_each(|e| collect_free_variables(e, bound, free));
+        }
+        ExprT::VariantConstructor(..)
+        | ExprT::StringLiteral(_)
+        | ExprT::IntegerLiteral(_)
+        | ExprT::FloatLiteral(_)
+        | ExprT::BooleanLiteral(_)
+        | ExprT::BuiltInFn(_)
+        | ExprT::Unit => (),
+    }
+}
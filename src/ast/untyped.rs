@@ -15,6 +15,7 @@ pub enum Ty {
     Float,
     String,
     Bool,
+    Char,
 }
 
 #[derive(Debug, Clone)]
@@ -82,31 +83,118 @@ pub enum Operator {
     BinOpEquals,
     BinOpAnd,
     BinOpOr,
+    /// euclidean remainder, never negative: `-1 % 5 == 4` and `7 % -3 == 1`
     BinOpMod,
+    /// integer exponentiation, negative exponents are an error
+    BinOpPow,
+    BinOpShl,
+    /// arithmetic shift, the sign bit is kept
+    BinOpShr,
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Operator::*;
+
+        let symbol = match self {
+            BinOpMul => "*",
+            BinOpDiv => "/",
+            BinOpAdd => "+",
+            BinOpSub => "-",
+            BinOpGreater => ">",
+            BinOpGreaterEq => ">=",
+            BinOpLess => "<",
+            BinOpLessEq => "<=",
+            BinOpEquals => "==",
+            BinOpAnd => "and",
+            BinOpOr => "or",
+            BinOpMod => "%",
+            BinOpPow => "^",
+            BinOpShl => "<<",
+            BinOpShr => ">>",
+        };
+
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UnaryOperator {
+    UnOpNeg,
+    UnOpNot,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard(Span),
+    Binding(Spanned<String>),
+    Variant(Spanned<String>, Option<Box<Pattern>>),
+    Tuple(Vec<Pattern>),
+    Record(Span, Vec<(Spanned<String>, Pattern)>),
+}
+
+impl Pattern {
+    pub fn span(&self) -> Span {
+        use Pattern::*;
+
+        match self {
+            Wildcard(s) => *s,
+            Binding(b) => b.1,
+            Variant(v, payload) => payload
+                .as_ref()
+                .map_or(v.1, |payload| v.1.encompass(payload.span())),
+            Tuple(patterns) => patterns
+                .iter()
+                .map(|p| p.span())
+                .fold_first(|s1, s2| s1.encompass(s2))
+                .unwrap(),
+            Record(s, _) => *s,
+        }
+    }
+
+    /// whether the pattern matches every value of the type it was checked against
+    pub fn is_irrefutable(&self) -> bool {
+        use Pattern::*;
+
+        match self {
+            Wildcard(_) | Binding(_) => true,
+            Variant(..) => false,
+            Tuple(patterns) => patterns.iter().all(|p| p.is_irrefutable()),
+            Record(_, fields) => fields.iter().all(|(_, p)| p.is_irrefutable()),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Expr {
-    LetBinding(Spanned<String>, Box<Expr>, Box<Expr>),
+    LetBinding(Pattern, Box<Expr>, Box<Expr>),
     Lambda(Spanned<String>, Box<Expr>),
     Application(Box<Expr>, Vec<Expr>),
     GroupedExpr(Box<Expr>),
 
     Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
-    Match(
-        Box<Expr>,
-        Vec<(Spanned<String>, Option<Spanned<String>>, Expr)>,
-    ),
+    /// `if cond then cons` without an else, `cons` is run for its effects
+    IfThen(Box<Expr>, Box<Expr>),
+    While(Box<Expr>, Box<Expr>),
+    Sequence(Vec<Expr>),
+    /// `e?`, unwraps an `Ok` or returns an `Err` from the enclosing function
+    Propagate(Box<Expr>, Span),
+    /// `try body with e -> handler`, the handler being a lambda over what the body failed with
+    Try(Box<Expr>, Box<Expr>),
+    Match(Box<Expr>, Vec<(Pattern, Expr)>),
 
     BinaryOp(Operator, Box<Expr>, Box<Expr>),
+    UnaryOp(Spanned<UnaryOperator>, Box<Expr>),
 
     FieldAccess(Box<Expr>, Spanned<String>),
     Record(Vec<(Spanned<String>, Expr)>),
+    RecordUpdate(Box<Expr>, Vec<(Spanned<String>, Expr)>),
     Tuple(Vec<Expr>),
     Symbol(Spanned<String>),
-    ListConstructor(),
+    ListConstructor(Spanned<Vec<Expr>>),
     StringLiteral(Spanned<String>),
     IntegerLiteral(Spanned<i64>),
+    FloatLiteral(Spanned<f64>),
     BooleanLiteral(Spanned<bool>),
     Unit(Span),
 }
@@ -118,10 +206,15 @@ impl Expr {
         match self {
             FieldAccess(e, s) => e.span().encompass(s.1),
             IntegerLiteral(i) => i.1,
+            FloatLiteral(f) => f.1,
             Symbol(s) => s.1,
             Lambda(p, e) => p.1.encompass(e.span()),
             BooleanLiteral(b) => b.1,
             Record(_fields) => Span(Position(0, 0), Position(0, 0)),
+            RecordUpdate(base, updates) => updates
+                .iter()
+                .map(|(_, e)| e.span())
+                .fold(base.span(), |acc, s| acc.encompass(s)),
             StringLiteral(s) => s.1,
             Application(l, r) => l.span().encompass(
                 r.iter()
@@ -129,13 +222,23 @@ impl Expr {
                     .fold_first(|s1, s2| s1.encompass(s2))
                     .unwrap(),
             ),
-            ListConstructor() => Span(Position(0, 0), Position(0, 0)),
+            ListConstructor(l) => l.1,
             GroupedExpr(e) => e.span(),
-            LetBinding(p, r, b) => p.1.encompass(r.span().encompass(b.span())),
+            LetBinding(p, r, b) => p.span().encompass(r.span().encompass(b.span())),
             BinaryOp(_o, e, r) => e.span().encompass(r.span()),
+            UnaryOp(o, e) => o.1.encompass(e.span()),
             Conditional(cond, cons, alt) => {
                 cond.span().encompass(cons.span()).encompass(alt.span())
             }
+            IfThen(cond, cons) => cond.span().encompass(cons.span()),
+            While(cond, body) => cond.span().encompass(body.span()),
+            Propagate(e, question) => e.span().encompass(*question),
+            Try(body, handler) => body.span().encompass(handler.span()),
+            Sequence(exprs) => exprs
+                .iter()
+                .map(|e| e.span())
+                .fold_first(|s1, s2| s1.encompass(s2))
+                .unwrap(),
             Tuple(fields) => fields
                 .iter()
                 .map(|e| e.span())
@@ -143,7 +246,7 @@ impl Expr {
                 .unwrap(),
             Match(expr, arms) => arms
                 .iter()
-                .map(|(_, _, e)| e.span())
+                .map(|(_, e)| e.span())
                 .fold(expr.span(), |acc, s| acc.encompass(s)),
             Unit(s) => *s,
         }
@@ -0,0 +1,255 @@
+//! The names builtins are bound to in programs. Besides the builtins the
+//! interpreter implements itself, a host embedding the language can register
+//! its own, and bind constants for programs to read, before typechecking.
+
+use crate::{
+    ast::typed::{BuiltInFn, Type},
+    interpret::{Interpreter, RuntimeResult, Value},
+};
+
+/// Implements a builtin registered by the host, taking the argument it was applied to
+pub type BuiltinHandler = fn(&mut Interpreter, Value) -> RuntimeResult<Value>;
+
+#[derive(Debug, Clone)]
+pub enum Builtin {
+    /// implemented by `Interpreter::call_builtin`
+    Native(BuiltInFn),
+    /// registered by the host, with the type programs see it at
+    Host(Type, BuiltinHandler),
+    /// a constant bound by the host
+    Global(Type, Value),
+}
+
+#[derive(Debug, Clone)]
+pub struct BuiltinRegistry {
+    entries: Vec<(String, Builtin)>,
+}
+
+const NATIVE_BUILTINS: &[(&str, BuiltInFn)] = &[
+    ("File_read", BuiltInFn::FileRead),
+    ("String_split", BuiltInFn::StringSplit),
+    ("String_parse_int", BuiltInFn::StringParseInt),
+    ("String_get_first", BuiltInFn::StringGetFirst),
+    ("Char_to_string", BuiltInFn::CharToString),
+    ("print", BuiltInFn::Print),
+    ("printi", BuiltInFn::Printi),
+    ("Int_popcount", BuiltInFn::IntPopcount),
+    ("Int_bits", BuiltInFn::IntBits),
+    ("uuid", BuiltInFn::Uuid),
+    ("memoize_by", BuiltInFn::MemoizeBy),
+    ("retry", BuiltInFn::Retry),
+    ("List_append", BuiltInFn::ListAppend),
+    ("List_concat", BuiltInFn::ListConcat),
+    ("List_product", BuiltInFn::ListProduct2),
+    ("List_map", BuiltInFn::ListMap),
+    ("List_filter", BuiltInFn::ListFilter),
+    ("List_fold", BuiltInFn::ListFold),
+    ("List_is_sorted", BuiltInFn::ListIsSorted),
+    ("List_binary_search", BuiltInFn::ListBinarySearch),
+    ("List_transpose", BuiltInFn::ListTranspose),
+    ("List_span", BuiltInFn::ListSpan),
+    ("Float_lerp", BuiltInFn::FloatLerp),
+    ("Float_clamp", BuiltInFn::FloatClamp),
+    ("Float_round_sig", BuiltInFn::FloatRoundSig),
+    ("String_repeat_joined", BuiltInFn::StringRepeatJoined),
+    ("String_common_prefix", BuiltInFn::StringCommonPrefix),
+    ("String_wrap", BuiltInFn::StringWrap),
+    ("List_dot", BuiltInFn::ListDot),
+    ("Path_normalize", BuiltInFn::PathNormalize),
+    ("Path_join", BuiltInFn::PathJoin),
+    ("Path_extension", BuiltInFn::PathExtension),
+    ("Path_basename", BuiltInFn::PathBasename),
+    ("Dir_read", BuiltInFn::ReadDir),
+    ("Dir_make", BuiltInFn::MakeDir),
+    ("File_delete", BuiltInFn::DeleteFile),
+    ("File_exists", BuiltInFn::FileExists),
+    ("Ref_new", BuiltInFn::RefNew),
+    ("Ref_get", BuiltInFn::RefGet),
+    ("Ref_set", BuiltInFn::RefSet),
+    ("File_copy", BuiltInFn::CopyFile),
+    ("File_move", BuiltInFn::MoveFile),
+    ("String_rolling_hash", BuiltInFn::RollingHash),
+    ("String_rle_encode", BuiltInFn::RleEncode),
+    ("String_rle_decode", BuiltInFn::RleDecode),
+    ("String_try_parse_int", BuiltInFn::StringTryParseInt),
+    ("List_stats", BuiltInFn::ListStats),
+    ("String_try_get_first", BuiltInFn::StringTryGetFirst),
+    ("String_parse_float", BuiltInFn::StringParseFloat),
+    ("Map_new", BuiltInFn::MapNew),
+    ("Map_insert", BuiltInFn::MapInsert),
+    ("Map_get", BuiltInFn::MapGet),
+    ("Map_remove", BuiltInFn::MapRemove),
+    ("memoize", BuiltInFn::Memoize),
+    ("format", BuiltInFn::Format),
+    ("assert", BuiltInFn::Assert),
+    ("exit", BuiltInFn::Exit),
+    ("String_char_at", BuiltInFn::StringCharAt),
+    ("String_to_chars", BuiltInFn::StringToChars),
+    ("Int_abs", BuiltInFn::IntAbs),
+    ("Int_min", BuiltInFn::IntMin),
+    ("Int_max", BuiltInFn::IntMax),
+    ("Float_sqrt", BuiltInFn::FloatSqrt),
+    ("Float_floor", BuiltInFn::FloatFloor),
+    ("Float_ceil", BuiltInFn::FloatCeil),
+    ("Float_round", BuiltInFn::FloatRound),
+    ("File_read_lines", BuiltInFn::FileReadLines),
+    ("Json_parse", BuiltInFn::JsonParse),
+    ("deep_copy", BuiltInFn::DeepCopy),
+    ("equals", BuiltInFn::Equals),
+    ("type_of", BuiltInFn::TypeOf),
+    ("String_index_of", BuiltInFn::StringIndexOf),
+    ("String_substring", BuiltInFn::Substring),
+    ("Tuple_to_list", BuiltInFn::TupleToList),
+    ("List_to_tuple", BuiltInFn::ListToTuple),
+    ("List_length", BuiltInFn::ListLength),
+    ("List_get", BuiltInFn::ListGet),
+    ("Range_fold", BuiltInFn::RangeFold),
+    ("Stdin_read_all", BuiltInFn::ReadAll),
+    ("eprint", BuiltInFn::EPrint),
+    ("eprintln", BuiltInFn::EPrintln),
+    ("variant_tag", BuiltInFn::VariantTag),
+    ("variant_name", BuiltInFn::VariantName),
+    ("show", BuiltInFn::Show),
+    ("Range_list", BuiltInFn::RangeList),
+    ("Range_step", BuiltInFn::RangeStep),
+    ("Map_keys", BuiltInFn::MapKeys),
+    ("Map_values", BuiltInFn::MapValues),
+    ("String_fold", BuiltInFn::StringFold),
+    ("File_try_read", BuiltInFn::FileTryRead),
+    ("raise", BuiltInFn::Raise),
+    ("catch", BuiltInFn::Catch),
+    ("Char_code", BuiltInFn::CharCode),
+    ("Char_from_code", BuiltInFn::CharFromCode),
+    ("time_it", BuiltInFn::TimeIt),
+    ("String_count", BuiltInFn::StringCount),
+    ("stack_depth", BuiltInFn::StackDepth),
+    ("String_words", BuiltInFn::StringWords),
+    ("String_repeat", BuiltInFn::StringRepeat),
+    ("String_pad_left", BuiltInFn::PadLeft),
+    ("String_pad_right", BuiltInFn::PadRight),
+    ("ignore", BuiltInFn::Ignore),
+    ("Bool_to_string", BuiltInFn::BoolToString),
+    ("String_parse_bool", BuiltInFn::StringParseBool),
+    ("List_min", BuiltInFn::ListMin),
+    ("List_max", BuiltInFn::ListMax),
+    ("List_sort", BuiltInFn::ListSort),
+    ("List_sort_by", BuiltInFn::ListSortBy),
+];
+
+impl Default for BuiltinRegistry {
+    /// Every builtin the interpreter implements itself
+    fn default() -> Self {
+        Self {
+            entries: NATIVE_BUILTINS
+                .iter()
+                .map(|(name, f)| (name.to_string(), Builtin::Native(*f)))
+                .collect(),
+        }
+    }
+}
+
+impl BuiltinRegistry {
+    pub fn empty() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Binds `name` to `handler` in programs typechecked with this registry,
+    /// replacing any builtin that was bound to the same name
+    pub fn register_builtin(&mut self, name: &str, ty: Type, handler: BuiltinHandler) {
+        self.insert(name, Builtin::Host(ty, handler));
+    }
+
+    /// Binds `name` to `value` in programs typechecked with this registry,
+    /// replacing any builtin that was bound to the same name
+    pub fn bind_global(&mut self, name: &str, ty: Type, value: Value) {
+        self.insert(name, Builtin::Global(ty, value));
+    }
+
+    fn insert(&mut self, name: &str, builtin: Builtin) {
+        match self.entries.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing)) => *existing = builtin,
+            None => self.entries.push((name.to_owned(), builtin)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Builtin> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, builtin)| builtin)
+    }
+
+    /// The builtins in the order they were registered, a host builtin is
+    /// referred to as `BuiltInFn::Host` with its position in this order
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &Builtin)> {
+        self.entries.iter().map(|(n, builtin)| (n.as_str(), builtin))
+    }
+
+    pub(crate) fn global(&self, name: &str) -> Option<&Value> {
+        match self.get(name) {
+            Some(Builtin::Global(_, value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn host_type(&self, index: usize) -> Option<&Type> {
+        match self.entries.get(index) {
+            Some((_, Builtin::Host(ty, _))) => Some(ty),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn handler(&self, index: usize) -> Option<BuiltinHandler> {
+        match self.entries.get(index) {
+            Some((_, Builtin::Host(_, handler))) => Some(*handler),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    use crate::{eval_string_with_builtins, interpret::RuntimeError};
+
+    fn double(_: &mut Interpreter, arg: Value) -> RuntimeResult<Value> {
+        match arg {
+            Value::Integer(i) => Ok(Value::Integer(i * 2)),
+            v => Err(RuntimeError::TypeMismatch(format!("double of {}", v))),
+        }
+    }
+
+    #[test]
+    fn host_builtins_can_be_called() {
+        let mut builtins = BuiltinRegistry::default();
+        builtins.register_builtin("double", Type::function(Type::INT, Type::INT), double);
+
+        let v = eval_string_with_builtins("main () = double 21 + 1\n", builtins).unwrap();
+        assert!(matches!(v, Value::Integer(43)), "{:?}", v);
+    }
+
+    #[test]
+    fn globals_can_be_read() {
+        let mut builtins = BuiltinRegistry::default();
+        builtins.bind_global("answer", Type::INT, Value::Integer(42));
+        builtins.bind_global("greeting", Type::STRING, Value::String(Rc::new("hi".to_owned())));
+
+        let v = eval_string_with_builtins("main () = (answer - 2, greeting)\n", builtins).unwrap();
+        assert_eq!(v.to_string(), "(40, hi)");
+    }
+
+    #[test]
+    fn globals_are_seen_from_any_function_unless_shadowed() {
+        let mut builtins = BuiltinRegistry::default();
+        builtins.bind_global("limit", Type::INT, Value::Integer(10));
+
+        let src = "clamp :: Int -> Int\nclamp x = if x > limit then limit else x\n\n\
+                   main () =\n\tlet a = clamp 25\n\tlet limit = 3\n\t(a, clamp 7, limit)\n";
+        let v = eval_string_with_builtins(src, builtins).unwrap();
+        assert_eq!(v.to_string(), "(10, 7, 3)");
+    }
+}
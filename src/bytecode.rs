@@ -0,0 +1,608 @@
+//! An alternative to the tree walking interpreter: function bodies are lowered
+//! into flat bytecode once and then run on a small stack machine.
+//!
+//! Only a subset of the language is supported so far. Programs using anything
+//! else fail with `RuntimeError::Unimplemented` when the function containing
+//! it is first called.
+
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::typed::*,
+    ast::untyped::{Operator, UnaryOperator},
+    interpret::{
+        binary_op, condition, unary_op, Interpreter, InterpreterConfig, RuntimeError,
+        RuntimeResult, Value,
+    },
+    parser::Span,
+    typecheck::TypeChecked,
+};
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    Const(Value),
+    Load(usize),
+    Store(usize),
+    Pop,
+    /// replaces the tuple on top of the stack with its elements, the first one on top
+    Unpack(usize),
+    Tuple(usize),
+    List(usize),
+    Field(usize),
+    Binary(Operator, Span),
+    Unary(UnaryOperator, Span),
+    Jump(usize),
+    JumpIfFalse(usize),
+    /// calls a top level function with as many arguments as it has parameters
+    Call(Rc<String>, usize, Span),
+    CallBuiltin(BuiltInFn, Span),
+}
+
+#[derive(Debug)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub params: usize,
+}
+
+struct Compiler<'a> {
+    code: Vec<Op>,
+    /// the local each slot holds, later slots shadow earlier ones
+    locals: Vec<&'a str>,
+    /// the span of the innermost expression being compiled which has one
+    span: Option<Span>,
+}
+
+impl<'a> Compiler<'a> {
+    fn compile_function(expr: &'a TypedExpr) -> RuntimeResult<Chunk> {
+        let mut compiler = Compiler {
+            code: Vec::new(),
+            locals: Vec::new(),
+            span: None,
+        };
+
+        let mut body = expr;
+        while let (ExprT::Lambda(p, inner, _), _) = body {
+            compiler.locals.push(p);
+            body = inner;
+        }
+        let params = compiler.locals.len();
+
+        compiler.compile(body)?;
+
+        Ok(Chunk {
+            code: compiler.code,
+            params,
+        })
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    /// points the jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let target = self.code.len();
+        match &mut self.code[at] {
+            Op::Jump(to) | Op::JumpIfFalse(to) => *to = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn slot(&self, name: &str) -> Option<usize> {
+        self.locals.iter().rposition(|l| *l == name)
+    }
+
+    /// an error for a construct the compiler can't lower yet, located at
+    /// the closest span it knows of
+    fn unsupported(&self, what: &str) -> RuntimeError {
+        let e = RuntimeError::Unimplemented(format!("bytecode for {}", what));
+        match self.span {
+            Some(span) => e.at(span),
+            None => e,
+        }
+    }
+
+    fn compile(&mut self, typed_expr: &'a TypedExpr) -> RuntimeResult<()> {
+        let outer = self.span;
+        if let Some(span) = typed_expr.0.span() {
+            self.span = Some(span);
+        }
+
+        let result = self.compile_expr(typed_expr);
+        self.span = outer;
+        result
+    }
+
+    fn compile_expr(&mut self, (expr, _t): &'a TypedExpr) -> RuntimeResult<()> {
+        match expr {
+            ExprT::Unit => {
+                self.emit(Op::Const(Value::Unit));
+            }
+            ExprT::IntegerLiteral(i) => {
+                self.emit(Op::Const(Value::Integer(*i)));
+            }
+            ExprT::FloatLiteral(f) => {
+                self.emit(Op::Const(Value::Float(*f)));
+            }
+            ExprT::BooleanLiteral(b) => {
+                self.emit(Op::Const(Value::Integer(*b as i64)));
+            }
+            ExprT::StringLiteral(s) => {
+                self.emit(Op::Const(Value::String(Rc::new(s.clone()))));
+            }
+            ExprT::Symbol(s, span) => match self.slot(s) {
+                Some(slot) => {
+                    self.emit(Op::Load(slot));
+                }
+                None => {
+                    return Err(RuntimeError::Unimplemented(format!(
+                        "bytecode for {} used as a value",
+                        s
+                    ))
+                    .at(*span))
+                }
+            },
+            ExprT::Tuple(exprs) | ExprT::ListLiteral(exprs) => {
+                for e in exprs {
+                    self.compile(e)?;
+                }
+
+                if let ExprT::Tuple(_) = expr {
+                    self.emit(Op::Tuple(exprs.len()));
+                } else {
+                    self.emit(Op::List(exprs.len()));
+                }
+            }
+            ExprT::FieldAccess(lhs, i) => {
+                self.compile(lhs)?;
+                self.emit(Op::Field(*i));
+            }
+            ExprT::BinaryOp(Operator::BinOpAnd, lhs, rhs, _) => {
+                self.compile(lhs)?;
+                let short_circuit = self.emit(Op::JumpIfFalse(0));
+                self.compile(rhs)?;
+                let end = self.emit(Op::Jump(0));
+                self.patch(short_circuit);
+                self.emit(Op::Const(Value::Integer(0)));
+                self.patch(end);
+            }
+            ExprT::BinaryOp(Operator::BinOpOr, lhs, rhs, _) => {
+                self.compile(lhs)?;
+                let eval_rhs = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Const(Value::Integer(1)));
+                let end = self.emit(Op::Jump(0));
+                self.patch(eval_rhs);
+                self.compile(rhs)?;
+                self.patch(end);
+            }
+            ExprT::BinaryOp(op, lhs, rhs, span) => {
+                self.compile(lhs)?;
+                self.compile(rhs)?;
+                self.emit(Op::Binary(*op, *span));
+            }
+            ExprT::UnaryOp(op, operand, span) => {
+                self.compile(operand)?;
+                self.emit(Op::Unary(*op, *span));
+            }
+            ExprT::Conditional(cond, cons, alt) => {
+                self.compile(cond)?;
+                let to_alt = self.emit(Op::JumpIfFalse(0));
+                self.compile(cons)?;
+                let end = self.emit(Op::Jump(0));
+                self.patch(to_alt);
+                self.compile(alt)?;
+                self.patch(end);
+            }
+            ExprT::IfThen(cond, cons) => {
+                self.compile(cond)?;
+                let to_end = self.emit(Op::JumpIfFalse(0));
+                self.compile(cons)?;
+                let end = self.emit(Op::Jump(0));
+                self.patch(to_end);
+                self.emit(Op::Const(Value::Unit));
+                self.patch(end);
+            }
+            ExprT::While(cond, body) => {
+                let start = self.code.len();
+                self.compile(cond)?;
+                let exit = self.emit(Op::JumpIfFalse(0));
+                self.compile(body)?;
+                self.emit(Op::Pop);
+                self.emit(Op::Jump(start));
+                self.patch(exit);
+                self.emit(Op::Const(Value::Unit));
+            }
+            ExprT::Sequence(exprs) => {
+                for (i, e) in exprs.iter().enumerate() {
+                    if i != 0 {
+                        self.emit(Op::Pop);
+                    }
+                    self.compile(e)?;
+                }
+            }
+            ExprT::LetBinding(pattern, rhs, body) => {
+                self.compile(rhs)?;
+
+                let depth = self.locals.len();
+                self.bind(pattern)?;
+                self.compile(body)?;
+                self.locals.truncate(depth);
+            }
+            ExprT::Application(lhs, args, span) => {
+                for arg in args {
+                    self.compile(arg)?;
+                }
+
+                match &lhs.0 {
+                    ExprT::BuiltInFn(builtin) if args.len() == 1 => {
+                        self.emit(Op::CallBuiltin(*builtin, *span));
+                    }
+                    ExprT::Symbol(name, _) if self.slot(name).is_none() => {
+                        self.emit(Op::Call(Rc::new(name.clone()), args.len(), *span));
+                    }
+                    _ => {
+                        return Err(RuntimeError::Unimplemented(
+                            "bytecode for calling a function value".to_owned(),
+                        )
+                        .at(*span))
+                    }
+                }
+            }
+            e => return Err(self.unsupported(e.kind_name())),
+        }
+
+        Ok(())
+    }
+
+    /// binds the value on top of the stack to the names in `pattern`
+    fn bind(&mut self, pattern: &'a PatternT) -> RuntimeResult<()> {
+        match pattern {
+            PatternT::Wildcard => {
+                self.emit(Op::Pop);
+            }
+            PatternT::Binding(name) => {
+                self.locals.push(name);
+                self.emit(Op::Store(self.locals.len() - 1));
+            }
+            PatternT::Tuple(patterns) => {
+                self.emit(Op::Unpack(patterns.len()));
+                for p in patterns {
+                    self.bind(p)?;
+                }
+            }
+            PatternT::Variant(..) => return Err(self.unsupported("variant patterns")),
+            PatternT::Record(_) => return Err(self.unsupported("record patterns")),
+        }
+
+        Ok(())
+    }
+}
+
+/// how deeply calls may nest
+const MAX_CALL_DEPTH: usize = 100_000;
+
+struct Vm {
+    program: TypeChecked,
+    chunks: HashMap<String, Rc<Chunk>>,
+    /// runs the builtins the same way the tree walker does, and holds the
+    /// fuel and deadline of the config
+    interpreter: Interpreter,
+}
+
+/// a call waiting for the function it called to return
+struct Frame {
+    chunk: Rc<Chunk>,
+    pc: usize,
+    locals: Vec<Value>,
+    stack: Vec<Value>,
+    /// where the function it is waiting for was called
+    call_site: Span,
+}
+
+impl Vm {
+    fn chunk(&mut self, name: &str) -> RuntimeResult<Rc<Chunk>> {
+        if let Some(chunk) = self.chunks.get(name) {
+            return Ok(chunk.clone());
+        }
+
+        let expr = {
+            let env = self.program.environment.borrow();
+            env.root_scope
+                .bindings
+                .get(name)
+                .ok_or_else(|| RuntimeError::UnboundSymbol(name.to_owned(), None))?
+                .clone()
+        };
+
+        let chunk = Rc::new(Compiler::compile_function(&expr)?);
+        self.chunks.insert(name.to_owned(), chunk.clone());
+        Ok(chunk)
+    }
+
+    /// Runs `chunk` to completion. Calls push a frame instead of recursing, so
+    /// how deep they go is limited by `MAX_CALL_DEPTH` rather than the native stack.
+    fn run(&mut self, chunk: Rc<Chunk>, args: Vec<Value>) -> RuntimeResult<Value> {
+        let mut frames = Vec::new();
+        // an error without a location of its own happened at the innermost call
+        self.run_frames(chunk, args, &mut frames)
+            .map_err(|e| match frames.last() {
+                Some(frame) => e.at(frame.call_site),
+                None => e,
+            })
+    }
+
+    fn run_frames(
+        &mut self,
+        mut chunk: Rc<Chunk>,
+        args: Vec<Value>,
+        frames: &mut Vec<Frame>,
+    ) -> RuntimeResult<Value> {
+        let mut locals = args;
+        let mut stack: Vec<Value> = Vec::new();
+        let mut pc = 0;
+
+        loop {
+            let current = chunk.clone();
+            let op = match current.code.get(pc) {
+                Some(op) => op,
+                None => {
+                    let result = stack.pop().unwrap();
+                    match frames.pop() {
+                        Some(frame) => {
+                            chunk = frame.chunk;
+                            pc = frame.pc;
+                            locals = frame.locals;
+                            stack = frame.stack;
+                            stack.push(result);
+                            continue;
+                        }
+                        None => return Ok(result),
+                    }
+                }
+            };
+            pc += 1;
+            self.interpreter.charge()?;
+
+            match op {
+                Op::Const(v) => stack.push(v.clone()),
+                Op::Load(slot) => stack.push(locals[*slot].clone()),
+                Op::Store(slot) => {
+                    let v = stack.pop().unwrap();
+                    if *slot < locals.len() {
+                        locals[*slot] = v;
+                    } else {
+                        locals.push(v);
+                    }
+                }
+                Op::Pop => {
+                    stack.pop();
+                }
+                Op::Unpack(n) => match stack.pop().unwrap() {
+                    Value::Tuple(values) if values.len() == *n => {
+                        stack.extend(values.into_iter().rev())
+                    }
+                    v => {
+                        return Err(RuntimeError::TypeMismatch(format!(
+                            "cannot destructure {} into {} values",
+                            v, n
+                        )))
+                    }
+                },
+                Op::Tuple(n) => {
+                    let values = stack.split_off(stack.len() - n);
+                    stack.push(Value::Tuple(values));
+                }
+                Op::List(n) => {
+                    let values = stack.split_off(stack.len() - n);
+                    stack.push(Value::List(values));
+                }
+                Op::Field(i) => match stack.pop().unwrap() {
+                    Value::Tuple(values) if *i < values.len() => stack.push(values[*i].clone()),
+                    _ => {
+                        return Err(RuntimeError::TypeMismatch(format!(
+                            "field access .{} on a value which is not a large enough tuple",
+                            i
+                        )))
+                    }
+                },
+                Op::Binary(op, span) => {
+                    let r = stack.pop().unwrap();
+                    let l = stack.pop().unwrap();
+                    stack.push(binary_op(*op, l, r).map_err(|e| e.at(*span))?);
+                }
+                Op::Unary(op, span) => {
+                    let v = stack.pop().unwrap();
+                    stack.push(unary_op(*op, v).map_err(|e| e.at(*span))?);
+                }
+                Op::Jump(to) => pc = *to,
+                Op::JumpIfFalse(to) => {
+                    if !condition(stack.pop().unwrap())? {
+                        pc = *to;
+                    }
+                }
+                Op::Call(name, argc, span) => {
+                    let callee = self.chunk(name).map_err(|e| e.at(*span))?;
+                    if callee.params != *argc {
+                        return Err(RuntimeError::Unimplemented(format!(
+                            "bytecode for partially applying {}",
+                            name
+                        ))
+                        .at(*span));
+                    }
+                    if frames.len() == MAX_CALL_DEPTH {
+                        return Err(RuntimeError::StackOverflow(MAX_CALL_DEPTH).at(*span));
+                    }
+
+                    let args = stack.split_off(stack.len() - argc);
+                    frames.push(Frame {
+                        chunk: std::mem::replace(&mut chunk, callee),
+                        pc: std::mem::replace(&mut pc, 0),
+                        locals: std::mem::replace(&mut locals, args),
+                        stack: std::mem::take(&mut stack),
+                        call_site: *span,
+                    });
+                }
+                Op::CallBuiltin(builtin, span) => {
+                    let arg = stack.pop().unwrap();
+                    self.interpreter
+                        .call_builtin(*builtin, arg)
+                        .map_err(|e| e.at(*span))?;
+                    stack.push(self.interpreter.pop_val().unwrap());
+                }
+            }
+        }
+    }
+}
+
+/// Runs `main` of `program` on the bytecode machine
+pub fn interpret_bytecode(program: TypeChecked) -> RuntimeResult<Value> {
+    interpret_bytecode_with_config(program, InterpreterConfig::default())
+}
+
+/// Runs `main` of `program` on the bytecode machine, each instruction using up
+/// a unit of the config's fuel
+pub fn interpret_bytecode_with_config(
+    program: TypeChecked,
+    config: InterpreterConfig,
+) -> RuntimeResult<Value> {
+    let mut vm = Vm {
+        interpreter: Interpreter::with_config(program.clone(), config),
+        program,
+        chunks: HashMap::new(),
+    };
+
+    let main = vm.chunk("main")?;
+    let args = vec![Value::Unit; main.params];
+    vm.run(main, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpret::interpret, typecheck_source};
+    use std::time::{Duration, Instant};
+
+    /// runs `src` on both interpreters and checks they agree
+    fn same_as_tree_walker(src: &str) {
+        let walked = interpret(typecheck_source(src)).map(|v| v.to_string());
+        let compiled = interpret_bytecode(typecheck_source(src)).map(|v| v.to_string());
+        match (walked, compiled) {
+            (Ok(w), Ok(c)) => assert_eq!(w, c, "results differ for {}", src),
+            (w, c) => panic!("{} failed: {:?} vs {:?}", src, w, c),
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        same_as_tree_walker("main () = (5 - 2) * 7 + 3 * 7 + 9 / 2 - -4\n");
+        same_as_tree_walker("main () = (1 < 2, 2 <= 1, 3 == 3, 4 > 5)\n");
+    }
+
+    #[test]
+    fn conditionals() {
+        same_as_tree_walker(
+            "pick :: Int -> Int\npick i = if i > 5 then i + i else i * i\n\n\
+             main () = (pick 3, pick 8)\n",
+        );
+    }
+
+    #[test]
+    fn let_and_tuple_destructuring() {
+        same_as_tree_walker(
+            "main () =\n\tlet x = 4\n\tlet (_, (a, b)) = (1, (2, x))\n\tlet x = a + b\n\tx\n",
+        );
+    }
+
+    #[test]
+    fn recursive_calls() {
+        same_as_tree_walker(
+            "fib :: Int -> Int\nfib n = if n < 2 then n else fib (n - 1) + fib (n - 2)\n\n\
+             main () = fib 15\n",
+        );
+    }
+
+    #[test]
+    fn builtins() {
+        same_as_tree_walker("main () = (Int_popcount 7, String_parse_int \"42\")\n");
+    }
+
+    #[test]
+    fn and_or_short_circuit() {
+        same_as_tree_walker(
+            "main () = (false and 1 / 0 == 0, true or 1 / 0 == 0, true and false, false or true)\n",
+        );
+    }
+
+    /// `e` without the location and calls wrapped around it
+    fn innermost(mut e: RuntimeError) -> RuntimeError {
+        while let RuntimeError::Located(_, inner) | RuntimeError::Traced(_, inner) = e {
+            e = *inner;
+        }
+        e
+    }
+
+    #[test]
+    fn fuel_is_used_per_instruction() {
+        let src = "spin :: Int -> Int\nspin n = spin (n + 1)\n\nmain () = spin 0\n";
+        let config = InterpreterConfig {
+            fuel: Some(1000),
+            ..InterpreterConfig::default()
+        };
+        match interpret_bytecode_with_config(typecheck_source(src), config).map_err(innermost) {
+            Err(RuntimeError::OutOfFuel) => {}
+            r => panic!("expected to run out of fuel, got {:?}", r),
+        }
+
+        let config = InterpreterConfig {
+            fuel: Some(10),
+            ..InterpreterConfig::default()
+        };
+        let result = interpret_bytecode_with_config(typecheck_source("main () = 1 + 2\n"), config);
+        assert_eq!(result.unwrap().to_string(), "3");
+    }
+
+    #[test]
+    fn timeout_stops_a_long_computation() {
+        let src = "fib :: Int -> Int\nfib n = if n < 2 then n else fib (n - 1) + fib (n - 2)\n\n\
+                   main () = fib 40\n";
+        let config = InterpreterConfig {
+            timeout: Some(Duration::from_millis(50)),
+            ..InterpreterConfig::default()
+        };
+        let started = Instant::now();
+        match interpret_bytecode_with_config(typecheck_source(src), config).map_err(innermost) {
+            Err(RuntimeError::Timeout) => {}
+            r => panic!("expected to time out, got {:?}", r),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn deep_recursion_is_an_error() {
+        let src = "count :: Int -> Int\ncount n = if n == 0 then 0 else 1 + count (n - 1)\n\n\
+                   main () = count 200000\n";
+        match interpret_bytecode(typecheck_source(src)).map_err(innermost) {
+            Err(RuntimeError::StackOverflow(limit)) => assert_eq!(limit, MAX_CALL_DEPTH),
+            r => panic!("expected to nest too deeply, got {:?}", r),
+        }
+
+        // far deeper than recursing on the native stack of the test's thread could go
+        let src = "count :: Int -> Int\ncount n = if n == 0 then 0 else 1 + count (n - 1)\n\n\
+                   main () = count 50000\n";
+        assert_eq!(interpret_bytecode(typecheck_source(src)).unwrap().to_string(), "50000");
+    }
+
+    #[test]
+    fn unsupported_constructs_are_located() {
+        let src = "main :: () -> Int\nmain () =\n\
+                   \tmatch Some 1 with\n\t| Some x -> x\n\t| None -> 0\n";
+        match interpret_bytecode(typecheck_source(src)) {
+            Err(e @ RuntimeError::Located(..)) => {
+                assert!(e.span().is_some());
+                assert!(format!("{:?}", e).contains("\"bytecode for MatchSum\""), "{:?}", e);
+            }
+            r => panic!("expected a located error, got {:?}", r),
+        }
+    }
+}
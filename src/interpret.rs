@@ -1,27 +1,926 @@
-use std::{collections::HashMap, fmt::Debug, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
+    fmt::{Debug, Display},
+    io::Read,
+    panic::AssertUnwindSafe,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
-use crate::{ast::typed::TypedExpr, ast::typed::*, typecheck::TypeChecked};
+use crate::{
+    ast::typed::TypedExpr,
+    ast::typed::*,
+    ast::untyped::{Operator, UnaryOperator},
+    json,
+    parser::Span,
+    typecheck::{closest_name, TypeChecked},
+};
 
 #[derive(Debug, Clone)]
+pub enum RuntimeError {
+    /// no arm of the match expression at `Span` matched the named variant (or value)
+    NonExhaustiveMatch(Span, String),
+    /// a value of the wrong kind reached an operation or builtin
+    TypeMismatch(String),
+    /// a binary operator isn't defined for operands of these kinds
+    OperandMismatch(Operator, String, String),
+    /// the condition of an `if` or `while` wasn't a Bool, with the kind it was
+    ConditionNotBool(String),
+    /// a symbol with nothing bound to it, with the closest name that is bound if any is close
+    UnboundSymbol(String, Option<String>),
+    NotAFunction(String),
+    /// the named function was applied to more arguments than the number it took
+    TooManyArguments(String, usize),
+    /// a builtin got a tuple of the wrong size, with the expected and actual size
+    ArityMismatch(BuiltInFn, usize, usize),
+    /// a builtin got an argument of the wrong type, with the type it expected
+    /// and the kinds of value it got
+    ArgumentMismatch(BuiltInFn, String, String),
+    /// a builtin got a value of the right type it can't do anything with
+    InvalidArgument(BuiltInFn, String),
+    /// the path involved and the error reported by the os
+    IoError(String, String),
+    /// a builtin needs a capability the interpreter wasn't given
+    MissingCapability(BuiltInFn, &'static str),
+    ArithmeticError(String),
+    /// the interpreter panicked, with the panic's message
+    InternalError(String),
+    /// the program evaluated more expressions than its fuel allowed
+    OutOfFuel,
+    /// the program ran past the deadline it was given
+    Timeout,
+    /// calls were nested deeper than the given limit
+    StackOverflow(usize),
+    /// an `assert` failed, with its message if it had one
+    AssertionFailed(Option<String>),
+    /// the program called `raise` with this value
+    Raised(Value),
+    Unimplemented(String),
+    /// not an error, the program called `exit` with this code
+    Exit(i64),
+    /// not an error, an `Err` propagated by `?` on its way out of the function
+    Return(Value),
+    /// an error along with the span of the expression it happened in
+    Located(Span, Box<RuntimeError>),
+    /// an error along with the calls that led to it, outermost first
+    Traced(Vec<CallFrame>, Box<RuntimeError>),
+}
+
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    pub function: String,
+    /// where the function was called from, the entry point has no call site
+    pub call_site: Option<Span>,
+}
+
+impl RuntimeError {
+    /// attaches `span` unless the error already knows where it happened
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            RuntimeError::Located(..)
+            | RuntimeError::NonExhaustiveMatch(..)
+            | RuntimeError::Exit(_)
+            | RuntimeError::Return(_) => self,
+            e => RuntimeError::Located(span, box e),
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            RuntimeError::Located(span, _) | RuntimeError::NonExhaustiveMatch(span, _) => {
+                Some(*span)
+            }
+            RuntimeError::Traced(_, e) => e.span(),
+            _ => None,
+        }
+    }
+
+    /// The value passed to `raise`, if that is what caused the error
+    pub fn raised(&self) -> Option<&Value> {
+        match self {
+            RuntimeError::Raised(v) => Some(v),
+            RuntimeError::Located(_, e) | RuntimeError::Traced(_, e) => e.raised(),
+            _ => None,
+        }
+    }
+
+    /// Whether a program may `catch` the error. Running out of fuel or time has
+    /// to stop the program, and exiting or returning aren't failures at all.
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            RuntimeError::OutOfFuel
+            | RuntimeError::Timeout
+            | RuntimeError::StackOverflow(_)
+            | RuntimeError::InternalError(_)
+            | RuntimeError::Exit(_)
+            | RuntimeError::Return(_) => false,
+            RuntimeError::Located(_, e) | RuntimeError::Traced(_, e) => e.is_recoverable(),
+            _ => true,
+        }
+    }
+
+    fn argument_mismatch(builtin: BuiltInFn, expected: &Type, arg: &Value) -> Self {
+        match (expected, arg) {
+            (Type::ConstructedType(TypeConstructor::Tuple(n), _), Value::Tuple(values))
+                if values.len() != *n =>
+            {
+                RuntimeError::ArityMismatch(builtin, *n, values.len())
+            }
+            _ => {
+                fn shape(v: &Value) -> String {
+                    match v {
+                        Value::Tuple(values) => {
+                            let elems = values.iter().map(shape).collect::<Vec<_>>();
+                            format!("({})", elems.join(", "))
+                        }
+                        v => v.kind(),
+                    }
+                }
+
+                RuntimeError::ArgumentMismatch(builtin, expected.to_string(), shape(arg))
+            }
+        }
+    }
+
+    fn unexpected_argument(builtin: BuiltInFn) -> Self {
+        RuntimeError::TypeMismatch(format!("unexpected argument to builtin {:?}", builtin))
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use RuntimeError::*;
+
+        match self {
+            NonExhaustiveMatch(span, unmatched) => write!(
+                f,
+                "no match arm for {} in match at {}:{}",
+                unmatched, span.0 .0, span.0 .1
+            ),
+            TypeMismatch(msg) => write!(f, "type mismatch: {}", msg),
+            OperandMismatch(op, l, r) => write!(f, "cannot apply {} to {} and {}", op, l, r),
+            ConditionNotBool(kind) => write!(f, "condition must be a Bool, got {}", kind),
+            UnboundSymbol(s, None) => write!(f, "unbound symbol {}", s),
+            UnboundSymbol(s, Some(similar)) => {
+                write!(f, "unbound symbol {}, did you mean {}?", s, similar)
+            }
+            NotAFunction(v) => write!(f, "{} is not a function", v),
+            TooManyArguments(function, taken) => write!(
+                f,
+                "{} takes {} arguments but was applied to more",
+                function, taken
+            ),
+            ArgumentMismatch(builtin, expected, found) => write!(
+                f,
+                "builtin {:?} expects an argument of type {} but got {}",
+                builtin, expected, found
+            ),
+            ArityMismatch(builtin, expected, found) => write!(
+                f,
+                "builtin {:?} takes {} arguments, got {}",
+                builtin, expected, found
+            ),
+            InvalidArgument(builtin, msg) => write!(f, "{:?}: {}", builtin, msg),
+            IoError(path, msg) => write!(f, "{}: {}", path, msg),
+            MissingCapability(builtin, capability) => {
+                write!(
+                    f,
+                    "builtin {:?} needs the {} capability",
+                    builtin, capability
+                )
+            }
+            ArithmeticError(msg) => write!(f, "arithmetic error: {}", msg),
+            InternalError(msg) => write!(f, "internal interpreter error: {}", msg),
+            OutOfFuel => write!(f, "ran out of fuel"),
+            Timeout => write!(f, "timed out"),
+            StackOverflow(limit) => write!(f, "calls nested deeper than {}", limit),
+            AssertionFailed(Some(msg)) => write!(f, "assertion failed: {}", msg),
+            AssertionFailed(None) => write!(f, "assertion failed"),
+            Raised(v) => write!(f, "raised {}", v),
+            Exit(code) => write!(f, "exited with code {}", code),
+            Return(v) => write!(f, "{} returned outside of a function", v),
+            Unimplemented(what) => write!(f, "not implemented: {}", what),
+            Located(span, e) => write!(f, "{}:{}: {}", span.0 .0, span.0 .1, e),
+            Traced(frames, e) => {
+                write!(f, "{}", e)?;
+                for frame in frames.iter().rev() {
+                    match frame.call_site {
+                        Some(span) => write!(
+                            f,
+                            "\n  in {} at {}:{}",
+                            frame.function, span.0 .0, span.0 .1
+                        )?,
+                        None => write!(f, "\n  in {}", frame.function)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub type RuntimeResult<T> = Result<T, RuntimeError>;
+
+#[derive(Clone)]
 pub enum Value {
     Unit,
     Tuple(Vec<Value>),
-    Function(Rc<String>, Vec<(String, Value)>, *const TypedExpr),
+    List(Vec<Value>),
+    Record(Vec<(String, Value)>),
+    Function(Rc<String>, Vec<(String, Value)>, Rc<TypedExpr>),
     String(Rc<String>),
+    Char(char),
     Integer(i64),
-    Variant(TypeHandle, usize, Rc<Value>),
+    Float(f64),
+    /// a variant of a sum type with its fields, nullary variants have none
+    Variant(TypeHandle, usize, Rc<Vec<Value>>),
     VariantConstructorFn(TypeHandle, usize),
     BuiltInFn(BuiltInFn),
+    Memoized(Rc<Memo>),
+    /// a mutable cell, shared between every copy of the value
+    Ref(Rc<RefCell<Value>>),
+    /// a mutable hash map, keeping the original key value next to each entry
+    Map(Rc<RefCell<ValueMap>>),
 }
 
+/// A function wrapped by `memoize_by` or `memoize`, caching results on the
+/// value of `key_fn`, or on the argument itself when there is none
 #[derive(Debug)]
-struct Interpreter {
+pub struct Memo {
+    pub function: Value,
+    pub key_fn: Option<Value>,
+    pub cache: RefCell<HashMap<HashKey, Value>>,
+}
+
+/// The subset of values which can be hashed and compared for equality
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    Unit,
+    Integer(i64),
+    String(Rc<String>),
+    Char(char),
+    Tuple(Vec<HashKey>),
+    List(Vec<HashKey>),
+    Record(Vec<(String, HashKey)>),
+    Variant(usize, usize, Vec<HashKey>),
+}
+
+/// A hash map which iterates in the order keys were first inserted, so programs
+/// walking over a map behave the same on every run
+#[derive(Debug, Clone, Default)]
+pub struct ValueMap {
+    indices: HashMap<HashKey, usize>,
+    entries: Vec<(HashKey, (Value, Value))>,
+}
+
+impl ValueMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &HashKey) -> Option<&(Value, Value)> {
+        self.indices.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    /// Replacing the entry of a key that is already present keeps its position
+    pub fn insert(&mut self, key: HashKey, entry: (Value, Value)) -> Option<(Value, Value)> {
+        match self.indices.get(&key) {
+            Some(&i) => Some(std::mem::replace(&mut self.entries[i].1, entry)),
+            None => {
+                self.indices.insert(key.clone(), self.entries.len());
+                self.entries.push((key, entry));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &HashKey) -> Option<(Value, Value)> {
+        let removed = self.indices.remove(key)?;
+        for i in self.indices.values_mut() {
+            if *i > removed {
+                *i -= 1;
+            }
+        }
+
+        Some(self.entries.remove(removed).1)
+    }
+
+    /// The entries in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (&HashKey, &(Value, Value))> {
+        self.entries.iter().map(|(h, entry)| (h, entry))
+    }
+
+    /// The key and value pairs in insertion order
+    pub fn values(&self) -> impl Iterator<Item = &(Value, Value)> {
+        self.entries.iter().map(|(_, entry)| entry)
+    }
+}
+
+impl std::iter::FromIterator<(HashKey, (Value, Value))> for ValueMap {
+    fn from_iter<I: IntoIterator<Item = (HashKey, (Value, Value))>>(iter: I) -> Self {
+        let mut map = ValueMap::new();
+        for (key, entry) in iter {
+            map.insert(key, entry);
+        }
+        map
+    }
+}
+
+impl Value {
+    /// Constructs variant `vi` of `th`, splitting a tuple `payload` into fields
+    /// if the variant was declared with one
+    pub fn variant(th: TypeHandle, vi: usize, payload: Value) -> Value {
+        let fields = match (th.variant_arity(vi), payload) {
+            (0, _) => vec![],
+            (1, payload) => vec![payload],
+            (_, Value::Tuple(fields)) => fields,
+            (_, payload) => vec![payload],
+        };
+
+        Value::Variant(th, vi, Rc::new(fields))
+    }
+
+    /// The fields of a variant as the single value the variant was declared with
+    pub fn variant_payload(fields: &[Value]) -> Value {
+        match fields {
+            [] => Value::Unit,
+            [field] => field.clone(),
+            fields => Value::Tuple(fields.to_vec()),
+        }
+    }
+
+    pub fn hash_key(&self) -> Option<HashKey> {
+        match self {
+            Value::Unit => Some(HashKey::Unit),
+            Value::Integer(i) => Some(HashKey::Integer(*i)),
+            Value::String(s) => Some(HashKey::String(s.clone())),
+            Value::Char(c) => Some(HashKey::Char(*c)),
+            Value::Tuple(values) => values
+                .iter()
+                .map(Value::hash_key)
+                .collect::<Option<_>>()
+                .map(HashKey::Tuple),
+            Value::List(values) => values
+                .iter()
+                .map(Value::hash_key)
+                .collect::<Option<_>>()
+                .map(HashKey::List),
+            Value::Record(fields) => fields
+                .iter()
+                .map(|(n, v)| v.hash_key().map(|k| (n.clone(), k)))
+                .collect::<Option<_>>()
+                .map(HashKey::Record),
+            Value::Variant(th, vi, fields) => fields
+                .iter()
+                .map(Value::hash_key)
+                .collect::<Option<_>>()
+                .map(|fields| HashKey::Variant(th.index, *vi, fields)),
+            _ => None,
+        }
+    }
+
+    pub fn variant_name(&self) -> Option<String> {
+        if let Value::Variant(th, vi, _) = self {
+            if let TypeDefinition::Sum { variants, .. } = &th.environment.borrow().types[th.index] {
+                return Some(variants[*vi].0.clone());
+            }
+        }
+
+        None
+    }
+
+    /// Copies the value along with the contents of every `Ref` and `Map` in it,
+    /// so mutating the copy doesn't affect the original. Functions get copies
+    /// of what they captured.
+    pub fn deep_copy(&self) -> Value {
+        self.deep_copy_with(&mut HashMap::new())
+    }
+
+    /// `deep_copy`, reusing the copies in `copies` of cells that were already
+    /// copied, keyed by their address. A cell reachable several times is only
+    /// copied once, so whatever shared it before shares the copy.
+    fn deep_copy_with(&self, copies: &mut HashMap<usize, Value>) -> Value {
+        let mut copy_all = |values: &[Value]| {
+            values
+                .iter()
+                .map(|v| v.deep_copy_with(copies))
+                .collect::<Vec<_>>()
+        };
+
+        match self {
+            Value::Tuple(values) => Value::Tuple(copy_all(values)),
+            Value::List(values) => Value::List(copy_all(values)),
+            Value::Record(fields) => Value::Record(
+                fields
+                    .iter()
+                    .map(|(n, v)| (n.clone(), v.deep_copy_with(copies)))
+                    .collect(),
+            ),
+            Value::Variant(th, vi, fields) => {
+                Value::Variant(th.clone(), *vi, Rc::new(copy_all(fields)))
+            }
+            Value::Function(p, captured, body) => Value::Function(
+                p.clone(),
+                captured
+                    .iter()
+                    .map(|(n, v)| (n.clone(), v.deep_copy_with(copies)))
+                    .collect(),
+                body.clone(),
+            ),
+            Value::Ref(cell) => {
+                let address = Rc::as_ptr(cell) as usize;
+                if let Some(copy) = copies.get(&address) {
+                    return copy.clone();
+                }
+
+                // registered before the contents are copied, in case they lead back here
+                let copy = Rc::new(RefCell::new(Value::Unit));
+                copies.insert(address, Value::Ref(copy.clone()));
+                *copy.borrow_mut() = cell.borrow().deep_copy_with(copies);
+                Value::Ref(copy)
+            }
+            Value::Map(map) => {
+                let address = Rc::as_ptr(map) as usize;
+                if let Some(copy) = copies.get(&address) {
+                    return copy.clone();
+                }
+
+                let copy = Rc::new(RefCell::new(ValueMap::new()));
+                copies.insert(address, Value::Map(copy.clone()));
+                let entries = map
+                    .borrow()
+                    .iter()
+                    .map(|(h, (k, v))| {
+                        (h.clone(), (k.deep_copy_with(copies), v.deep_copy_with(copies)))
+                    })
+                    .collect();
+                *copy.borrow_mut() = entries;
+                Value::Map(copy)
+            }
+            v => v.clone(),
+        }
+    }
+
+    /// What kind of value this is, variants along with the name of their type
+    pub fn kind(&self) -> String {
+        match self {
+            Value::Unit => "unit".to_owned(),
+            Value::Integer(_) => "integer".to_owned(),
+            Value::Float(_) => "float".to_owned(),
+            Value::String(_) => "string".to_owned(),
+            Value::Char(_) => "char".to_owned(),
+            Value::Tuple(_) => "tuple".to_owned(),
+            Value::List(_) => "list".to_owned(),
+            Value::Record(_) => "record".to_owned(),
+            Value::Variant(th, ..) => format!(
+                "variant {}",
+                th.environment.borrow().types[th.index].qualified_name()
+            ),
+            Value::Ref(_) => "ref".to_owned(),
+            Value::Map(_) => "map".to_owned(),
+            Value::Function(..)
+            | Value::VariantConstructorFn(..)
+            | Value::BuiltInFn(_)
+            | Value::Memoized(_) => "function".to_owned(),
+        }
+    }
+
+    /// Whether the value could have type `ty`, looking only as deep as is cheap:
+    /// the elements of lists and the contents of refs and maps aren't checked
+    fn has_type(&self, ty: &Type) -> bool {
+        let (tc, params) = match ty {
+            Type::ConstructedType(tc, params) => (tc, params),
+            Type::TypeVariable(_) | Type::ErrType => return true,
+        };
+
+        match (tc, self) {
+            (TypeConstructor::Unit, Value::Unit)
+            | (TypeConstructor::Int, Value::Integer(_))
+            | (TypeConstructor::Bool, Value::Integer(_))
+            | (TypeConstructor::Float, Value::Float(_))
+            | (TypeConstructor::String, Value::String(_))
+            | (TypeConstructor::Char, Value::Char(_))
+            | (TypeConstructor::List, Value::List(_))
+            | (TypeConstructor::Ref, Value::Ref(_))
+            | (TypeConstructor::Map, Value::Map(_)) => true,
+            // a record only has a record type with exactly its field names, it is
+            // never interchangeable with a tuple of the same shape
+            (TypeConstructor::UserType(th), Value::Record(values)) => {
+                match &th.environment.borrow().types[th.index] {
+                    TypeDefinition::Record { fields, .. } => {
+                        fields.len() == values.len()
+                            && fields.iter().all(|(name, _)| values.iter().any(|(n, _)| n == name))
+                    }
+                    _ => false,
+                }
+            }
+            (TypeConstructor::Tuple(_), Value::Tuple(values)) => {
+                values.len() == params.len()
+                    && values.iter().zip(params).all(|(v, t)| v.has_type(t))
+            }
+            (TypeConstructor::UserType(th), Value::Variant(vth, ..)) => th == vth,
+            (TypeConstructor::Function, v) => v.is_callable(),
+            _ => false,
+        }
+    }
+
+    fn is_callable(&self) -> bool {
+        matches!(
+            self,
+            Value::Function(..)
+                | Value::VariantConstructorFn(..)
+                | Value::BuiltInFn(_)
+                | Value::Memoized(_)
+        )
+    }
+
+    fn is_atomic(&self) -> bool {
+        match self {
+            Value::Variant(_, _, fields) => fields.is_empty(),
+            Value::Ref(_) => false,
+            _ => true,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Unit => f.write_str("()"),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::String(s) => f.write_str(s),
+            Value::Char(c) => write!(f, "{}", c),
+            Value::Tuple(values) => {
+                f.write_str("(")?;
+                for (i, v) in values.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                f.write_str(")")
+            }
+            Value::List(values) => {
+                f.write_str("[")?;
+                for (i, v) in values.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                f.write_str("]")
+            }
+            Value::Record(fields) => {
+                f.write_str("{ ")?;
+                for (i, (n, v)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}: {}", n, v)?;
+                }
+                f.write_str(" }")
+            }
+            Value::Variant(_, _, fields) => {
+                f.write_str(&self.variant_name().unwrap())?;
+
+                match &fields[..] {
+                    [] => Ok(()),
+                    [p] if p.is_atomic() => write!(f, " {}", p),
+                    [p] => write!(f, " ({})", p),
+                    fields => write!(f, " {}", Value::variant_payload(fields)),
+                }
+            }
+            Value::Map(map) => {
+                let entries = map
+                    .borrow()
+                    .values()
+                    .map(|(k, v)| format!("{}: {}", k, v))
+                    .collect::<Vec<_>>();
+
+                write!(f, "map {{ {} }}", entries.join(", "))
+            }
+            Value::Ref(cell) => match &*cell.borrow() {
+                v if v.is_atomic() => write!(f, "ref {}", v),
+                v => write!(f, "ref ({})", v),
+            },
+            Value::Function(arg, ..) => write!(f, "<function/{}>", arg),
+            Value::BuiltInFn(builtin) => write!(f, "<builtin:{:?}>", builtin),
+            Value::VariantConstructorFn(..) | Value::Memoized(_) => f.write_str("<function>"),
+        }
+    }
+}
+
+// written by hand so functions don't dump their captured bindings and body pointer
+impl Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Unit => f.write_str("Unit"),
+            Value::Tuple(values) => f.debug_tuple("Tuple").field(values).finish(),
+            Value::List(values) => f.debug_tuple("List").field(values).finish(),
+            Value::Record(fields) => f.debug_tuple("Record").field(fields).finish(),
+            Value::Function(..) | Value::BuiltInFn(_) => write!(f, "{}", self),
+            Value::String(s) => f.debug_tuple("String").field(s).finish(),
+            Value::Char(c) => f.debug_tuple("Char").field(c).finish(),
+            Value::Integer(i) => f.debug_tuple("Integer").field(i).finish(),
+            Value::Float(x) => f.debug_tuple("Float").field(x).finish(),
+            Value::Variant(th, vi, fields) => f
+                .debug_tuple("Variant")
+                .field(th)
+                .field(vi)
+                .field(fields)
+                .finish(),
+            Value::VariantConstructorFn(th, vi) => f
+                .debug_tuple("VariantConstructorFn")
+                .field(th)
+                .field(vi)
+                .finish(),
+            Value::Memoized(memo) => f.debug_tuple("Memoized").field(memo).finish(),
+            Value::Ref(cell) => f.debug_tuple("Ref").field(cell).finish(),
+            Value::Map(map) => f.debug_tuple("Map").field(map).finish(),
+        }
+    }
+}
+
+pub const DEFAULT_SEED: u64 = 0x5eed;
+
+/// the longest string String_repeat and the like build, in bytes, so a huge
+/// count fails with an error instead of aborting on allocation
+const MAX_REPEATED_LEN: usize = 1 << 30;
+
+/// the most pairs List_product builds, for the same reason
+const MAX_PRODUCT_LEN: usize = 1 << 22;
+
+/// reading the clock costs more than evaluating most expressions
+const DEADLINE_CHECK_INTERVAL: u32 = 1024;
+
+/// What a program is allowed to do to the world outside of the interpreter
+#[derive(Debug, Clone, Copy)]
+pub struct Capabilities {
+    pub allow_file_read: bool,
+    pub allow_file_write: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Self {
+            allow_file_read: true,
+            allow_file_write: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Self {
+            allow_file_read: false,
+            allow_file_write: false,
+        }
+    }
+}
+
+/// Called before every expression is evaluated, with the stack as it is at that point
+pub type TraceFn = Box<dyn FnMut(&TypedExpr, &[Value])>;
+
+/// Called when evaluation reaches a breakpoint, with the span that was hit,
+/// the bindings visible at that point (innermost last) and the stack
+pub type BreakpointHook = Box<dyn FnMut(Span, &[(String, Value)], &[Value])>;
+
+/// Receives everything the program prints
+pub type OutputFn = Box<dyn FnMut(&str)>;
+
+/// Tells the time as the program sees it, measured from any fixed point
+pub type ClockFn = Box<dyn FnMut() -> Duration>;
+
+/// Everything about how a program is run that can differ from the defaults
+pub struct InterpreterConfig {
+    pub seed: u64,
+    pub capabilities: Capabilities,
+    /// how many expressions may be evaluated, unlimited if None
+    pub fuel: Option<u64>,
+    /// how long the program may run for, counted from when the interpreter is created
+    pub timeout: Option<Duration>,
+    pub trace: Option<TraceFn>,
+    pub breakpoints: Option<(Breakpoints, BreakpointHook)>,
+    /// where printed output goes instead of stdout
+    pub output: Option<OutputFn>,
+    /// where output printed with `eprint` goes instead of stderr
+    pub error_output: Option<OutputFn>,
+    /// where input is read from instead of stdin
+    pub input: Option<Box<dyn Read>>,
+    /// whether to count what gets evaluated, see `Interpreter::profile`
+    pub profile: bool,
+    /// what `time_it` measures with instead of the system clock. Timeouts
+    /// always use the system clock.
+    pub clock: Option<ClockFn>,
+}
+
+impl Default for InterpreterConfig {
+    fn default() -> Self {
+        Self {
+            seed: DEFAULT_SEED,
+            capabilities: Capabilities::all(),
+            fuel: None,
+            timeout: None,
+            trace: None,
+            breakpoints: None,
+            output: None,
+            error_output: None,
+            input: None,
+            profile: false,
+            clock: None,
+        }
+    }
+}
+
+/// How often each kind of expression was evaluated and how many times a
+/// function was applied to an argument, a call with several arguments
+/// counting once per argument
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub evaluations: BTreeMap<&'static str, u64>,
+    pub applications: u64,
+}
+
+impl Profile {
+    pub fn total_evaluations(&self) -> u64 {
+        self.evaluations.values().sum()
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (kind, count) in &self.evaluations {
+            writeln!(f, "{:>10} {}", count, kind)?;
+        }
+        write!(f, "{:>10} function applications", self.applications)
+    }
+}
+
+/// A shared set of spans to break on. Clones refer to the same set, so
+/// breakpoints can be added and removed while the program is running.
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints(Rc<RefCell<HashSet<Span>>>);
+
+impl Breakpoints {
+    pub fn add(&self, span: Span) {
+        self.0.borrow_mut().insert(span);
+    }
+
+    pub fn remove(&self, span: Span) -> bool {
+        self.0.borrow_mut().remove(&span)
+    }
+
+    pub fn contains(&self, span: Span) -> bool {
+        self.0.borrow().contains(&span)
+    }
+}
+
+/// The state of an interpreter at some point, which it can be put back into.
+/// Everything mutable is copied, so later changes to refs and maps don't show up
+/// in the snapshot.
+#[derive(Debug, Clone)]
+pub struct InterpreterSnapshot {
+    /// the program the snapshot belongs to, which function values point into
+    environment: Rc<RefCell<TypeEnvironment>>,
+    stack: Vec<Value>,
+    bindings: Vec<(String, Value)>,
+    frames: Vec<usize>,
+    call_stack: Vec<CallFrame>,
+    rng_state: u64,
+    fuel: Option<u64>,
+}
+
+pub struct Interpreter {
     stack: Vec<Value>,
-    bindings: HashMap<String, Value>,
+    /// the local bindings of every active call, innermost last. Later
+    /// entries shadow earlier ones with the same name.
+    bindings: Vec<(String, Value)>,
+    /// where the bindings of each active call start in `bindings`
+    frames: Vec<usize>,
     program: TypeChecked,
+    rng_state: u64,
+    capabilities: Capabilities,
+    trace: Option<TraceFn>,
+    breakpoints: Option<(Breakpoints, BreakpointHook)>,
+    /// frames are only popped on a successful return, so after an error
+    /// this still holds the calls that led to it
+    call_stack: Vec<CallFrame>,
+    /// how many more expressions may be evaluated, unlimited if None
+    fuel: Option<u64>,
+    /// when evaluation has to stop, and how many expressions are left until
+    /// the clock is looked at again
+    deadline: Option<(Instant, u32)>,
+    output: Option<OutputFn>,
+    error_output: Option<OutputFn>,
+    input: Option<Box<dyn Read>>,
+    profile: Option<Profile>,
+    clock: ClockFn,
 }
 
 impl Interpreter {
+    pub fn new(program: TypeChecked) -> Self {
+        Self::with_config(program, InterpreterConfig::default())
+    }
+
+    pub fn with_config(program: TypeChecked, config: InterpreterConfig) -> Self {
+        Self {
+            bindings: Vec::new(),
+            frames: Vec::new(),
+            stack: Vec::new(),
+            program,
+            rng_state: config.seed,
+            capabilities: config.capabilities,
+            trace: config.trace,
+            breakpoints: config.breakpoints,
+            call_stack: Vec::new(),
+            fuel: config.fuel,
+            deadline: config.timeout.map(|t| (Instant::now() + t, 0)),
+            output: config.output,
+            error_output: config.error_output,
+            input: config.input,
+            profile: if config.profile {
+                Some(Profile::default())
+            } else {
+                None
+            },
+            clock: config.clock.unwrap_or_else(|| {
+                let start = Instant::now();
+                Box::new(move || start.elapsed())
+            }),
+        }
+    }
+
+    /// What has been evaluated so far, if the interpreter was configured to profile
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn snapshot(&self) -> InterpreterSnapshot {
+        let (stack, bindings) = copy_state(&self.stack, &self.bindings);
+
+        InterpreterSnapshot {
+            environment: self.program.environment.clone(),
+            stack,
+            bindings,
+            frames: self.frames.clone(),
+            call_stack: self.call_stack.clone(),
+            rng_state: self.rng_state,
+            fuel: self.fuel,
+        }
+    }
+
+    /// Puts the interpreter back into the state `snapshot` was taken in. The
+    /// snapshot is copied again, so it can be restored any number of times.
+    pub fn restore(&mut self, snapshot: &InterpreterSnapshot) -> RuntimeResult<()> {
+        if !Rc::ptr_eq(&snapshot.environment, &self.program.environment) {
+            return Err(RuntimeError::InternalError(
+                "restoring a snapshot of a different program".to_owned(),
+            ));
+        }
+
+        let (stack, bindings) = copy_state(&snapshot.stack, &snapshot.bindings);
+        self.stack = stack;
+        self.bindings = bindings;
+        self.frames = snapshot.frames.clone();
+        self.call_stack = snapshot.call_stack.clone();
+        self.rng_state = snapshot.rng_state;
+        self.fuel = snapshot.fuel;
+        Ok(())
+    }
+
+    fn write_output(&mut self, s: &str) {
+        match &mut self.output {
+            Some(output) => output(s),
+            None => print!("{}", s),
+        }
+    }
+
+    fn write_error_output(&mut self, s: &str) {
+        match &mut self.error_output {
+            Some(output) => output(s),
+            None => eprint!("{}", s),
+        }
+    }
+
+    /// splitmix64, good enough for ids and not meant for anything cryptographic
+    pub fn next_random(&mut self) -> u64 {
+        self.rng_state = self.rng_state.wrapping_add(0x9e3779b97f4a7c15);
+
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
     pub fn push_val(&mut self, value: Value) {
         self.stack.push(value);
     }
@@ -30,75 +929,225 @@ impl Interpreter {
         self.stack.pop()
     }
 
-    pub fn call_fn(&mut self, f: &str) {
+    pub fn call_fn(&mut self, f: &str) -> RuntimeResult<()> {
         let (e, _t) = {
             let env = self.program.environment.borrow();
 
             env.root_scope
                 .bindings
                 .get(f)
-                .expect(&format!("function not found: {}", f))
+                .ok_or_else(|| RuntimeError::UnboundSymbol(f.to_owned(), None))?
                 .clone()
         };
 
-        if let ExprT::Lambda(_p, body) = e.clone() {
-            self.eval_expr(&body)
+        // the clone shares the body with the binding, closures made by it keep
+        // the body alive after the call returns
+        if let ExprT::Lambda(_p, body, _) = e {
+            self.call_stack.push(CallFrame {
+                function: f.to_owned(),
+                call_site: None,
+            });
+            let depth = self.stack.len();
+            match self.eval_expr(&body) {
+                Ok(()) => (),
+                Err(RuntimeError::Return(v)) => {
+                    self.stack.truncate(depth);
+                    self.push_val(v);
+                }
+                Err(e) => return Err(e),
+            }
+            self.call_stack.pop();
+            Ok(())
         } else {
-            panic!("Tried to call non function value {:?}", e);
+            Err(RuntimeError::NotAFunction(f.to_owned()))
+        }
+    }
+
+    /// Applies the top level function `name` to each of `args` in turn, the
+    /// same way an application in a program would
+    pub fn call_with_args(&mut self, name: &str, args: Vec<Value>) -> RuntimeResult<()> {
+        let mut f = {
+            let env = self.program.environment.borrow();
+
+            match env.root_scope.bindings.get(name) {
+                Some((ExprT::Lambda(p, body, _), _)) => Value::Function(
+                    Rc::new(p.clone()),
+                    vec![],
+                    body.clone(),
+                ),
+                Some((ExprT::BuiltInFn(f), _)) => Value::BuiltInFn(*f),
+                Some(_) => return Err(RuntimeError::NotAFunction(name.to_owned())),
+                None => {
+                    let candidates = env.root_scope.bindings.keys().map(String::as_str);
+                    let similar = closest_name(name, candidates);
+                    return Err(RuntimeError::UnboundSymbol(name.to_owned(), similar));
+                }
+            }
+        };
+
+        self.call_stack.push(CallFrame {
+            function: name.to_owned(),
+            call_site: None,
+        });
+        for (applied, arg) in args.into_iter().enumerate() {
+            if !f.is_callable() {
+                return Err(RuntimeError::TooManyArguments(name.to_owned(), applied));
+            }
+            f = self.apply_function(f, arg)?;
         }
+        self.call_stack.pop();
+
+        self.push_val(f);
+        Ok(())
+    }
+
+    pub fn eval_binding(&mut self, name: &str) -> RuntimeResult<()> {
+        let binding = {
+            let env = self.program.environment.borrow();
+
+            env.root_scope
+                .bindings
+                .get(name)
+                .ok_or_else(|| RuntimeError::UnboundSymbol(name.to_owned(), None))?
+                .clone()
+        };
+
+        self.eval_expr(&binding)
     }
 
-    pub fn call_builtin(&mut self, builtin: BuiltInFn, arg: Value) {
+    pub fn call_builtin(&mut self, builtin: BuiltInFn, arg: Value) -> RuntimeResult<()> {
+        self.check_argument(builtin, &arg)?;
+
         match builtin {
             BuiltInFn::FileRead => {
+                self.require(builtin, "allow_file_read", |c| c.allow_file_read)?;
+
                 if let Value::String(s) = arg {
-                    let buf = std::fs::read_to_string(s.as_str()).unwrap();
+                    let buf = std::fs::read_to_string(s.as_str())
+                        .map_err(|e| RuntimeError::IoError(s.to_string(), e.to_string()))?;
                     self.push_val(Value::String(Rc::new(buf)));
                 } else {
-                    panic!()
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            // like File_read, but a file that can't be read is an Err the program can handle
+            BuiltInFn::FileTryRead => {
+                self.require(builtin, "allow_file_read", |c| c.allow_file_read)?;
+
+                if let Value::String(s) = arg {
+                    let (variant, payload) = match std::fs::read_to_string(s.as_str()) {
+                        Ok(buf) => ("Ok", buf),
+                        Err(e) => {
+                            let error = RuntimeError::IoError(s.to_string(), e.to_string());
+                            ("Err", error.to_string())
+                        }
+                    };
+                    let result =
+                        self.prelude_variant("Result", variant, Value::String(Rc::new(payload)));
+                    self.push_val(result);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
             BuiltInFn::Print => {
                 if let Value::String(s) = arg {
-                    print!("{}", s);
+                    self.write_output(&s);
                     self.push_val(Value::Unit);
                 } else {
-                    panic!();
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
             BuiltInFn::Printi => {
                 if let Value::Integer(i) = arg {
-                    print!("{}", i);
+                    self.write_output(&i.to_string());
                     self.push_val(Value::Unit);
                 } else {
-                    panic!()
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
+            BuiltInFn::Show => {
+                self.write_output(&arg.to_string());
+                self.push_val(Value::Unit);
+            }
             BuiltInFn::StringParseInt => {
                 if let Value::String(s) = arg {
-                    self.push_val(Value::Integer(s.parse::<i64>().unwrap()));
+                    let i = s.parse::<i64>().map_err(|_| {
+                        RuntimeError::InvalidArgument(builtin, format!("{:?} is not an integer", s))
+                    })?;
+                    self.push_val(Value::Integer(i));
                 } else {
-                    panic!()
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
-            BuiltInFn::StringGetFirst => {
+            BuiltInFn::StringParseFloat => {
                 if let Value::String(s) = arg {
-                    self.push_val(Value::Tuple(vec![
-                        Value::String(Rc::new(s[0..1].to_string())),
-                        Value::String(Rc::new(s[1..].to_string())),
-                    ]));
+                    let f = s.parse::<f64>().map_err(|_| {
+                        RuntimeError::InvalidArgument(builtin, format!("{:?} is not a number", s))
+                    })?;
+                    self.push_val(Value::Float(f));
                 } else {
-                    panic!();
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
-            BuiltInFn::StringSplit => {
-                if let Value::Tuple(args) = arg {
-                    assert!(args.len() == 2);
-                    match (&args[0], &args[1]) {
-                        (Value::String(input), Value::String(seperator)) => {
-                            if let Some(sep_i) = input.find(seperator.as_str()) {
-                                let (up, to) = input.split_at(sep_i);
-                                self.push_val(Value::Tuple(vec![
+            BuiltInFn::StringTryParseInt => {
+                if let Value::String(s) = arg {
+                    let v = match s.parse::<i64>() {
+                        Ok(i) => self.prelude_variant("Option", "Some", Value::Integer(i)),
+                        Err(_) => self.prelude_variant("Option", "None", Value::Unit),
+                    };
+                    self.push_val(v);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::StringTryGetFirst => {
+                if let Value::String(s) = arg {
+                    let mut chars = s.chars();
+                    let v = match chars.next() {
+                        Some(first) => self.prelude_variant(
+                            "Option",
+                            "Some",
+                            Value::Tuple(vec![
+                                Value::Char(first),
+                                Value::String(Rc::new(chars.as_str().to_string())),
+                            ]),
+                        ),
+                        None => self.prelude_variant("Option", "None", Value::Unit),
+                    };
+                    self.push_val(v);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::StringGetFirst => {
+                if let Value::String(s) = arg {
+                    let mut chars = s.chars();
+                    let first = chars.next().ok_or_else(|| {
+                        RuntimeError::InvalidArgument(builtin, "empty string".to_owned())
+                    })?;
+                    self.push_val(Value::Tuple(vec![
+                        Value::Char(first),
+                        Value::String(Rc::new(chars.as_str().to_string())),
+                    ]));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::CharToString => {
+                if let Value::Char(c) = arg {
+                    self.push_val(Value::String(Rc::new(c.to_string())));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::StringSplit => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::String(input), Value::String(seperator)) => {
+                            if let Some(sep_i) = input.find(seperator.as_str()) {
+                                let (up, to) = input.split_at(sep_i);
+                                self.push_val(Value::Tuple(vec![
                                     Value::String(Rc::new(up.to_string())),
                                     Value::String(Rc::new(to[seperator.len()..].to_owned())),
                                 ]));
@@ -109,227 +1158,4846 @@ impl Interpreter {
                                 ]));
                             }
                         }
-                        _ => panic!(),
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
                     }
                 } else {
-                    panic!()
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
-            _ => {
-                dbg!(builtin);
-
-                unimplemented!()
+            BuiltInFn::IntPopcount => {
+                if let Value::Integer(i) = arg {
+                    self.push_val(Value::Integer(i.count_ones() as i64));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
             }
-        }
-    }
+            BuiltInFn::IntBits => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 3)?;
+                    match (&args[0], &args[1], &args[2]) {
+                        (Value::Integer(value), Value::Integer(offset), Value::Integer(width)) => {
+                            let end = offset.checked_add(*width);
+                            if *offset < 0 || *width < 0 || end.map_or(true, |end| end > 64) {
+                                return Err(RuntimeError::InvalidArgument(
+                                    builtin,
+                                    format!(
+                                        "{} bits at offset {} are out of range for a 64 bit \
+                                         integer",
+                                        width, offset
+                                    ),
+                                ));
+                            }
 
-    pub fn eval_expr(&mut self, (expr, _et): &TypedExpr) {
-        match expr {
-            ExprT::Tuple(exprs) => {
-                let mut vals = Vec::new();
-                for e in exprs {
-                    self.eval_expr(e);
-                    vals.push(self.pop_val().unwrap());
+                            let mask = if *width == 64 {
+                                u64::MAX
+                            } else {
+                                (1u64 << width) - 1
+                            };
+                            let shifted = (*value as u64).checked_shr(*offset as u32).unwrap_or(0);
+                            self.push_val(Value::Integer((shifted & mask) as i64));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
-                self.push_val(Value::Tuple(vals));
             }
-            ExprT::LetBinding(binding, rhs, body) => {
-                self.eval_expr(rhs);
-                let rv = self.pop_val().unwrap();
-                self.bindings.insert(binding.clone(), rv);
+            BuiltInFn::Uuid => {
+                if let Value::Unit = arg {
+                    // lay the bits out like a version 4 uuid
+                    let hi = (self.next_random() & !0xf000) | 0x4000;
+                    let lo = (self.next_random() & !(0xc << 60)) | (0x8 << 60);
 
-                self.eval_expr(body);
-                self.bindings.remove(binding);
+                    self.push_val(Value::String(Rc::new(format!(
+                        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+                        hi >> 32,
+                        (hi >> 16) & 0xffff,
+                        hi & 0xffff,
+                        lo >> 48,
+                        lo & 0xffff_ffff_ffff
+                    ))));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
             }
-            ExprT::MatchSum(matchee, arms) => {
-                self.eval_expr(matchee);
+            BuiltInFn::Memoize => self.push_val(Value::Memoized(Rc::new(Memo {
+                function: arg,
+                key_fn: None,
+                cache: RefCell::new(HashMap::new()),
+            }))),
+            BuiltInFn::MemoizeBy => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    let mut args = args.into_iter();
 
-                if let Some(Value::Variant(th, vi, val)) = self.pop_val() {
-                    for (arm_i, binding, body) in arms {
-                        if *arm_i == vi {
-                            binding.iter().for_each(|binding| {
-                                self.bindings.insert(binding.clone(), (*val).clone());
-                            });
+                    self.push_val(Value::Memoized(Rc::new(Memo {
+                        function: args.next().unwrap(),
+                        key_fn: Some(args.next().unwrap()),
+                        cache: RefCell::new(HashMap::new()),
+                    })));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::Retry => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::Integer(attempts), thunk) => {
+                            if *attempts < 1 {
+                                return Err(RuntimeError::InvalidArgument(
+                                    builtin,
+                                    format!("needs at least one attempt, got {}", attempts),
+                                ));
+                            }
 
-                            self.eval_expr(body);
+                            let result_th =
+                                TypeHandle::prelude(&self.program.environment, "Result");
+                            let ok = result_th.variant_index("Ok");
 
-                            binding.iter().for_each(|binding| {
-                                self.bindings.remove(binding);
-                            });
+                            let mut result = Value::Unit;
+                            for _ in 0..*attempts {
+                                result = self.apply_function(thunk.clone(), Value::Unit)?;
+                                match &result {
+                                    Value::Variant(th, vi, _) if *th == result_th && *vi == ok => {
+                                        break
+                                    }
+                                    _ => (),
+                                }
+                            }
 
-                            return;
+                            self.push_val(result);
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListAppend => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    let mut args = args.into_iter();
+                    match (args.next().unwrap(), args.next().unwrap()) {
+                        (Value::List(mut list), value) => {
+                            list.push(value);
+                            self.push_val(Value::List(list));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListConcat => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    let mut args = args.into_iter();
+                    match (args.next().unwrap(), args.next().unwrap()) {
+                        (Value::List(mut a), Value::List(b)) => {
+                            a.extend(b);
+                            self.push_val(Value::List(a));
                         }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
                     }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListProduct2 => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::List(a), Value::List(b)) => {
+                            let len = a
+                                .len()
+                                .checked_mul(b.len())
+                                .filter(|len| *len <= MAX_PRODUCT_LEN)
+                                .ok_or_else(|| {
+                                    RuntimeError::InvalidArgument(
+                                        builtin,
+                                        format!(
+                                            "the product of {} and {} elements is too long",
+                                            a.len(),
+                                            b.len()
+                                        ),
+                                    )
+                                })?;
 
-                    panic!("{:?}, {:?}", arms, vi)
+                            let mut pairs = Vec::with_capacity(len);
+                            for x in a {
+                                for y in b {
+                                    pairs.push(Value::Tuple(vec![x.clone(), y.clone()]));
+                                }
+                            }
+                            self.push_val(Value::List(pairs));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListMap => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    let mut args = args.into_iter();
+                    match (args.next().unwrap(), args.next().unwrap()) {
+                        (f, Value::List(list)) => {
+                            let mapped = list
+                                .into_iter()
+                                .map(|v| self.apply_function(f.clone(), v))
+                                .collect::<RuntimeResult<_>>()?;
+                            self.push_val(Value::List(mapped));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListFilter => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    let mut args = args.into_iter();
+                    match (args.next().unwrap(), args.next().unwrap()) {
+                        (f, Value::List(list)) => {
+                            let mut kept = Vec::new();
+                            for v in list {
+                                if let Value::Integer(0) =
+                                    self.apply_function(f.clone(), v.clone())?
+                                {
+                                    continue;
+                                }
+                                kept.push(v);
+                            }
+                            self.push_val(Value::List(kept));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListFold => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 3)?;
+                    let mut args = args.into_iter();
+                    match (
+                        args.next().unwrap(),
+                        args.next().unwrap(),
+                        args.next().unwrap(),
+                    ) {
+                        (f, init, Value::List(list)) => {
+                            let mut acc = init;
+                            for v in list {
+                                let partial = self.apply_function(f.clone(), acc)?;
+                                acc = self.apply_function(partial, v)?;
+                            }
+                            self.push_val(acc);
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListIsSorted => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::List(list), less) => {
+                            let mut sorted = true;
+                            for pair in list.windows(2) {
+                                // out of order if the later element is strictly less
+                                if self.less_than(less, &pair[1], &pair[0])? {
+                                    sorted = false;
+                                    break;
+                                }
+                            }
+                            self.push_val(Value::Integer(sorted as i64));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
                 } else {
-                    panic!()
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
-            ExprT::Application(lhs, rhs) => {
-                self.eval_expr(lhs);
+            BuiltInFn::ListBinarySearch => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 3)?;
+                    match (&args[0], &args[1], &args[2]) {
+                        (Value::List(list), target, less) => {
+                            let (mut lo, mut hi) = (0, list.len());
+                            let mut found = None;
 
-                for expr in rhs {
-                    let top = self.pop_val();
-                    if let Some(Value::Function(p, curried, body)) = top {
-                        // scoping
-                        self.eval_expr(expr);
-                        let rv = self.pop_val().unwrap();
-                        let bindings_tmp = self.bindings.clone();
-                        self.bindings.clear();
+                            while lo < hi {
+                                let mid = lo + (hi - lo) / 2;
+                                if self.less_than(less, &list[mid], target)? {
+                                    lo = mid + 1;
+                                } else if self.less_than(less, target, &list[mid])? {
+                                    hi = mid;
+                                } else {
+                                    found = Some(mid);
+                                    break;
+                                }
+                            }
+
+                            let result = match found {
+                                Some(i) => {
+                                    self.prelude_variant("Option", "Some", Value::Integer(i as i64))
+                                }
+                                None => self.prelude_variant("Option", "None", Value::Unit),
+                            };
+                            self.push_val(result);
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListTranspose => {
+                if let Value::List(rows) = arg {
+                    let width = match rows.first() {
+                        Some(Value::List(row)) => row.len(),
+                        Some(_) => return Err(RuntimeError::unexpected_argument(builtin)),
+                        None => 0,
+                    };
 
-                        for (i, e) in curried.clone() {
-                            self.bindings.insert(i, e);
+                    let mut columns = vec![Vec::with_capacity(rows.len()); width];
+                    for row in rows {
+                        match row {
+                            Value::List(row) if row.len() == width => {
+                                for (column, v) in columns.iter_mut().zip(row) {
+                                    column.push(v);
+                                }
+                            }
+                            Value::List(row) => {
+                                return Err(RuntimeError::InvalidArgument(
+                                    builtin,
+                                    format!(
+                                        "cannot transpose ragged list: expected rows of length {}, found {}",
+                                        width,
+                                        row.len()
+                                    ),
+                                ))
+                            }
+                            _ => return Err(RuntimeError::unexpected_argument(builtin)),
                         }
-                        self.bindings.insert((*p).clone(), rv);
+                    }
 
-                        self.eval_expr(unsafe { &*body });
+                    self.push_val(Value::List(columns.into_iter().map(Value::List).collect()));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListSpan => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    let mut args = args.into_iter();
+                    match (args.next().unwrap(), args.next().unwrap()) {
+                        (Value::List(mut list), pred) => {
+                            let mut split = list.len();
+                            for (i, v) in list.iter().enumerate() {
+                                if let Value::Integer(0) =
+                                    self.apply_function(pred.clone(), v.clone())?
+                                {
+                                    split = i;
+                                    break;
+                                }
+                            }
 
-                        self.bindings = bindings_tmp;
-                    } else if let Some(Value::VariantConstructorFn(th, vi)) = top {
-                        self.eval_expr(expr);
-                        let rv = self.pop_val().unwrap();
-                        self.push_val(Value::Variant(th.clone(), vi, Rc::new(rv)));
-                    } else if let Some(Value::BuiltInFn(f)) = top {
-                        self.eval_expr(expr);
-                        let argv = self.pop_val().unwrap();
-                        self.call_builtin(f, argv);
-                    } else {
-                        dbg!(lhs, top, &self.stack, &self.bindings);
-                        panic!("Not good")
+                            let rest = list.split_off(split);
+                            self.push_val(Value::Tuple(vec![Value::List(list), Value::List(rest)]));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
                     }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
-            ExprT::Lambda(p, body) => {
-                self.push_val(Value::Function(
-                    Rc::new(p.clone()),
-                    self.bindings.clone().into_iter().collect(),
-                    body.as_ref() as *const TypedExpr,
-                ));
+            BuiltInFn::FloatLerp => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 3)?;
+                    match (&args[0], &args[1], &args[2]) {
+                        (Value::Float(a), Value::Float(b), Value::Float(t)) => {
+                            self.push_val(Value::Float(a + (b - a) * t));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
             }
-            ExprT::BooleanLiteral(b) => self.push_val(Value::Integer(*b as i64)),
-            ExprT::Conditional(cond, cons, alt) => {
-                self.eval_expr(cond);
+            BuiltInFn::FloatClamp => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 3)?;
+                    match (&args[0], &args[1], &args[2]) {
+                        (Value::Float(x), Value::Float(lo), Value::Float(hi)) => {
+                            if x.is_nan() || lo.is_nan() || hi.is_nan() {
+                                return Err(RuntimeError::InvalidArgument(
+                                    builtin,
+                                    "called with NaN".to_owned(),
+                                ));
+                            }
+                            if lo > hi {
+                                return Err(RuntimeError::InvalidArgument(
+                                    builtin,
+                                    format!("empty range {:?}..{:?}", lo, hi),
+                                ));
+                            }
 
-                if let Value::Integer(0) = self.pop_val().unwrap() {
-                    self.eval_expr(alt);
+                            self.push_val(Value::Float(x.max(*lo).min(*hi)));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
                 } else {
-                    self.eval_expr(cons);
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
-            ExprT::Symbol(s) => {
-                let val = {
-                    let env = self.program.environment.borrow();
+            BuiltInFn::FloatRoundSig => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::Float(x), Value::Integer(sig)) => {
+                            if *sig <= 0 {
+                                return Err(RuntimeError::InvalidArgument(
+                                    builtin,
+                                    format!("cannot round to {} significant figures", sig),
+                                ));
+                            }
 
-                    let b = env.root_scope.bindings.get(s);
+                            if *x == 0.0 || !x.is_finite() {
+                                self.push_val(Value::Float(*x));
+                                return Ok(());
+                            }
 
-                    if let Some(b) = b {
-                        if let (ExprT::Lambda(p, body), _) = b {
-                            Value::Function(
-                                Rc::new(p.clone()),
-                                vec![],
-                                body.as_ref() as *const TypedExpr,
-                            )
-                        } else if let (ExprT::BuiltInFn(f), _) = b {
-                            Value::BuiltInFn(*f)
-                        } else {
-                            panic!()
+                            // scale so the digits to keep sit left of the decimal point.
+                            // dividing by an exact power of ten instead of multiplying
+                            // by its (inexact) reciprocal keeps e.g. 120.0 exact.
+                            let magnitude = x.abs().log10().floor() as i32;
+                            let shift = (*sig as i32) - 1 - magnitude;
+                            let rounded = if shift >= 0 {
+                                let p = 10f64.powi(shift);
+                                (x * p).round() / p
+                            } else {
+                                let p = 10f64.powi(-shift);
+                                (x / p).round() * p
+                            };
+
+                            self.push_val(Value::Float(rounded));
                         }
-                    } else if let Some(b) = self.bindings.get(s).cloned() {
-                        b
-                    } else {
-                        panic!("{:?}", s)
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
                     }
-                };
-                self.push_val(val);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
             }
-            ExprT::Record(fields) => {
-                let mut r = Vec::new();
-                for f in fields {
-                    self.eval_expr(f);
-                    r.push(self.pop_val().unwrap());
-                }
-                self.push_val(Value::Tuple(r));
-            }
-            ExprT::BinaryOp(op, lhs, rhs) => {
-                self.eval_expr(lhs);
-                self.eval_expr(rhs);
-
-                use crate::ast::untyped::Operator;
-
-                match (self.pop_val().unwrap(), self.pop_val().unwrap()) {
-                    (Value::Integer(r), Value::Integer(l)) => {
-                        let r = match op {
-                            Operator::BinOpAdd => l + r,
-                            Operator::BinOpSub => l - r,
-                            Operator::BinOpMul => l * r,
-                            Operator::BinOpDiv => l / r,
-                            Operator::BinOpLess => (l < r) as i64,
-                            Operator::BinOpLessEq => (l <= r) as i64,
-                            Operator::BinOpGreater => (l > r) as i64,
-                            Operator::BinOpGreaterEq => (l >= r) as i64,
-                            Operator::BinOpEquals => (l == r) as i64,
-                            Operator::BinOpAnd => (l & r) as i64,
-                            Operator::BinOpOr => (l | r) as i64,
-                            Operator::BinOpMod => (l % r),
-                            _ => panic!(),
-                        };
+            BuiltInFn::StringRepeatJoined => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 3)?;
+                    match (&args[0], &args[1], &args[2]) {
+                        (Value::String(unit), Value::Integer(count), Value::String(sep)) => {
+                            if *count < 0 {
+                                return Err(RuntimeError::InvalidArgument(
+                                    builtin,
+                                    format!("negative count {}", count),
+                                ));
+                            }
+
+                            let len = usize::try_from(*count)
+                                .ok()
+                                .and_then(|n| {
+                                    let seps = sep.len().checked_mul(n.saturating_sub(1))?;
+                                    unit.len().checked_mul(n)?.checked_add(seps)
+                                })
+                                .filter(|len| *len <= MAX_REPEATED_LEN)
+                                .ok_or_else(|| {
+                                    RuntimeError::InvalidArgument(
+                                        builtin,
+                                        format!("repeating {} times is too long", count),
+                                    )
+                                })?;
 
-                        self.push_val(Value::Integer(r));
+                            let mut joined = String::with_capacity(len);
+                            // repeating nothing any number of times is nothing
+                            if len > 0 {
+                                for i in 0..*count {
+                                    if i != 0 {
+                                        joined.push_str(sep);
+                                    }
+                                    joined.push_str(unit);
+                                }
+                            }
+                            self.push_val(Value::String(Rc::new(joined)));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
                     }
-                    (Value::String(r), Value::String(l)) => match op {
-                        Operator::BinOpEquals => {
-                            self.push_val(Value::Integer((l == r) as i64));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::StringCommonPrefix => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::String(a), Value::String(b)) => {
+                            let len = a
+                                .char_indices()
+                                .zip(b.chars())
+                                .find(|((_, ca), cb)| ca != cb)
+                                .map_or(a.len().min(b.len()), |((i, _), _)| i);
+
+                            self.push_val(Value::String(Rc::new(a[..len].to_owned())));
                         }
-                        _ => panic!(),
-                    },
-                    _ => panic!(),
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
-            ExprT::StringLiteral(s) => {
-                self.push_val(Value::String(Rc::new(s.clone())));
+            BuiltInFn::StringWrap => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::String(text), Value::Integer(width)) => {
+                            if *width <= 0 {
+                                return Err(RuntimeError::InvalidArgument(
+                                    builtin,
+                                    format!("cannot wrap to width {}", width),
+                                ));
+                            }
+                            let width = *width as usize;
+
+                            // greedy, existing line breaks are kept as they are
+                            let mut out = Vec::new();
+                            for line in text.split('\n') {
+                                let mut current = String::new();
+                                for word in line.split(' ').filter(|w| !w.is_empty()) {
+                                    let len = current.chars().count();
+                                    if len > 0 && len + 1 + word.chars().count() > width {
+                                        out.push(std::mem::take(&mut current));
+                                    }
+                                    if !current.is_empty() {
+                                        current.push(' ');
+                                    }
+                                    current.push_str(word);
+                                }
+                                out.push(current);
+                            }
+
+                            self.push_val(Value::String(Rc::new(out.join("\n"))));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
             }
-            ExprT::IntegerLiteral(i) => self.push_val(Value::Integer(*i)),
-            ExprT::VariantConstructor(th, vi) => {
-                let t = self.program.environment.borrow().types[th.index].clone();
-                if let TypeDefinition::Sum { variants, .. } = t {
-                    let (_n, vt) = &variants[*vi];
-                    {
-                        self.push_val(Value::VariantConstructorFn(th.clone(), *vi));
+            BuiltInFn::ListDot => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::List(a), Value::List(b)) => {
+                            if a.len() != b.len() {
+                                return Err(RuntimeError::InvalidArgument(
+                                    builtin,
+                                    format!(
+                                        "lists of different length {} and {}",
+                                        a.len(),
+                                        b.len()
+                                    ),
+                                ));
+                            }
+
+                            let mut sum = 0i64;
+                            for (x, y) in a.iter().zip(b.iter()) {
+                                match (x, y) {
+                                    (Value::Integer(x), Value::Integer(y)) => {
+                                        sum = x
+                                            .checked_mul(*y)
+                                            .and_then(|p| sum.checked_add(p))
+                                            .ok_or_else(|| {
+                                                RuntimeError::ArithmeticError(
+                                                    "integer overflow in List_dot".to_owned(),
+                                                )
+                                            })?;
+                                    }
+                                    _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                                }
+                            }
+
+                            self.push_val(Value::Integer(sum));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
                     }
                 } else {
-                    panic!()
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
-            ExprT::BuiltInFn(f) => {
-                self.push_val(Value::BuiltInFn(f.clone()));
+            BuiltInFn::PathNormalize => {
+                if let Value::String(path) = arg {
+                    self.push_val(Value::String(Rc::new(normalize_path(&path))));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
             }
-            ExprT::FieldAccess(lhs, i) => {
-                self.eval_expr(lhs);
+            BuiltInFn::PathJoin => {
+                if let Value::List(segments) = arg {
+                    let mut joined = String::new();
+                    for segment in segments {
+                        if let Value::String(segment) = segment {
+                            // an absolute segment replaces everything before it
+                            if segment.starts_with('/') {
+                                joined.clear();
+                            } else if !joined.is_empty() && !joined.ends_with('/') {
+                                joined.push('/');
+                            }
+                            joined.push_str(&segment);
+                        } else {
+                            return Err(RuntimeError::unexpected_argument(builtin));
+                        }
+                    }
+                    self.push_val(Value::String(Rc::new(joined)));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::PathExtension => {
+                if let Value::String(path) = arg {
+                    // like std::path, a leading dot starts a name rather than an extension
+                    let result = match path_basename(&path).rfind('.') {
+                        Some(i) if i > 0 => self.prelude_variant(
+                            "Option",
+                            "Some",
+                            Value::String(Rc::new(path_basename(&path)[i + 1..].to_owned())),
+                        ),
+                        _ => self.prelude_variant("Option", "None", Value::Unit),
+                    };
+                    self.push_val(result);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::PathBasename => {
+                if let Value::String(path) = arg {
+                    self.push_val(Value::String(Rc::new(path_basename(&path).to_owned())));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ReadDir => {
+                self.require(builtin, "allow_file_read", |c| c.allow_file_read)?;
 
-                if let Some(Value::Tuple(values)) = self.pop_val() {
-                    self.push_val(values[*i].clone())
+                if let Value::String(path) = arg {
+                    let io_error =
+                        |e: std::io::Error| RuntimeError::IoError(path.to_string(), e.to_string());
+
+                    let mut names = Vec::new();
+                    for entry in std::fs::read_dir(path.as_str()).map_err(io_error)? {
+                        let name = entry.map_err(io_error)?.file_name();
+                        names.push(name.to_string_lossy().into_owned());
+                    }
+                    names.sort();
+
+                    self.push_val(Value::List(
+                        names
+                            .into_iter()
+                            .map(|n| Value::String(Rc::new(n)))
+                            .collect(),
+                    ));
                 } else {
-                    panic!()
+                    return Err(RuntimeError::unexpected_argument(builtin));
                 }
             }
-            ExprT::Unit => self.push_val(Value::Unit),
-            _ => {
-                dbg!(expr);
-                unimplemented!()
+            BuiltInFn::MakeDir => {
+                self.require(builtin, "allow_file_write", |c| c.allow_file_write)?;
+
+                if let Value::String(path) = arg {
+                    // create_dir_all is already fine with the directory existing
+                    std::fs::create_dir_all(path.as_str())
+                        .map_err(|e| RuntimeError::IoError(path.to_string(), e.to_string()))?;
+                    self.push_val(Value::Unit);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
             }
-        }
-    }
-}
+            BuiltInFn::DeleteFile => {
+                self.require(builtin, "allow_file_write", |c| c.allow_file_write)?;
 
-pub fn interpret(program: TypeChecked) {
-    let mut interpreter = Interpreter {
-        bindings: HashMap::new(),
-        stack: Vec::new(),
-        program,
-    };
+                if let Value::String(path) = arg {
+                    std::fs::remove_file(path.as_str())
+                        .map_err(|e| RuntimeError::IoError(path.to_string(), e.to_string()))?;
+                    self.push_val(Value::Unit);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::FileExists => {
+                self.require(builtin, "allow_file_read", |c| c.allow_file_read)?;
+
+                if let Value::String(path) = arg {
+                    let exists = std::path::Path::new(path.as_str()).exists();
+                    self.push_val(Value::Integer(exists as i64));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::RefNew => self.push_val(Value::Ref(Rc::new(RefCell::new(arg)))),
+            BuiltInFn::RefGet => {
+                if let Value::Ref(cell) = arg {
+                    let v = cell.borrow().clone();
+                    self.push_val(v);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::RefSet => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::Ref(cell), v) => {
+                            *cell.borrow_mut() = v.clone();
+                            self.push_val(Value::Unit);
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::CopyFile | BuiltInFn::MoveFile => {
+                self.require(builtin, "allow_file_write", |c| c.allow_file_write)?;
 
-    interpreter.call_fn("main");
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::String(src), Value::String(dst)) => {
+                            // both of these replace an existing destination
+                            let result = if let BuiltInFn::CopyFile = builtin {
+                                std::fs::copy(src.as_str(), dst.as_str()).map(|_| ())
+                            } else {
+                                std::fs::rename(src.as_str(), dst.as_str())
+                            };
 
-    dbg!(interpreter.pop_val());
+                            result.map_err(|e| {
+                                RuntimeError::IoError(src.to_string(), e.to_string())
+                            })?;
+                            self.push_val(Value::Unit);
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::RollingHash => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::String(s), Value::Integer(window)) => {
+                            if *window <= 0 {
+                                return Err(RuntimeError::InvalidArgument(
+                                    builtin,
+                                    format!("window size must be positive, got {}", window),
+                                ));
+                            }
+
+                            let chars = s.chars().collect::<Vec<_>>();
+                            self.push_val(Value::List(
+                                rolling_hashes(&chars, *window as usize)
+                                    .into_iter()
+                                    .map(Value::Integer)
+                                    .collect(),
+                            ));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::RleEncode => {
+                if let Value::String(s) = arg {
+                    let mut runs: Vec<(char, i64)> = Vec::new();
+                    for c in s.chars() {
+                        match runs.last_mut() {
+                            Some((last, count)) if *last == c => *count += 1,
+                            _ => runs.push((c, 1)),
+                        }
+                    }
+
+                    self.push_val(Value::List(
+                        runs.into_iter()
+                            .map(|(c, count)| {
+                                Value::Tuple(vec![
+                                    Value::String(Rc::new(c.to_string())),
+                                    Value::Integer(count),
+                                ])
+                            })
+                            .collect(),
+                    ));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::RleDecode => {
+                if let Value::List(runs) = arg {
+                    // the runs are all checked before any is expanded, so a
+                    // decoding that would be too long fails without building it
+                    let mut decoded = Vec::with_capacity(runs.len());
+                    let mut total = 0usize;
+                    for run in &runs {
+                        match run {
+                            Value::Tuple(run) if run.len() == 2 => match (&run[0], &run[1]) {
+                                (Value::String(_), Value::Integer(count)) if *count < 0 => {
+                                    return Err(RuntimeError::InvalidArgument(
+                                        builtin,
+                                        format!("negative run length {}", count),
+                                    ))
+                                }
+                                (Value::String(s), Value::Integer(count)) => {
+                                    total = usize::try_from(*count)
+                                        .ok()
+                                        .and_then(|n| s.len().checked_mul(n))
+                                        .and_then(|len| len.checked_add(total))
+                                        .filter(|len| *len <= MAX_REPEATED_LEN)
+                                        .ok_or_else(|| {
+                                            RuntimeError::InvalidArgument(
+                                                builtin,
+                                                format!("a run of {} is too long", count),
+                                            )
+                                        })?;
+                                    decoded.push((s, *count as usize));
+                                }
+                                _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                            },
+                            _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                        }
+                    }
+
+                    let mut out = String::with_capacity(total);
+                    for (s, count) in decoded {
+                        out.push_str(&s.repeat(count));
+                    }
+
+                    self.push_val(Value::String(Rc::new(out)));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListStats => {
+                if let Value::List(values) = arg {
+                    let ints = values
+                        .iter()
+                        .map(|v| match v {
+                            Value::Integer(i) => Ok(*i),
+                            _ => Err(RuntimeError::unexpected_argument(builtin)),
+                        })
+                        .collect::<RuntimeResult<Vec<_>>>()?;
+
+                    if ints.is_empty() {
+                        return Err(RuntimeError::InvalidArgument(
+                            builtin,
+                            "cannot compute statistics of an empty list".to_owned(),
+                        ));
+                    }
+
+                    let sum = ints.iter().try_fold(0i64, |acc, i| acc.checked_add(*i));
+                    let sum = sum.ok_or_else(|| {
+                        RuntimeError::ArithmeticError(
+                            "integer overflow in List_stats sum".to_owned(),
+                        )
+                    })?;
+                    let count = ints.len() as i64;
+
+                    self.push_val(Value::Tuple(vec![
+                        Value::Integer(count),
+                        Value::Integer(sum),
+                        Value::Integer(*ints.iter().min().unwrap()),
+                        Value::Integer(*ints.iter().max().unwrap()),
+                        Value::Float(sum as f64 / count as f64),
+                    ]));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::MapNew => self.push_val(Value::Map(Rc::new(RefCell::new(ValueMap::new())))),
+            BuiltInFn::MapInsert => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 3)?;
+                    match (&args[0], &args[1], &args[2]) {
+                        (Value::Map(map), k, v) => {
+                            let key = Self::map_key(builtin, k)?;
+                            map.borrow_mut().insert(key, (k.clone(), v.clone()));
+                            self.push_val(Value::Unit);
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::MapGet | BuiltInFn::MapRemove => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::Map(map), k) => {
+                            let key = Self::map_key(builtin, k)?;
+                            let found = if let BuiltInFn::MapGet = builtin {
+                                map.borrow().get(&key).map(|(_, v)| v.clone())
+                            } else {
+                                map.borrow_mut().remove(&key).map(|(_, v)| v)
+                            };
+
+                            let v = match found {
+                                Some(v) => self.prelude_variant("Option", "Some", v),
+                                None => self.prelude_variant("Option", "None", Value::Unit),
+                            };
+                            self.push_val(v);
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::Format => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::String(fmt), values) => {
+                            // a tuple supplies one value per placeholder, unit supplies
+                            // none and anything else is a single value
+                            let values = match values {
+                                Value::Tuple(values) => values.iter().collect(),
+                                Value::Unit => vec![],
+                                v => vec![v],
+                            };
+
+                            let out = format_placeholders(fmt, &values)
+                                .map_err(|msg| RuntimeError::InvalidArgument(builtin, msg))?;
+                            self.push_val(Value::String(Rc::new(out)));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::Assert => {
+                let (cond, msg) = match arg {
+                    Value::Integer(cond) => (cond, None),
+                    Value::Tuple(args) => {
+                        expect_arity(builtin, &args, 2)?;
+                        match (&args[0], &args[1]) {
+                            (Value::Integer(cond), Value::String(msg)) => {
+                                (*cond, Some(msg.to_string()))
+                            }
+                            _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                        }
+                    }
+                    _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                };
+
+                if cond == 0 {
+                    return Err(RuntimeError::AssertionFailed(msg));
+                }
+                self.push_val(Value::Unit);
+            }
+            BuiltInFn::Exit => match arg {
+                Value::Integer(code) => return Err(RuntimeError::Exit(code)),
+                _ => return Err(RuntimeError::unexpected_argument(builtin)),
+            },
+            BuiltInFn::Raise => return Err(RuntimeError::Raised(arg)),
+            BuiltInFn::Catch => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    let depths = self.depths();
+
+                    let result = match self.apply_function(args[0].clone(), Value::Unit) {
+                        Err(e) if e.is_recoverable() => {
+                            self.unwind(depths);
+                            let failure = self.failure(&e);
+                            self.apply_function(args[1].clone(), failure)?
+                        }
+                        result => result?,
+                    };
+                    self.push_val(result);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::StringCharAt => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::String(s), Value::Integer(i)) => {
+                            // indexes count chars, not bytes
+                            let c = usize::try_from(*i)
+                                .ok()
+                                .and_then(|i| s.chars().nth(i))
+                                .ok_or_else(|| {
+                                    RuntimeError::InvalidArgument(
+                                        builtin,
+                                        format!(
+                                            "index {} out of bounds for a string of {} chars",
+                                            i,
+                                            s.chars().count()
+                                        ),
+                                    )
+                                })?;
+                            self.push_val(Value::Char(c));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::StringToChars => {
+                if let Value::String(s) = arg {
+                    self.push_val(Value::List(s.chars().map(Value::Char).collect()));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::IntAbs => {
+                if let Value::Integer(i) = arg {
+                    let abs = i.checked_abs().ok_or_else(|| {
+                        RuntimeError::ArithmeticError(format!("abs of {} overflows", i))
+                    })?;
+                    self.push_val(Value::Integer(abs));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::IntMin | BuiltInFn::IntMax => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::Integer(a), Value::Integer(b)) => {
+                            let r = match builtin {
+                                BuiltInFn::IntMin => *a.min(b),
+                                _ => *a.max(b),
+                            };
+                            self.push_val(Value::Integer(r));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::FloatSqrt
+            | BuiltInFn::FloatFloor
+            | BuiltInFn::FloatCeil
+            | BuiltInFn::FloatRound => {
+                if let Value::Float(x) = arg {
+                    let r = match builtin {
+                        BuiltInFn::FloatSqrt => x.sqrt(),
+                        BuiltInFn::FloatFloor => x.floor(),
+                        BuiltInFn::FloatCeil => x.ceil(),
+                        _ => x.round(),
+                    };
+                    self.push_val(Value::Float(r));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::FileReadLines => {
+                self.require(builtin, "allow_file_read", |c| c.allow_file_read)?;
+
+                if let Value::String(path) = arg {
+                    let buf = std::fs::read_to_string(path.as_str())
+                        .map_err(|e| RuntimeError::IoError(path.to_string(), e.to_string()))?;
+                    // a trailing newline doesn't start another line, \r\n is a newline too
+                    let lines = buf
+                        .lines()
+                        .map(|l| Value::String(Rc::new(l.to_owned())))
+                        .collect();
+                    self.push_val(Value::List(lines));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::JsonParse => {
+                if let Value::String(s) = arg {
+                    let th = TypeHandle::prelude(&self.program.environment, "Json");
+                    let value = json::json_to_value(&s, &th).map_err(|e| match e {
+                        json::JsonError::Malformed(at, expected) => RuntimeError::InvalidArgument(
+                            builtin,
+                            format!("malformed json at byte {}, expected {}", at, expected),
+                        ),
+                        e => RuntimeError::InvalidArgument(builtin, format!("{:?}", e)),
+                    })?;
+                    self.push_val(value);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::DeepCopy => self.push_val(arg.deep_copy()),
+            BuiltInFn::Equals => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    let equal = values_equal(&args[0], &args[1])?;
+                    self.push_val(Value::Integer(equal as i64));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::Host(index) => {
+                let handler = self.program.builtins.handler(index).ok_or_else(|| {
+                    RuntimeError::Unimplemented(format!("host builtin {}", index))
+                })?;
+                let result = handler(self, arg)?;
+                self.push_val(result);
+            }
+            BuiltInFn::TypeOf => self.push_val(Value::String(Rc::new(arg.kind()))),
+            BuiltInFn::StringIndexOf => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::String(haystack), Value::String(needle)) => {
+                            // find gives a byte offset, which is counted in chars to match
+                            // String_char_at. An empty needle is found right at the start.
+                            let result = match haystack.find(needle.as_str()) {
+                                Some(at) => self.prelude_variant(
+                                    "Option",
+                                    "Some",
+                                    Value::Integer(haystack[..at].chars().count() as i64),
+                                ),
+                                None => self.prelude_variant("Option", "None", Value::Unit),
+                            };
+                            self.push_val(result);
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::Substring => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 3)?;
+                    match (&args[0], &args[1], &args[2]) {
+                        (Value::String(s), Value::Integer(start), Value::Integer(end)) => {
+                            let len = s.chars().count();
+                            let in_range = |i: i64| usize::try_from(i).ok().filter(|i| *i <= len);
+
+                            let (from, to) = match (in_range(*start), in_range(*end)) {
+                                (Some(from), Some(to)) if from <= to => (from, to),
+                                _ => {
+                                    return Err(RuntimeError::InvalidArgument(
+                                        builtin,
+                                        format!(
+                                            "range {}..{} is invalid for a string of {} chars",
+                                            start, end, len
+                                        ),
+                                    ))
+                                }
+                            };
+
+                            let sub = s.chars().skip(from).take(to - from).collect::<String>();
+                            self.push_val(Value::String(Rc::new(sub)));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::TupleToList => match arg {
+                Value::Tuple(values) => self.push_val(Value::List(values)),
+                _ => return Err(RuntimeError::unexpected_argument(builtin)),
+            },
+            BuiltInFn::ListToTuple => match arg {
+                Value::List(values) if values.len() == 2 => self.push_val(Value::Tuple(values)),
+                Value::List(values) => {
+                    return Err(RuntimeError::InvalidArgument(
+                        builtin,
+                        format!("a pair needs 2 elements, got {}", values.len()),
+                    ))
+                }
+                _ => return Err(RuntimeError::unexpected_argument(builtin)),
+            },
+            BuiltInFn::ListLength => {
+                if let Value::List(values) = arg {
+                    self.push_val(Value::Integer(values.len() as i64));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListGet => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::List(values), Value::Integer(i)) => {
+                            let elem = usize::try_from(*i).ok().and_then(|i| values.get(i));
+                            let result = match elem {
+                                Some(v) => self.prelude_variant("Option", "Some", v.clone()),
+                                None => self.prelude_variant("Option", "None", Value::Unit),
+                            };
+                            self.push_val(result);
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::RangeFold => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 4)?;
+                    let mut args = args.into_iter();
+                    match (
+                        args.next().unwrap(),
+                        args.next().unwrap(),
+                        args.next().unwrap(),
+                        args.next().unwrap(),
+                    ) {
+                        (Value::Integer(start), Value::Integer(end), init, f) => {
+                            let mut acc = init;
+                            for i in start..end {
+                                let partial = self.apply_function(f.clone(), acc)?;
+                                acc = self.apply_function(partial, Value::Integer(i))?;
+                            }
+                            self.push_val(acc);
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ReadAll => {
+                let mut buf = String::new();
+                let read = match &mut self.input {
+                    Some(input) => input.read_to_string(&mut buf),
+                    None => std::io::stdin().read_to_string(&mut buf),
+                };
+                read.map_err(|e| RuntimeError::IoError("<stdin>".to_owned(), e.to_string()))?;
+                self.push_val(Value::String(Rc::new(buf)));
+            }
+            BuiltInFn::EPrint | BuiltInFn::EPrintln => {
+                if let Value::String(s) = arg {
+                    self.write_error_output(&s);
+                    if let BuiltInFn::EPrintln = builtin {
+                        self.write_error_output("\n");
+                    }
+                    self.push_val(Value::Unit);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            // the index of the variant in its type's declaration, as matches use it
+            BuiltInFn::VariantTag => match arg {
+                Value::Variant(_, vi, _) => self.push_val(Value::Integer(vi as i64)),
+                _ => return Err(RuntimeError::unexpected_argument(builtin)),
+            },
+            BuiltInFn::VariantName => match arg.variant_name() {
+                Some(name) => self.push_val(Value::String(Rc::new(name))),
+                None => return Err(RuntimeError::unexpected_argument(builtin)),
+            },
+            // [start, end) counting up, empty when end isn't past start
+            BuiltInFn::RangeList | BuiltInFn::RangeStep => {
+                if let Value::Tuple(args) = arg {
+                    let step = match builtin {
+                        BuiltInFn::RangeStep => {
+                            expect_arity(builtin, &args, 3)?;
+                            match args[2] {
+                                Value::Integer(step) if step > 0 => step as usize,
+                                Value::Integer(step) => {
+                                    return Err(RuntimeError::InvalidArgument(
+                                        builtin,
+                                        format!("step must be positive, got {}", step),
+                                    ))
+                                }
+                                _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                            }
+                        }
+                        _ => {
+                            expect_arity(builtin, &args, 2)?;
+                            1
+                        }
+                    };
+
+                    match (&args[0], &args[1]) {
+                        (Value::Integer(start), Value::Integer(end)) => {
+                            let values = (*start..*end).step_by(step).map(Value::Integer).collect();
+                            self.push_val(Value::List(values));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            // both in the order the keys were first inserted
+            BuiltInFn::MapKeys | BuiltInFn::MapValues => {
+                if let Value::Map(map) = arg {
+                    let values = map
+                        .borrow()
+                        .values()
+                        .map(|(k, v)| match builtin {
+                            BuiltInFn::MapKeys => k.clone(),
+                            _ => v.clone(),
+                        })
+                        .collect();
+                    self.push_val(Value::List(values));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::StringFold => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 3)?;
+                    let mut args = args.into_iter();
+                    match (args.next().unwrap(), args.next().unwrap(), args.next().unwrap()) {
+                        (Value::String(s), init, f) => {
+                            let mut acc = init;
+                            for c in s.chars() {
+                                let partial = self.apply_function(f.clone(), acc)?;
+                                acc = self.apply_function(partial, Value::Char(c))?;
+                            }
+                            self.push_val(acc);
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::CharCode => match arg {
+                Value::Char(c) => self.push_val(Value::Integer(c as i64)),
+                _ => return Err(RuntimeError::unexpected_argument(builtin)),
+            },
+            BuiltInFn::CharFromCode => match arg {
+                Value::Integer(code) => {
+                    // surrogates and anything past U+10FFFF aren't chars
+                    let c = u32::try_from(code).ok().and_then(std::char::from_u32).ok_or_else(|| {
+                        RuntimeError::InvalidArgument(
+                            builtin,
+                            format!("{} is not a valid code point", code),
+                        )
+                    })?;
+                    self.push_val(Value::Char(c));
+                }
+                _ => return Err(RuntimeError::unexpected_argument(builtin)),
+            },
+            BuiltInFn::TimeIt => {
+                let start = (self.clock)();
+                let result = self.apply_function(arg, Value::Unit)?;
+                let elapsed = (self.clock)().checked_sub(start).unwrap_or_default();
+                let millis = Value::Integer(elapsed.as_millis() as i64);
+
+                self.push_val(Value::Tuple(vec![result, millis]));
+            }
+            // non overlapping occurrences, so "aa" occurs twice in "aaaaa"
+            BuiltInFn::StringCount => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::String(_), Value::String(needle)) if needle.is_empty() => {
+                            return Err(RuntimeError::InvalidArgument(
+                                builtin,
+                                "cannot count occurrences of an empty string".to_owned(),
+                            ))
+                        }
+                        (Value::String(haystack), Value::String(needle)) => {
+                            let count = haystack.matches(needle.as_str()).count();
+                            self.push_val(Value::Integer(count as i64));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            // the calls that are active, not counting the call to stack_depth itself
+            BuiltInFn::StackDepth => {
+                let depth = self.call_stack.len().saturating_sub(1);
+                self.push_val(Value::Integer(depth as i64));
+            }
+            BuiltInFn::StringWords => {
+                if let Value::String(s) = arg {
+                    let words = s
+                        .split_whitespace()
+                        .map(|w| Value::String(Rc::new(w.to_owned())))
+                        .collect();
+                    self.push_val(Value::List(words));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::StringRepeat => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    match (&args[0], &args[1]) {
+                        (Value::String(_), Value::Integer(count)) if *count < 0 => {
+                            return Err(RuntimeError::InvalidArgument(
+                                builtin,
+                                format!("negative count {}", count),
+                            ))
+                        }
+                        (Value::String(s), Value::Integer(count)) => {
+                            let len = usize::try_from(*count)
+                                .ok()
+                                .and_then(|n| s.len().checked_mul(n))
+                                .filter(|len| *len <= MAX_REPEATED_LEN);
+                            if len.is_none() {
+                                return Err(RuntimeError::InvalidArgument(
+                                    builtin,
+                                    format!("repeating {} times is too long", count),
+                                ));
+                            }
+
+                            self.push_val(Value::String(Rc::new(s.repeat(*count as usize))));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            // the width is counted in chars, longer strings are left alone
+            BuiltInFn::PadLeft | BuiltInFn::PadRight => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 3)?;
+                    match (&args[0], &args[1], &args[2]) {
+                        (Value::String(s), Value::Integer(width), Value::Char(fill)) => {
+                            let missing = (*width).max(0) as usize;
+                            let missing = missing.saturating_sub(s.chars().count());
+                            let padding = fill.to_string().repeat(missing);
+
+                            let padded = match builtin {
+                                BuiltInFn::PadLeft => padding + s,
+                                _ => s.to_string() + &padding,
+                            };
+                            self.push_val(Value::String(Rc::new(padded)));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            // the argument was already evaluated for its effects, only its value is dropped
+            BuiltInFn::Ignore => self.push_val(Value::Unit),
+            BuiltInFn::BoolToString => match arg {
+                Value::Integer(b) => {
+                    let s = if b != 0 { "true" } else { "false" };
+                    self.push_val(Value::String(Rc::new(s.to_owned())));
+                }
+                _ => return Err(RuntimeError::unexpected_argument(builtin)),
+            },
+            BuiltInFn::StringParseBool => match arg {
+                Value::String(s) => {
+                    let b = match s.to_lowercase().as_str() {
+                        "true" => true,
+                        "false" => false,
+                        _ => {
+                            return Err(RuntimeError::InvalidArgument(
+                                builtin,
+                                format!("{:?} is neither true nor false", s),
+                            ))
+                        }
+                    };
+                    self.push_val(Value::Integer(b as i64));
+                }
+                _ => return Err(RuntimeError::unexpected_argument(builtin)),
+            },
+            // compares with <, so it works on whatever the operator does, None when empty
+            BuiltInFn::ListMin | BuiltInFn::ListMax => {
+                if let Value::List(values) = arg {
+                    let mut values = values.into_iter();
+                    let mut best = match values.next() {
+                        Some(first) => first,
+                        None => {
+                            let none = self.prelude_variant("Option", "None", Value::Unit);
+                            self.push_val(none);
+                            return Ok(());
+                        }
+                    };
+
+                    for v in values {
+                        let (l, r) = match builtin {
+                            BuiltInFn::ListMin => (v.clone(), best.clone()),
+                            _ => (best.clone(), v.clone()),
+                        };
+                        if condition(binary_op(Operator::BinOpLess, l, r)?)? {
+                            best = v;
+                        }
+                    }
+
+                    let some = self.prelude_variant("Option", "Some", best);
+                    self.push_val(some);
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListSort => {
+                if let Value::List(values) = arg {
+                    let sorted = merge_sort(values, &mut |a, b| {
+                        condition(binary_op(Operator::BinOpLess, a.clone(), b.clone())?)
+                    })?;
+                    self.push_val(Value::List(sorted));
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+            BuiltInFn::ListSortBy => {
+                if let Value::Tuple(args) = arg {
+                    expect_arity(builtin, &args, 2)?;
+                    let mut args = args.into_iter();
+                    match (args.next().unwrap(), args.next().unwrap()) {
+                        (Value::List(values), less) => {
+                            let sorted =
+                                merge_sort(values, &mut |a, b| self.less_than(&less, a, b))?;
+                            self.push_val(Value::List(sorted));
+                        }
+                        _ => return Err(RuntimeError::unexpected_argument(builtin)),
+                    }
+                } else {
+                    return Err(RuntimeError::unexpected_argument(builtin));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks `arg` against the parameter type of `builtin` before it runs,
+    /// so a wrong argument is reported the same way for every builtin
+    fn check_argument(&self, builtin: BuiltInFn, arg: &Value) -> RuntimeResult<()> {
+        let ty = match builtin {
+            BuiltInFn::Host(index) => match self.program.builtins.host_type(index) {
+                Some(ty) => ty.clone(),
+                None => return Ok(()),
+            },
+            _ => builtin.resolved_type(&self.program.environment),
+        };
+
+        match &ty {
+            Type::ConstructedType(TypeConstructor::Function, params) => match &params[0] {
+                param if !arg.has_type(param) => {
+                    Err(RuntimeError::argument_mismatch(builtin, param, arg))
+                }
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn require(
+        &self,
+        builtin: BuiltInFn,
+        name: &'static str,
+        capability: impl Fn(&Capabilities) -> bool,
+    ) -> RuntimeResult<()> {
+        if capability(&self.capabilities) {
+            Ok(())
+        } else {
+            Err(RuntimeError::MissingCapability(builtin, name))
+        }
+    }
+
+    /// Finds the innermost binding of `name` in the current call
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        let start = self.frames.last().copied().unwrap_or(0);
+        self.bindings[start..]
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+
+    /// Runs an entry point and returns its value, attaching the call stack to
+    /// any error. A panic inside becomes an `InternalError` so embedders survive
+    /// whatever case the interpreter still doesn't handle.
+    fn run(&mut self, entry: impl FnOnce(&mut Self) -> RuntimeResult<()>) -> RuntimeResult<Value> {
+        let result =
+            std::panic::catch_unwind(AssertUnwindSafe(|| entry(self))).unwrap_or_else(|payload| {
+                let msg = match payload.downcast_ref::<&str>() {
+                    Some(msg) => msg.to_string(),
+                    None => match payload.downcast_ref::<String>() {
+                        Some(msg) => msg.clone(),
+                        None => "panic without a message".to_owned(),
+                    },
+                };
+                Err(RuntimeError::InternalError(msg))
+            });
+
+        match result {
+            Ok(()) => Ok(self.pop_val().unwrap()),
+            Err(e @ RuntimeError::Exit(_)) => Err(e),
+            Err(e) => Err(RuntimeError::Traced(
+                std::mem::take(&mut self.call_stack),
+                box e,
+            )),
+        }
+    }
+
+    fn map_key(builtin: BuiltInFn, key: &Value) -> RuntimeResult<HashKey> {
+        key.hash_key().ok_or_else(|| {
+            RuntimeError::InvalidArgument(builtin, format!("key {} is not hashable", key))
+        })
+    }
+
+    /// How deep each of the stacks an error can leave behind is
+    fn depths(&self) -> (usize, usize, usize, usize) {
+        (
+            self.stack.len(),
+            self.bindings.len(),
+            self.frames.len(),
+            self.call_stack.len(),
+        )
+    }
+
+    /// Drops what an error left on the stacks above `depths`, before recovering from it
+    fn unwind(&mut self, (stack, bindings, frames, calls): (usize, usize, usize, usize)) {
+        self.stack.truncate(stack);
+        self.bindings.truncate(bindings);
+        self.frames.truncate(frames);
+        self.call_stack.truncate(calls);
+    }
+
+    /// What a handler recovering from `e` is given, `Raised` with the value passed
+    /// to `raise` or `Failed` with the message of any other error
+    fn failure(&self, e: &RuntimeError) -> Value {
+        match e.raised() {
+            Some(v) => self.prelude_variant("Failure", "Raised", v.clone()),
+            None => {
+                let msg = Value::String(Rc::new(e.to_string()));
+                self.prelude_variant("Failure", "Failed", msg)
+            }
+        }
+    }
+
+    fn prelude_variant(&self, ty: &str, variant: &str, payload: Value) -> Value {
+        let th = TypeHandle::prelude(&self.program.environment, ty);
+        let vi = th.variant_index(variant);
+        Value::variant(th, vi, payload)
+    }
+
+    fn match_pattern(
+        pattern: &PatternT,
+        value: &Value,
+        bound: &mut Vec<(String, Value)>,
+    ) -> RuntimeResult<bool> {
+        match (pattern, value) {
+            (PatternT::Wildcard, _) => Ok(true),
+            (PatternT::Binding(name), v) => {
+                bound.push((name.clone(), v.clone()));
+                Ok(true)
+            }
+            (PatternT::Variant(i, payload), Value::Variant(_, vi, fields)) => match payload
+                .as_deref()
+            {
+                Some(PatternT::Tuple(patterns)) if i == vi && patterns.len() == fields.len() => {
+                    for (p, v) in patterns.iter().zip(fields.iter()) {
+                        if !Self::match_pattern(p, v, bound)? {
+                            return Ok(false);
+                        }
+                    }
+                    Ok(true)
+                }
+                Some(p) if i == vi => {
+                    Self::match_pattern(p, &Value::variant_payload(fields), bound)
+                }
+                _ => Ok(i == vi),
+            },
+            (PatternT::Tuple(patterns), Value::Tuple(values)) => {
+                for (p, v) in patterns.iter().zip(values.iter()) {
+                    if !Self::match_pattern(p, v, bound)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            (PatternT::Record(patterns), Value::Record(fields)) => {
+                for (name, p) in patterns {
+                    let (_, v) = fields.iter().find(|(n, _)| n == name).ok_or_else(|| {
+                        RuntimeError::TypeMismatch(format!("record has no field {}", name))
+                    })?;
+                    if !Self::match_pattern(p, v, bound)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            _ => Err(RuntimeError::TypeMismatch(format!(
+                "pattern {:?} cannot match {}",
+                pattern, value
+            ))),
+        }
+    }
+
+    fn less_than(&mut self, less: &Value, a: &Value, b: &Value) -> RuntimeResult<bool> {
+        let partial = self.apply_function(less.clone(), a.clone())?;
+        match self.apply_function(partial, b.clone())? {
+            Value::Integer(0) => Ok(false),
+            Value::Integer(_) => Ok(true),
+            v => Err(RuntimeError::TypeMismatch(format!(
+                "comparison returned {} instead of a Bool",
+                v
+            ))),
+        }
+    }
+
+    pub fn apply_function(&mut self, f: Value, arg: Value) -> RuntimeResult<Value> {
+        if let Some(profile) = &mut self.profile {
+            profile.applications += 1;
+        }
+
+        match f {
+            Value::Function(p, curried, body) => {
+                // the body only sees what the closure captured and its parameter
+                let start = self.bindings.len();
+                self.frames.push(start);
+
+                self.bindings.extend(curried);
+                self.bindings.push(((*p).clone(), arg));
+
+                let depth = self.stack.len();
+                let result = self.eval_expr(&body);
+
+                self.bindings.truncate(start);
+                self.frames.pop();
+                match result {
+                    Ok(()) => Ok(self.pop_val().unwrap()),
+                    // `?` leaves whatever the body was in the middle of on the stack
+                    Err(RuntimeError::Return(v)) => {
+                        self.stack.truncate(depth);
+                        Ok(v)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Value::VariantConstructorFn(th, vi) => Ok(Value::variant(th, vi, arg)),
+            Value::BuiltInFn(f) => {
+                self.call_builtin(f, arg)?;
+                Ok(self.pop_val().unwrap())
+            }
+            Value::Memoized(memo) => {
+                let (key, builtin) = match &memo.key_fn {
+                    Some(key_fn) => (
+                        self.apply_function(key_fn.clone(), arg.clone())?,
+                        BuiltInFn::MemoizeBy,
+                    ),
+                    None => (arg.clone(), BuiltInFn::Memoize),
+                };
+                let key = key.hash_key().ok_or_else(|| {
+                    RuntimeError::InvalidArgument(builtin, format!("key {} is not hashable", key))
+                })?;
+
+                if let Some(cached) = memo.cache.borrow().get(&key) {
+                    return Ok(cached.clone());
+                }
+
+                let result = self.apply_function(memo.function.clone(), arg)?;
+                memo.cache.borrow_mut().insert(key, result.clone());
+                Ok(result)
+            }
+            f => Err(RuntimeError::NotAFunction(f.to_string())),
+        }
+    }
+
+    /// Uses up a unit of fuel for one step of the program, failing once there
+    /// is none left or the deadline has passed
+    pub(crate) fn charge(&mut self) -> RuntimeResult<()> {
+        if let Some(fuel) = &mut self.fuel {
+            *fuel = fuel.checked_sub(1).ok_or(RuntimeError::OutOfFuel)?;
+        }
+
+        if let Some((deadline, until_check)) = &mut self.deadline {
+            if *until_check == 0 {
+                if Instant::now() >= *deadline {
+                    return Err(RuntimeError::Timeout);
+                }
+                *until_check = DEADLINE_CHECK_INTERVAL;
+            } else {
+                *until_check -= 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn eval_expr(&mut self, typed_expr: &TypedExpr) -> RuntimeResult<()> {
+        self.charge()?;
+
+        if let Some(trace) = &mut self.trace {
+            trace(typed_expr, &self.stack);
+        }
+
+        if let Some(profile) = &mut self.profile {
+            *profile.evaluations.entry(typed_expr.0.kind_name()).or_insert(0) += 1;
+        }
+
+        if let Some((breakpoints, hook)) = &mut self.breakpoints {
+            if let Some(span) = typed_expr.0.span().filter(|s| breakpoints.contains(*s)) {
+                let start = self.frames.last().copied().unwrap_or(0);
+                hook(span, &self.bindings[start..], &self.stack);
+            }
+        }
+
+        let (expr, _et) = typed_expr;
+        match expr {
+            ExprT::Tuple(exprs) => {
+                let mut vals = Vec::new();
+                for e in exprs {
+                    self.eval_expr(e)?;
+                    vals.push(self.pop_val().unwrap());
+                }
+                self.push_val(Value::Tuple(vals));
+            }
+            ExprT::ListLiteral(exprs) => {
+                let mut vals = Vec::new();
+                for e in exprs {
+                    self.eval_expr(e)?;
+                    vals.push(self.pop_val().unwrap());
+                }
+                self.push_val(Value::List(vals));
+            }
+            ExprT::LetBinding(pattern, rhs, body) => {
+                self.eval_expr(rhs)?;
+                let rv = self.pop_val().unwrap();
+
+                // let patterns are irrefutable, the typechecker makes sure of that
+                let mut bound = Vec::new();
+                assert!(Self::match_pattern(pattern, &rv, &mut bound)?);
+
+                let depth = self.bindings.len();
+                self.bindings.extend(bound);
+                self.eval_expr(body)?;
+                self.bindings.truncate(depth);
+            }
+            ExprT::MatchSum(matchee, arms, span) => {
+                self.eval_expr(matchee)?;
+
+                let val = self.pop_val().unwrap();
+
+                for (pattern, body) in arms {
+                    let mut bound = Vec::new();
+                    if Self::match_pattern(pattern, &val, &mut bound)? {
+                        let depth = self.bindings.len();
+                        self.bindings.extend(bound);
+                        self.eval_expr(body)?;
+                        self.bindings.truncate(depth);
+
+                        return Ok(());
+                    }
+                }
+
+                let unmatched = val.variant_name().unwrap_or_else(|| val.to_string());
+                return Err(RuntimeError::NonExhaustiveMatch(*span, unmatched));
+            }
+            ExprT::Application(lhs, rhs, span) => {
+                self.eval_expr(lhs)?;
+
+                let function = match &lhs.0 {
+                    ExprT::Symbol(name, _) => name.clone(),
+                    ExprT::BuiltInFn(builtin) => format!("{:?}", builtin),
+                    _ => "<anonymous>".to_owned(),
+                };
+
+                // every argument is applied to the result of the previous one, so
+                // partial application falls out of functions being curried
+                for (applied, expr) in rhs.iter().enumerate() {
+                    let top = self.pop_val().unwrap();
+                    if !top.is_callable() {
+                        return Err(RuntimeError::TooManyArguments(function, applied).at(*span));
+                    }
+
+                    self.eval_expr(expr)?;
+                    let rv = self.pop_val().unwrap();
+
+                    self.call_stack.push(CallFrame {
+                        function: function.clone(),
+                        call_site: Some(*span),
+                    });
+                    let result = self.apply_function(top, rv).map_err(|e| e.at(*span))?;
+                    self.call_stack.pop();
+
+                    self.push_val(result);
+                }
+            }
+            ExprT::Lambda(p, body, captures) => {
+                // the parameter is bound on application, everything else the body
+                // refers to has to be captured now
+                let captured = captures
+                    .iter()
+                    .filter_map(|name| self.lookup(name).map(|v| (name.clone(), v.clone())))
+                    .collect();
+
+                self.push_val(Value::Function(
+                    Rc::new(p.clone()),
+                    captured,
+                    body.clone(),
+                ));
+            }
+            ExprT::BooleanLiteral(b) => self.push_val(Value::Integer(*b as i64)),
+            ExprT::Conditional(cond, cons, alt) => {
+                self.eval_expr(cond)?;
+
+                if condition(self.pop_val().unwrap())? {
+                    self.eval_expr(cons)?
+                } else {
+                    self.eval_expr(alt)?
+                }
+            }
+            ExprT::IfThen(cond, cons) => {
+                self.eval_expr(cond)?;
+
+                if condition(self.pop_val().unwrap())? {
+                    self.eval_expr(cons)?
+                } else {
+                    self.push_val(Value::Unit)
+                }
+            }
+            ExprT::Try(body, handler) => {
+                let depths = self.depths();
+
+                match self.eval_expr(body) {
+                    Ok(()) => (),
+                    Err(e) if e.is_recoverable() => {
+                        self.unwind(depths);
+                        self.eval_expr(handler)?;
+                        let handler = self.pop_val().unwrap();
+                        let failure = self.failure(&e);
+                        let result = self.apply_function(handler, failure)?;
+                        self.push_val(result);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            ExprT::Propagate(operand, _) => {
+                self.eval_expr(operand)?;
+
+                let ok =
+                    TypeHandle::prelude(&self.program.environment, "Result").variant_index("Ok");
+                match self.pop_val().unwrap() {
+                    Value::Variant(_, vi, fields) if vi == ok => self.push_val(fields[0].clone()),
+                    err @ Value::Variant(..) => return Err(RuntimeError::Return(err)),
+                    v => {
+                        return Err(RuntimeError::TypeMismatch(format!(
+                            "? applied to {} instead of a Result",
+                            v
+                        )))
+                    }
+                }
+            }
+            ExprT::Sequence(exprs) => {
+                let (last, effects) = exprs.split_last().unwrap();
+
+                for e in effects {
+                    self.eval_expr(e)?;
+                    self.pop_val();
+                }
+
+                self.eval_expr(last)?;
+            }
+            ExprT::While(cond, body) => {
+                loop {
+                    self.eval_expr(cond)?;
+
+                    if !condition(self.pop_val().unwrap())? {
+                        break;
+                    }
+                    self.eval_expr(body)?;
+                    self.pop_val();
+                }
+
+                self.push_val(Value::Unit);
+            }
+            ExprT::Symbol(s, span) => {
+                // locals shadow top level bindings, as they do when typechecking
+                let val = if let Some(b) = self.lookup(s).cloned() {
+                    b
+                } else {
+                    let env = self.program.environment.borrow();
+
+                    let b = env.root_scope.bindings.get(s);
+
+                    if let Some(b) = b {
+                        if let (ExprT::Lambda(p, body, _), _) = b {
+                            Value::Function(
+                                Rc::new(p.clone()),
+                                vec![],
+                                body.clone(),
+                            )
+                        } else if let (ExprT::BuiltInFn(f), _) = b {
+                            Value::BuiltInFn(*f)
+                        } else if let Some(value) = self.program.builtins.global(s) {
+                            value.clone()
+                        } else {
+                            return Err(RuntimeError::Unimplemented(format!(
+                                "top level binding {} which is not a function",
+                                s
+                            ))
+                            .at(*span));
+                        }
+                    } else {
+                        let start = self.frames.last().copied().unwrap_or(0);
+                        let candidates = env
+                            .root_scope
+                            .bindings
+                            .keys()
+                            .chain(self.bindings[start..].iter().map(|(n, _)| n))
+                            .map(String::as_str);
+                        let similar = closest_name(s, candidates);
+
+                        return Err(RuntimeError::UnboundSymbol(s.clone(), similar).at(*span));
+                    }
+                };
+                self.push_val(val);
+            }
+            ExprT::Record(fields) => {
+                let mut r = Vec::new();
+                for (n, f) in fields {
+                    self.eval_expr(f)?;
+                    r.push((n.clone(), self.pop_val().unwrap()));
+                }
+                self.push_val(Value::Record(r));
+            }
+            ExprT::BinaryOp(op @ Operator::BinOpAnd, lhs, rhs, span)
+            | ExprT::BinaryOp(op @ Operator::BinOpOr, lhs, rhs, span) => {
+                self.eval_expr(lhs)?;
+
+                let lv = self.pop_val().unwrap();
+                match (op, &lv) {
+                    // the left side already decides the result
+                    (Operator::BinOpAnd, Value::Integer(0)) => self.push_val(lv),
+                    (Operator::BinOpOr, Value::Integer(l)) if *l != 0 => self.push_val(lv),
+                    (_, Value::Integer(_)) => self.eval_expr(rhs)?,
+                    _ => {
+                        return Err(RuntimeError::TypeMismatch(format!(
+                            "cannot apply {:?} to {}",
+                            op, lv
+                        ))
+                        .at(*span))
+                    }
+                }
+            }
+            ExprT::BinaryOp(op, lhs, rhs, span) => {
+                // pop each operand right after evaluating it, so the order
+                // never depends on how the stack is unwound
+                self.eval_expr(lhs)?;
+                let l = self.pop_val().unwrap();
+                self.eval_expr(rhs)?;
+                let r = self.pop_val().unwrap();
+
+                let result = binary_op(*op, l, r).map_err(|e| e.at(*span))?;
+                self.push_val(result);
+            }
+            ExprT::UnaryOp(op, operand, span) => {
+                self.eval_expr(operand)?;
+
+                let v = self.pop_val().unwrap();
+                let result = unary_op(*op, v).map_err(|e| e.at(*span))?;
+                self.push_val(result);
+            }
+            ExprT::StringLiteral(s) => {
+                self.push_val(Value::String(Rc::new(s.clone())));
+            }
+            ExprT::IntegerLiteral(i) => self.push_val(Value::Integer(*i)),
+            ExprT::FloatLiteral(f) => self.push_val(Value::Float(*f)),
+            ExprT::VariantConstructor(th, vi) => {
+                let t = self.program.environment.borrow().types[th.index].clone();
+                if let TypeDefinition::Sum { .. } = t {
+                    if th.variant_arity(*vi) == 0 {
+                        self.push_val(Value::Variant(th.clone(), *vi, Rc::new(vec![])));
+                    } else {
+                        self.push_val(Value::VariantConstructorFn(th.clone(), *vi));
+                    }
+                } else {
+                    return Err(RuntimeError::TypeMismatch(format!(
+                        "{} is not a sum type",
+                        t.qualified_name()
+                    )));
+                }
+            }
+            ExprT::BuiltInFn(f) => {
+                self.push_val(Value::BuiltInFn(f.clone()));
+            }
+            ExprT::RecordUpdate(base, updates) => {
+                self.eval_expr(base)?;
+
+                if let Some(Value::Record(mut fields)) = self.pop_val() {
+                    for (name, e) in updates {
+                        self.eval_expr(e)?;
+                        let v = self.pop_val().unwrap();
+
+                        let (_, field) =
+                            fields.iter_mut().find(|(n, _)| n == name).ok_or_else(|| {
+                                RuntimeError::TypeMismatch(format!("record has no field {}", name))
+                            })?;
+                        *field = v;
+                    }
+                    self.push_val(Value::Record(fields));
+                } else {
+                    return Err(RuntimeError::TypeMismatch(
+                        "record update on a non record value".to_owned(),
+                    ));
+                }
+            }
+            ExprT::RecordFieldAccess(lhs, name) => {
+                self.eval_expr(lhs)?;
+
+                if let Some(Value::Record(fields)) = self.pop_val() {
+                    let (_, v) = fields.into_iter().find(|(n, _)| n == name).ok_or_else(|| {
+                        RuntimeError::TypeMismatch(format!("record has no field {}", name))
+                    })?;
+                    self.push_val(v)
+                } else {
+                    return Err(RuntimeError::TypeMismatch(format!(
+                        "field access .{} on a non record value",
+                        name
+                    )));
+                }
+            }
+            ExprT::FieldAccess(lhs, i) => {
+                self.eval_expr(lhs)?;
+
+                match self.pop_val() {
+                    Some(Value::Tuple(values)) if *i < values.len() => {
+                        self.push_val(values[*i].clone())
+                    }
+                    _ => {
+                        return Err(RuntimeError::TypeMismatch(format!(
+                            "field access .{} on a value which is not a large enough tuple",
+                            i
+                        )))
+                    }
+                }
+            }
+            ExprT::Unit => self.push_val(Value::Unit),
+        }
+
+        Ok(())
+    }
+}
+
+/// Bools are integers at runtime, with 0 being false
+pub(crate) fn condition(v: Value) -> RuntimeResult<bool> {
+    match v {
+        Value::Integer(i) => Ok(i != 0),
+        v => Err(RuntimeError::ConditionNotBool(v.kind())),
+    }
+}
+
+pub(crate) fn unary_op(op: UnaryOperator, v: Value) -> RuntimeResult<Value> {
+    match (op, v) {
+        (UnaryOperator::UnOpNeg, Value::Integer(i)) => i
+            .checked_neg()
+            .map(Value::Integer)
+            .ok_or_else(|| RuntimeError::ArithmeticError(format!("negating {} overflows", i))),
+        (UnaryOperator::UnOpNeg, Value::Float(f)) => Ok(Value::Float(-f)),
+        (UnaryOperator::UnOpNot, Value::Integer(b)) => Ok(Value::Integer((b == 0) as i64)),
+        (op, v) => Err(RuntimeError::TypeMismatch(format!(
+            "cannot apply {:?} to {}",
+            op, v
+        ))),
+    }
+}
+
+pub(crate) fn binary_op(op: Operator, l: Value, r: Value) -> RuntimeResult<Value> {
+    let mismatch = |l: &Value, r: &Value| RuntimeError::OperandMismatch(op, l.kind(), r.kind());
+
+    if let Operator::BinOpEquals = op {
+        return Ok(Value::Integer(values_equal(&l, &r)? as i64));
+    }
+
+    match (&l, &r) {
+        (Value::Integer(l), Value::Integer(r)) => {
+            let (l, r) = (*l, *r);
+            let overflow = || {
+                RuntimeError::ArithmeticError(format!("integer overflow in {} {:?} {}", l, op, r))
+            };
+
+            let r = match op {
+                Operator::BinOpAdd => l.checked_add(r).ok_or_else(overflow)?,
+                Operator::BinOpSub => l.checked_sub(r).ok_or_else(overflow)?,
+                Operator::BinOpMul => l.checked_mul(r).ok_or_else(overflow)?,
+                Operator::BinOpDiv | Operator::BinOpMod if r == 0 => {
+                    return Err(RuntimeError::ArithmeticError("division by zero".to_owned()))
+                }
+                Operator::BinOpDiv => l.checked_div(r).ok_or_else(overflow)?,
+                Operator::BinOpLess => (l < r) as i64,
+                Operator::BinOpLessEq => (l <= r) as i64,
+                Operator::BinOpGreater => (l > r) as i64,
+                Operator::BinOpGreaterEq => (l >= r) as i64,
+                Operator::BinOpMod => l.checked_rem_euclid(r).ok_or_else(overflow)?,
+                Operator::BinOpShl | Operator::BinOpShr if !(0..64).contains(&r) => {
+                    return Err(RuntimeError::ArithmeticError(format!(
+                        "shift amount {} is out of range",
+                        r
+                    )))
+                }
+                // checked_shl only fails for amounts outside of 0..64, which are
+                // rejected above, bits shifted out are lost so `1 << 63` is negative
+                Operator::BinOpShl => l.checked_shl(r as u32).ok_or_else(overflow)?,
+                Operator::BinOpShr => l.checked_shr(r as u32).ok_or_else(overflow)?,
+                Operator::BinOpPow if r < 0 => {
+                    return Err(RuntimeError::ArithmeticError(format!(
+                        "negative exponent in {} ^ {}",
+                        l, r
+                    )))
+                }
+                Operator::BinOpPow => u32::try_from(r)
+                    .ok()
+                    .and_then(|r| l.checked_pow(r))
+                    .ok_or_else(overflow)?,
+                _ => return Err(mismatch(&Value::Integer(l), &Value::Integer(r))),
+            };
+
+            Ok(Value::Integer(r))
+        }
+        // floats follow IEEE 754, dividing by zero gives an infinity or NaN
+        (Value::Float(l), Value::Float(r)) => {
+            let (l, r) = (*l, *r);
+            let v = match op {
+                Operator::BinOpAdd => Value::Float(l + r),
+                Operator::BinOpSub => Value::Float(l - r),
+                Operator::BinOpMul => Value::Float(l * r),
+                Operator::BinOpDiv => Value::Float(l / r),
+                Operator::BinOpPow => Value::Float(l.powf(r)),
+                Operator::BinOpLess => Value::Integer((l < r) as i64),
+                Operator::BinOpLessEq => Value::Integer((l <= r) as i64),
+                Operator::BinOpGreater => Value::Integer((l > r) as i64),
+                Operator::BinOpGreaterEq => Value::Integer((l >= r) as i64),
+                Operator::BinOpEquals => Value::Integer((l == r) as i64),
+                _ => return Err(mismatch(&Value::Float(l), &Value::Float(r))),
+            };
+
+            Ok(v)
+        }
+        (Value::String(ls), Value::String(rs)) => {
+            let b = match op {
+                Operator::BinOpLess => ls < rs,
+                Operator::BinOpLessEq => ls <= rs,
+                Operator::BinOpGreater => ls > rs,
+                Operator::BinOpGreaterEq => ls >= rs,
+                _ => return Err(mismatch(&l, &r)),
+            };
+
+            Ok(Value::Integer(b as i64))
+        }
+        (Value::Char(lc), Value::Char(rc)) => {
+            let b = match op {
+                Operator::BinOpLess => lc < rc,
+                Operator::BinOpLessEq => lc <= rc,
+                Operator::BinOpGreater => lc > rc,
+                Operator::BinOpGreaterEq => lc >= rc,
+                _ => return Err(mismatch(&l, &r)),
+            };
+
+            Ok(Value::Integer(b as i64))
+        }
+        _ => Err(mismatch(&l, &r)),
+    }
+}
+
+/// Structural equality, compound values are equal when all their parts are.
+/// Values of different kinds are never equal, functions can't be compared at all.
+fn values_equal(l: &Value, r: &Value) -> RuntimeResult<bool> {
+    let all_equal = |ls: &[Value], rs: &[Value]| -> RuntimeResult<bool> {
+        if ls.len() != rs.len() {
+            return Ok(false);
+        }
+
+        for (l, r) in ls.iter().zip(rs.iter()) {
+            if !values_equal(l, r)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    };
+
+    match (l, r) {
+        (Value::Unit, Value::Unit) => Ok(true),
+        (Value::Integer(l), Value::Integer(r)) => Ok(l == r),
+        (Value::Float(l), Value::Float(r)) => Ok(l == r),
+        (Value::String(l), Value::String(r)) => Ok(l == r),
+        (Value::Char(l), Value::Char(r)) => Ok(l == r),
+        (Value::Tuple(ls), Value::Tuple(rs)) | (Value::List(ls), Value::List(rs)) => {
+            all_equal(ls, rs)
+        }
+        (Value::Record(ls), Value::Record(rs)) => {
+            if ls.len() != rs.len() {
+                return Ok(false);
+            }
+
+            for (name, lv) in ls {
+                match rs.iter().find(|(n, _)| n == name) {
+                    Some((_, rv)) if values_equal(lv, rv)? => (),
+                    _ => return Ok(false),
+                }
+            }
+
+            Ok(true)
+        }
+        (Value::Variant(lt, lv, lf), Value::Variant(rt, rv, rf)) => {
+            if lt != rt || lv != rv || lf.len() != rf.len() {
+                return Ok(false);
+            }
+
+            for (l, r) in lf.iter().zip(rf.iter()) {
+                if !values_equal(l, r)? {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        }
+        (Value::Ref(l), Value::Ref(r)) => values_equal(&l.borrow(), &r.borrow()),
+        (Value::Map(l), Value::Map(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            if l.len() != r.len() {
+                return Ok(false);
+            }
+
+            for (key, (_, lv)) in l.iter() {
+                match r.get(key) {
+                    Some((_, rv)) if values_equal(lv, rv)? => (),
+                    _ => return Ok(false),
+                }
+            }
+
+            Ok(true)
+        }
+        (l, r) if l.is_callable() || r.is_callable() => Err(RuntimeError::TypeMismatch(
+            format!("cannot compare {} and {} for equality", l, r),
+        )),
+        _ => Ok(false),
+    }
+}
+
+/// Deep copies of a stack and bindings, sharing cells between them wherever the originals did
+fn copy_state(
+    stack: &[Value],
+    bindings: &[(String, Value)],
+) -> (Vec<Value>, Vec<(String, Value)>) {
+    let mut copies = HashMap::new();
+
+    let stack = stack.iter().map(|v| v.deep_copy_with(&mut copies)).collect();
+    let bindings = bindings
+        .iter()
+        .map(|(n, v)| (n.clone(), v.deep_copy_with(&mut copies)))
+        .collect();
+
+    (stack, bindings)
+}
+
+fn expect_arity(builtin: BuiltInFn, args: &[Value], n: usize) -> RuntimeResult<()> {
+    if args.len() == n {
+        Ok(())
+    } else {
+        Err(RuntimeError::ArityMismatch(builtin, n, args.len()))
+    }
+}
+
+/// A stable sort which stops at the first comparison that fails. `less` may
+/// run code from the program, so it isn't trusted to be a total order.
+fn merge_sort(
+    mut values: Vec<Value>,
+    less: &mut impl FnMut(&Value, &Value) -> RuntimeResult<bool>,
+) -> RuntimeResult<Vec<Value>> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, less)?;
+    let right = merge_sort(right, less)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // equal elements keep their order by taking from the left first
+        if less(r, l)? {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+
+    Ok(merged)
+}
+
+/// Collapses `.`, `..` and repeated separators without touching the filesystem
+fn normalize_path(path: &str) -> String {
+    let absolute = path.starts_with('/');
+
+    let mut components: Vec<&str> = Vec::new();
+    for c in path.split('/') {
+        match c {
+            "" | "." => (),
+            ".." => match components.last() {
+                Some(&last) if last != ".." => {
+                    components.pop();
+                }
+                // `..` at the root is the root itself
+                _ if absolute => (),
+                _ => components.push(".."),
+            },
+            c => components.push(c),
+        }
+    }
+
+    let joined = components.join("/");
+    match (absolute, joined.is_empty()) {
+        (true, _) => format!("/{}", joined),
+        (false, true) => ".".to_owned(),
+        (false, false) => joined,
+    }
+}
+
+/// The last component of `path`, ignoring trailing separators
+fn path_basename(path: &str) -> &str {
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.rfind('/') {
+        Some(i) => &trimmed[i + 1..],
+        None => trimmed,
+    }
+}
+
+/// Substitutes `{}` in `fmt` with `values` in order, `{{` and `}}` are literal braces
+fn format_placeholders(fmt: &str, values: &[&Value]) -> Result<String, String> {
+    let mut out = String::new();
+    let mut values = values.iter();
+    let mut used = 0;
+    let mut chars = fmt.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                out.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                match values.next() {
+                    Some(v) => out.push_str(&v.to_string()),
+                    None => {
+                        return Err(format!("more placeholders than the {} values given", used))
+                    }
+                }
+                used += 1;
+            }
+            ('{', _) | ('}', _) => return Err(format!("unmatched {} in format string", c)),
+            (c, _) => out.push(c),
+        }
+    }
+
+    match values.len() {
+        0 => Ok(out),
+        n => Err(format!(
+            "{} placeholders but {} values given",
+            used,
+            used + n
+        )),
+    }
+}
+
+const HASH_BASE: i64 = 257;
+const HASH_MODULUS: i64 = 1_000_000_007;
+
+/// Rabin-Karp style hashes of every `window` sized run of `chars`, each one
+/// derived from the previous in constant time
+fn rolling_hashes(chars: &[char], window: usize) -> Vec<i64> {
+    if window > chars.len() {
+        return Vec::new();
+    }
+
+    // the weight of the char leaving the window, base^(window - 1)
+    let top = (1..window).fold(1, |acc, _| acc * HASH_BASE % HASH_MODULUS);
+
+    let mut hash = chars[..window]
+        .iter()
+        .fold(0, |acc, &c| (acc * HASH_BASE + c as i64) % HASH_MODULUS);
+    let mut hashes = vec![hash];
+
+    for i in window..chars.len() {
+        let outgoing = chars[i - window] as i64 * top % HASH_MODULUS;
+        hash = (hash - outgoing + HASH_MODULUS) % HASH_MODULUS;
+        hash = (hash * HASH_BASE + chars[i] as i64) % HASH_MODULUS;
+        hashes.push(hash);
+    }
+
+    hashes
+}
+
+pub fn interpret(program: TypeChecked) -> RuntimeResult<Value> {
+    interpret_with_config(program, InterpreterConfig::default())
+}
+
+pub fn interpret_with_config(
+    program: TypeChecked,
+    config: InterpreterConfig,
+) -> RuntimeResult<Value> {
+    Interpreter::with_config(program, config).run(|i| i.call_fn("main"))
+}
+
+/// The exit code of a process that ran a program to `result`. An Int returned
+/// from main is the exit code like one passed to exit, any other value is success.
+pub fn exit_code(result: &RuntimeResult<Value>) -> i32 {
+    match result {
+        Ok(Value::Integer(code)) | Err(RuntimeError::Exit(code)) => *code as i32,
+        Ok(_) => 0,
+        Err(_) => 1,
+    }
+}
+
+pub fn interpret_with_seed(program: TypeChecked, seed: u64) -> RuntimeResult<Value> {
+    let config = InterpreterConfig {
+        seed,
+        ..InterpreterConfig::default()
+    };
+    interpret_with_config(program, config)
+}
+
+pub fn interpret_with_capabilities(
+    program: TypeChecked,
+    capabilities: Capabilities,
+) -> RuntimeResult<Value> {
+    let config = InterpreterConfig {
+        capabilities,
+        ..InterpreterConfig::default()
+    };
+    interpret_with_config(program, config)
+}
+
+/// Runs `main`, failing with `OutOfFuel` once it has evaluated `fuel` expressions
+pub fn interpret_with_fuel(program: TypeChecked, fuel: u64) -> RuntimeResult<Value> {
+    let config = InterpreterConfig {
+        fuel: Some(fuel),
+        ..InterpreterConfig::default()
+    };
+    interpret_with_config(program, config)
+}
+
+/// Runs `main`, failing with `Timeout` if it is still running after `timeout`
+pub fn interpret_with_timeout(program: TypeChecked, timeout: Duration) -> RuntimeResult<Value> {
+    let config = InterpreterConfig {
+        timeout: Some(timeout),
+        ..InterpreterConfig::default()
+    };
+    interpret_with_config(program, config)
+}
+
+pub fn interpret_with_trace(program: TypeChecked, trace: TraceFn) -> RuntimeResult<Value> {
+    let config = InterpreterConfig {
+        trace: Some(trace),
+        ..InterpreterConfig::default()
+    };
+    interpret_with_config(program, config)
+}
+
+/// Runs `main` and returns everything it printed along with its result.
+/// What it prints with `eprint` still goes to stderr.
+pub fn interpret_capturing(program: TypeChecked) -> (RuntimeResult<Value>, String) {
+    let captured = Rc::new(RefCell::new(String::new()));
+    let sink = captured.clone();
+    let config = InterpreterConfig {
+        output: Some(box move |s: &str| sink.borrow_mut().push_str(s)),
+        ..InterpreterConfig::default()
+    };
+    let result = interpret_with_config(program, config);
+
+    let output = captured.borrow().clone();
+    (result, output)
+}
+
+/// Runs `main` and reports how often each kind of expression was evaluated,
+/// which is filled in as far as the program got even if it fails
+pub fn interpret_with_profile(program: TypeChecked) -> (RuntimeResult<Value>, Profile) {
+    let config = InterpreterConfig {
+        profile: true,
+        ..InterpreterConfig::default()
+    };
+    let mut interpreter = Interpreter::with_config(program, config);
+    let result = interpreter.run(|i| i.call_fn("main"));
+
+    (result, interpreter.profile.unwrap_or_default())
+}
+
+pub fn interpret_with_breakpoints(
+    program: TypeChecked,
+    breakpoints: Breakpoints,
+    hook: BreakpointHook,
+) -> RuntimeResult<Value> {
+    let config = InterpreterConfig {
+        breakpoints: Some((breakpoints, hook)),
+        ..InterpreterConfig::default()
+    };
+    interpret_with_config(program, config)
+}
+
+/// Runs the top level function `name` applied to `args` rather than `main`
+pub fn run_function(program: TypeChecked, name: &str, args: Vec<Value>) -> RuntimeResult<Value> {
+    let mut interpreter = Interpreter::new(program);
+
+    interpreter.run(|i| i.call_with_args(name, args))
+}
+
+pub fn interpret_binding(program: TypeChecked, name: &str) -> RuntimeResult<Value> {
+    let mut interpreter = Interpreter::new(program);
+
+    interpreter.run(|i| i.eval_binding(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval_string;
+
+    fn eval(src: &str) -> Value {
+        eval_string(src).unwrap_or_else(|e| panic!("{} failed: {:?}", src, e))
+    }
+
+    fn eval_int(src: &str) -> i64 {
+        match eval(src) {
+            Value::Integer(i) => i,
+            v => panic!("{} evaluated to {} instead of an integer", src, v),
+        }
+    }
+
+    /// a fresh, empty directory for a test to work in
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("lang-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// the value of `src` along with everything it printed
+    fn eval_printing(src: &str) -> (Value, String) {
+        let (result, output) = interpret_capturing(crate::typecheck_source(src));
+        (result.unwrap_or_else(|e| panic!("{} failed: {:?}", src, e)), output)
+    }
+
+    /// the error `src` fails with, stripped of where it happened
+    fn eval_err(src: &str) -> RuntimeError {
+        match eval_string(src) {
+            Err(crate::Error::Runtime(e)) => innermost(e),
+            r => panic!("{} didn't fail at runtime: {:?}", src, r),
+        }
+    }
+
+    /// Runs `src` with globals bound at types they don't have, the way a careless
+    /// host might bind them: `one` is declared a String but holds the integer 1,
+    /// `word` is declared an Int but holds a string
+    fn eval_mistyped(src: &str) -> Result<Value, crate::Error> {
+        let mut builtins = crate::builtins::BuiltinRegistry::default();
+        builtins.bind_global("one", Type::STRING, Value::Integer(1));
+        builtins.bind_global("word", Type::INT, Value::String(Rc::new("one".to_owned())));
+        crate::eval_string_with_builtins(src, builtins)
+    }
+
+    /// `eval_err` for `eval_mistyped`
+    fn eval_mistyped_err(src: &str) -> RuntimeError {
+        match eval_mistyped(src) {
+            Err(crate::Error::Runtime(e)) => innermost(e),
+            r => panic!("{} didn't fail at runtime: {:?}", src, r),
+        }
+    }
+
+    /// `e` without the location and calls wrapped around it
+    fn innermost(mut e: RuntimeError) -> RuntimeError {
+        while let RuntimeError::Located(_, inner) | RuntimeError::Traced(_, inner) = e {
+            e = *inner;
+        }
+        e
+    }
+
+    #[test]
+    fn popcount_counts_set_bits() {
+        assert_eq!(eval_int("main () = Int_popcount 0\n"), 0);
+        assert_eq!(eval_int("main () = Int_popcount 7\n"), 3);
+        assert_eq!(eval_int("main () = Int_popcount (0 - 1)\n"), 64);
+    }
+
+    #[test]
+    fn int_bits_extracts_a_nibble() {
+        assert_eq!(eval_int("main () = Int_bits (43981, 4, 4)\n"), 0xC); // 0xABCD
+        assert_eq!(eval_int("main () = Int_bits (-1, 0, 64)\n"), -1);
+        assert_eq!(eval_int("main () = Int_bits (-1, 60, 4)\n"), 0xF);
+
+        for args in &["0, 61, 4", "0, 0, 65", "0, -1, 4", "0, 9223372036854775807, 1"] {
+            match eval_err(&format!("main () = Int_bits ({})\n", args)) {
+                RuntimeError::InvalidArgument(BuiltInFn::IntBits, _) => {}
+                e => panic!("expected ({}) to be out of range, got {:?}", args, e),
+            }
+        }
+    }
+
+
+    #[test]
+    fn uuid_is_fixed_by_the_seed() {
+        let uuid = |seed| {
+            let program = crate::typecheck_source("main () = (uuid (), uuid ())\n");
+            interpret_with_seed(program, seed).unwrap().to_string()
+        };
+
+        assert_eq!(uuid(7), uuid(7));
+        assert_eq!(
+            uuid(7),
+            "(63cbe1e4-5932-4dd7-844c-3cd7f43c661c, e6984080-bab1-4a02-953a-eb70673e29cb)"
+        );
+        assert_ne!(uuid(7), uuid(8));
+    }
+
+    #[test]
+    fn memoize_by_caches_on_the_key() {
+        let src = "slow :: (Int, String) -> Int\nslow p = p.0 * 10\n\n\
+                   by_name :: (Int, String) -> String\nby_name p = p.1\n\n\
+                   main () =\n\tlet m = memoize_by (slow, by_name)\n\
+                   \t(m (1, \"a\"), m (2, \"a\"), m (3, \"b\"))\n";
+        // (2, "a") has the same key as (1, "a") so gets its result without running slow
+        assert_eq!(eval(src).to_string(), "(10, 10, 30)");
+    }
+
+    #[test]
+    fn retry_stops_at_the_first_ok() {
+        let flaky = "get_int :: Ref Int -> Int\nget_int r = Ref_get r\n\n\
+                     flaky :: Ref Int -> () -> Result Int String\nflaky calls u =\n\
+                     \tlet n = get_int calls + 1\n\tRef_set (calls, n);\n\
+                     \tif n < 3 then Err \"not yet\" else Ok n\n\n";
+
+        let src = format!(
+            "{}main () =\n\tlet calls = Ref_new 0\n\tlet r = retry (5, flaky calls)\n\
+             \t(r, get_int calls)\n",
+            flaky
+        );
+        assert_eq!(eval(&src).to_string(), "(Ok 3, 3)");
+
+        let src = format!(
+            "{}main () =\n\tlet calls = Ref_new 0\n\tlet r = retry (2, flaky calls)\n\
+             \t(r, get_int calls)\n",
+            flaky
+        );
+        assert_eq!(eval(&src).to_string(), "(Err not yet, 2)");
+    }
+
+    #[test]
+    fn empty_and_nested_lists() {
+        let src = "main () =\n\
+                   \tlet empty = []\n\
+                   \tlet nested = [[1, 2], [3], []]\n\
+                   \t(empty, nested, List_append ([1, 2], 3), List_concat ([1], [4, 5]))\n";
+        assert_eq!(eval(src).to_string(), "([], [[1, 2], [3], []], [1, 2, 3], [1, 4, 5])");
+
+        // lists are homogeneous
+        let r = eval_string("main () = [1, \"two\"]\n");
+        assert!(matches!(r, Err(crate::Error::TypeChecking(_))), "{:?}", r);
+    }
+
+    #[test]
+    fn list_product_pairs_every_element() {
+        let v = eval("main () = List_product ([1, 2], [\"a\", \"b\", \"c\"])\n");
+        assert_eq!(v.to_string(), "[(1, a), (1, b), (1, c), (2, a), (2, b), (2, c)]");
+        assert_eq!(eval("main () = List_product ([], [1])\n").to_string(), "[]");
+
+        // 2500 pairs of 2500 pairs is more than a product may hold
+        let fifty = (0..50).map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+        let src = format!(
+            "main () =\n\tlet l = [{}]\n\tlet p = List_product (l, l)\n\tList_product (p, p)\n",
+            fifty
+        );
+        match eval_err(&src) {
+            RuntimeError::InvalidArgument(BuiltInFn::ListProduct2, msg) => {
+                assert_eq!(msg, "the product of 2500 and 2500 elements is too long")
+            }
+            e => panic!("expected the product to be too long, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn map_filter_and_fold() {
+        let src = "add :: Int -> Int -> Int\n\
+                   add a b = a + b\n\
+                   \n\
+                   above :: Int -> Int -> Bool\n\
+                   above n x = x > n\n\
+                   \n\
+                   main () =\n\
+                   \tlet n = 10\n\
+                   \tlet xs = [1, 5, 12, 20]\n\
+                   \t(List_map (add n, xs), List_filter (above n, xs), List_fold (add, 100, xs))\n";
+        assert_eq!(eval(src).to_string(), "([11, 15, 22, 30], [12, 20], 138)");
+
+        // closures capturing a local
+        let src = "main () =\n\tlet tag = \"t\"\n\tList_map (\\x -> (tag, x), [1, 2])\n";
+        assert_eq!(eval(src).to_string(), "[(t, 1), (t, 2)]");
+    }
+
+    #[test]
+    fn map_filter_and_fold_over_empty_lists() {
+        let src = "add :: Int -> Int -> Int\n\
+                   add a b = a + b\n\
+                   \n\
+                   positive :: Int -> Bool\n\
+                   positive x = x > 0\n\
+                   \n\
+                   main () =\n\
+                   \t(List_map (add 1, []), List_fold (add, 7, []), List_filter (positive, []))\n";
+        assert_eq!(eval(src).to_string(), "([], 7, [])");
+    }
+
+    #[test]
+    fn list_is_sorted() {
+        let src = "lt :: Int -> Int -> Bool\n\
+                   lt a b = a < b\n\
+                   \n\
+                   main () =\n\
+                   \t(List_is_sorted ([1, 2, 2, 5], lt), List_is_sorted ([3, 1, 2], lt),\n\
+                   \t List_is_sorted ([], lt), List_is_sorted ([9], lt))\n";
+        assert_eq!(eval(src).to_string(), "(1, 0, 1, 1)");
+    }
+
+    #[test]
+    fn get_first_handles_multibyte_characters() {
+        let src = "main () =\n\
+                   \tlet a = String_get_first \"éa\"\n\
+                   \tlet b = String_get_first \"😀x\"\n\
+                   \t(a, b, Char_to_string a.0, String_get_first \"z\")\n";
+        assert_eq!(eval(src).to_string(), "((é, a), (😀, x), é, (z, ))");
+    }
+
+    #[test]
+    fn binary_search_finds_present_targets_only() {
+        let src = "lt :: Int -> Int -> Bool\n\
+                   lt a b = a < b\n\
+                   \n\
+                   search :: Int -> Option Int\n\
+                   search t = List_binary_search ([1, 3, 5, 7, 9, 11], t, lt)\n\
+                   \n\
+                   main () =\n\
+                   \t(search 7, search 4, search 1, search 11, List_binary_search ([], 1, lt))\n";
+        assert_eq!(eval(src).to_string(), "(Some 3, None, Some 0, Some 5, None)");
+    }
+
+    #[test]
+    fn strings_compare_lexicographically() {
+        let src = "order :: String -> String -> (String, String)\n\
+                   order a b = if a <= b then (a, b) else (b, a)\n\
+                   \n\
+                   main () =\n\
+                   \t(order \"pear\" \"apple\", order \"apple\" \"pear\", order \"b\" \"ab\")\n";
+        assert_eq!(eval(src).to_string(), "((apple, pear), (apple, pear), (ab, b))");
+
+        let src = "main () = (\"abc\" < \"abd\", \"b\" <= \"a\", \"b\" > \"a\", \"a\" >= \"a\")\n";
+        let v = eval(src);
+        assert_eq!(v.to_string(), "(1, 0, 1, 1)");
+    }
+
+    #[test]
+    fn list_transpose() {
+        let v = eval("main () = (List_transpose [[1, 2], [3, 4]], List_transpose [[1, 2, 3]])\n");
+        assert_eq!(v.to_string(), "([[1, 3], [2, 4]], [[1], [2], [3]])");
+
+        match eval_err("main () = List_transpose [[1, 2], [3]]\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::ListTranspose, _) => {}
+            e => panic!("expected ragged input to fail, got {:?}", e),
+        }
+    }
+
+
+    #[test]
+    fn negation() {
+        let v = eval("neg :: Int -> Int\nneg x = -x\n\nmain () = (neg 5, neg (-5), neg 0)\n");
+        assert_eq!(v.to_string(), "(-5, 5, 0)");
+        assert_eq!(eval_int("main () = - -9223372036854775807\n"), i64::MAX);
+
+        match eval_err("main () = - -9223372036854775808\n") {
+            RuntimeError::ArithmeticError(_) => {}
+            e => panic!("expected an overflow, got {:?}", e),
+        }
+    }
+
+
+    #[test]
+    fn logical_not() {
+        let v = eval("main () = (!true, !false, !(1 < 2))\n");
+        // booleans are 0 and 1 at runtime
+        assert_eq!(v.to_string(), "(0, 1, 0)");
+    }
+
+    #[test]
+    fn list_span_splits_off_leading_digits() {
+        let src = "to_chars :: String -> Char[]\n\
+                   to_chars s =\n\
+                   \tif s == \"\" then [] else\n\
+                   \tlet first = String_get_first s\n\
+                   \tList_concat ([first.0], to_chars first.1)\n\
+                   \n\
+                   is_digit :: Char -> Bool\n\
+                   is_digit c =\n\
+                   \tlet s = Char_to_string c\n\
+                   \ts >= \"0\" and s <= \"9\"\n\
+                   \n\
+                   span :: String -> (Char[], Char[])\n\
+                   span s = List_span (to_chars s, is_digit)\n\
+                   \n\
+                   main () = (span \"42abc\", span \"abc\", span \"123\", span \"\")\n";
+        assert_eq!(
+            eval(src).to_string(),
+            "(([4, 2], [a, b, c]), ([], [a, b, c]), ([1, 2, 3], []), ([], []))"
+        );
+    }
+
+    #[test]
+    fn and_or_skip_the_right_side() {
+        // dividing by zero would panic if the right side ran
+        let src = "main () = (false and (1 / 0 == 0), true or (1 / 0 == 0))\n";
+        assert_eq!(eval(src).to_string(), "(0, 1)");
+
+        let src = "main () = (true and (2 / 1 == 2), false or (2 / 1 == 3))\n";
+        assert_eq!(eval(src).to_string(), "(1, 0)");
+
+        match eval_err("main () = true and (1 / 0 == 0)\n") {
+            RuntimeError::ArithmeticError(_) => {}
+            e => panic!("expected the right side to run and fail, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn float_lerp() {
+        let lerp = |t: &str| eval(&format!("main () = Float_lerp (2.0, 6.0, {})\n", t)).to_string();
+        assert_eq!(lerp("0.0"), "2.0");
+        assert_eq!(lerp("1.0"), "6.0");
+        assert_eq!(lerp("0.5"), "4.0");
+        // outside of 0..1 it extrapolates
+        assert_eq!(lerp("1.5"), "8.0");
+    }
+
+    #[test]
+    fn record_fields_are_found_by_name() {
+        let src = "type Point = { x: Int, y: Int, label: String }\n\
+                   \n\
+                   sum :: Point -> Int\n\
+                   sum p = p.x + p.y\n\
+                   \n\
+                   make :: () -> Point\n\
+                   make u = { label: \"origin\", y: 2, x: 1 }\n\
+                   \n\
+                   main () =\n\
+                   \tlet p = make ()\n\
+                   \t(p, p.label, p.y, sum p)\n";
+        assert_eq!(eval(src).to_string(), "({ x: 1, y: 2, label: origin }, origin, 2, 3)");
+    }
+
+    #[test]
+    fn float_clamp() {
+        let src = "main () =\n\
+                   \t(Float_clamp (-5.0, 0.0, 1.0), Float_clamp (0.25, 0.0, 1.0),\n\
+                   \t Float_clamp (3.5, 0.0, 1.0))\n";
+        assert_eq!(eval(src).to_string(), "(0.0, 0.25, 1.0)");
+
+        match eval_err("main () = Float_clamp (0.5, 1.0, 0.0)\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::FloatClamp, _) => {}
+            e => panic!("expected an empty range to fail, got {:?}", e),
+        }
+    }
+
+
+    #[test]
+    fn record_update_copies_the_other_fields() {
+        let src = "type Point = { x: Int, y: Int, label: String }\n\
+                   \n\
+                   make :: () -> Point\n\
+                   make u = { x: 1, y: 2, label: \"a\" }\n\
+                   \n\
+                   main () =\n\
+                   \tlet p = make ()\n\
+                   \t(p, { p with y = 12 })\n";
+        assert_eq!(eval(src).to_string(), "({ x: 1, y: 2, label: a }, { x: 1, y: 12, label: a })");
+
+        let src = "type Point = { x: Int, y: Int }\n\
+                   \n\
+                   make :: () -> Point\n\
+                   make u = { x: 1, y: 2 }\n\
+                   \n\
+                   main () = { make () with z = 3 }\n";
+        let r = eval_string(src);
+        assert!(matches!(r, Err(crate::Error::TypeChecking(_))), "{:?}", r);
+    }
+
+    #[test]
+    fn float_round_sig() {
+        let src = "main () =\n\
+                   \t(Float_round_sig (123.456, 2), Float_round_sig (0.00012345, 3),\n\
+                   \t Float_round_sig (-987.6, 1), Float_round_sig (0.0, 4))\n";
+        assert_eq!(eval(src).to_string(), "(120.0, 0.000123, -1000.0, 0.0)");
+
+        match eval_err("main () = Float_round_sig (1.5, 0)\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::FloatRoundSig, _) => {}
+            e => panic!("expected no significant figures to fail, got {:?}", e),
+        }
+    }
+
+
+    #[test]
+    fn wildcard_arms_match_the_rest() {
+        let src = "type Shape =\n\
+                   \t| Circle of Int\n\
+                   \t| Square of Int\n\
+                   \t| Triangle of Int\n\
+                   \n\
+                   describe :: Shape -> String\n\
+                   describe s = match s with\n\
+                   \t| Circle r -> \"circle\"\n\
+                   \t| _ -> \"polygon\"\n\
+                   \n\
+                   keep_circles :: Shape -> Shape\n\
+                   keep_circles s = match s with\n\
+                   \t| Square x -> Shape.Circle x\n\
+                   \t| other -> other\n\
+                   \n\
+                   main () =\n\
+                   \t(describe (Shape.Circle 1), describe (Shape.Triangle 3),\n\
+                   \t keep_circles (Shape.Square 2), keep_circles (Shape.Triangle 4))\n";
+        assert_eq!(eval(src).to_string(), "(circle, polygon, Circle 2, Triangle 4)");
+    }
+
+    #[test]
+    fn string_repeat_joined() {
+        let src = "main () =\n\
+                   \t(String_repeat_joined (\"?\", 3, \",\"),\n\
+                   \t String_repeat_joined (\"ab\", 0, \"-\"),\n\
+                   \t String_repeat_joined (\"x\", 1, \", \"))\n";
+        assert_eq!(eval(src).to_string(), "(?,?,?, , x)");
+        let v = eval("main () = String_repeat_joined (\"\", 1000000000000, \"\")\n");
+        assert_eq!(v.to_string(), "");
+
+        // the separators count towards the length too
+        for args in &["\"abc\", 9223372036854775807, \",\"", "\"\", 4611686018427387904, \"--\""] {
+            match eval_err(&format!("main () = String_repeat_joined ({})\n", args)) {
+                RuntimeError::InvalidArgument(BuiltInFn::StringRepeatJoined, msg) => {
+                    assert!(msg.ends_with("times is too long"), "{}", msg)
+                }
+                e => panic!("expected repeating ({}) to fail, got {:?}", args, e),
+            }
+        }
+    }
+
+    #[test]
+    fn nested_patterns() {
+        let src = "type Pair = | Pair of (Int, Int)\n\
+                   type Opt = | Nope | Just of Pair\n\
+                   type Named = | Anon | Named of (Int, String)\n\
+                   \n\
+                   sum :: Opt -> Int\n\
+                   sum o = match o with\n\
+                   \t| Just (Pair (a, b)) -> a + b\n\
+                   \t| Nope -> 0\n\
+                   \n\
+                   name :: Named -> String\n\
+                   name n = match n with\n\
+                   \t| Named (_, s) -> s\n\
+                   \t| Anon -> \"none\"\n\
+                   \n\
+                   main () =\n\
+                   \t(sum (Opt.Just (Pair.Pair (1, 2))), sum (Opt.Nope),\n\
+                   \t name (Named.Named (1, \"x\")), name (Named.Anon))\n";
+        assert_eq!(eval(src).to_string(), "(3, 0, x, none)");
+    }
+
+    #[test]
+    fn nested_variant_patterns() {
+        let src = "type Opt = | Nope | Just of Int\n\
+                   type Box = | Empty | Full of Opt\n\
+                   \n\
+                   depth :: Box -> Int\n\
+                   depth b = match b with\n\
+                   \t| Full (Just n) -> n\n\
+                   \t| Full Nope -> 1\n\
+                   \t| Empty -> 0\n\
+                   \n\
+                   main () =\n\
+                   \t(depth (Box.Full (Opt.Just 5)), depth (Box.Full (Opt.Nope)),\n\
+                   \t depth (Box.Empty))\n";
+        assert_eq!(eval(src).to_string(), "(5, 1, 0)");
+    }
+
+    #[test]
+    fn string_common_prefix() {
+        let src = "main () =\n\
+                   \t(String_common_prefix (\"foobar\", \"foobaz\"),\n\
+                   \t String_common_prefix (\"abc\", \"xyz\"),\n\
+                   \t String_common_prefix (\"héllo\", \"hélp\"),\n\
+                   \t String_common_prefix (\"foo\", \"foobar\"))\n";
+        assert_eq!(eval(src).to_string(), "(fooba, , hél, foo)");
+    }
+
+    #[test]
+    fn string_wrap() {
+        let src = "main () = String_wrap (\"the quick brown fox jumps over the lazy dog\", 10)\n";
+        assert_eq!(eval(src).to_string(), "the quick\nbrown fox\njumps over\nthe lazy\ndog");
+
+        // words longer than the width get a line of their own
+        let src = "main () = String_wrap (\"a supercalifragilistic word\", 10)\n";
+        assert_eq!(eval(src).to_string(), "a\nsupercalifragilistic\nword");
+    }
+
+    #[test]
+    fn list_dot() {
+        let src = "main () = (List_dot ([1, 2, 3], [4, 5, 6]), List_dot ([], []))\n";
+        assert_eq!(eval(src).to_string(), "(32, 0)");
+
+        match eval_err("main () = List_dot ([1, 2], [3])\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::ListDot, _) => {}
+            e => panic!("expected unequal lengths to fail, got {:?}", e),
+        }
+        match eval_err("main () = List_dot ([9223372036854775807], [2])\n") {
+            RuntimeError::ArithmeticError(_) => {}
+            e => panic!("expected an overflow, got {:?}", e),
+        }
+    }
+
+
+
+    #[test]
+    fn let_destructuring() {
+        let src = "type Point = { x: Int, y: Int }\n\
+                   \n\
+                   origin :: () -> Point\n\
+                   origin u = { x: 3, y: 4 }\n\
+                   \n\
+                   main () =\n\
+                   \tlet (c, rest) = String_get_first \"hello\"\n\
+                   \tlet { x, y: py } = origin ()\n\
+                   \tlet (_, (a, b)) = (1, (2, 3))\n\
+                   \t(Char_to_string c, rest, x + py, a * b)\n";
+        assert_eq!(eval(src).to_string(), "(h, ello, 7, 6)");
+    }
+
+    #[test]
+    fn path_normalize_and_join() {
+        let src = "main () =\n\
+                   \t(Path_normalize \"a/./b/../c\", Path_normalize \"/../x//y/\",\n\
+                   \t Path_normalize \"../a/..\", Path_normalize \"a/..\",\n\
+                   \t Path_join [\"a\", \"b/\", \"c.txt\"],\n\
+                   \t Path_join [\"a\", \"/etc\", \"hosts\"])\n";
+        assert_eq!(eval(src).to_string(), "(a/c, /x/y, .., ., a/b/c.txt, /etc/hosts)");
+    }
+
+    #[test]
+    fn path_extension_and_basename() {
+        let src = "main () =\n\
+                   \t(Path_extension \"dir/file.tar.gz\",\n\
+                   \t Path_basename \"dir/file.tar.gz\",\n\
+                   \t Path_extension \"dir.d/Makefile\", Path_extension \".bashrc\",\n\
+                   \t Path_basename \"/usr/lib/\")\n";
+        assert_eq!(eval(src).to_string(), "(Some gz, file.tar.gz, None, None, lib)");
+    }
+
+    #[test]
+    fn runtime_errors_point_at_the_faulty_expression() {
+        let src = "divide :: Int -> Int\n\
+                   divide x = 100 / x\n\
+                   \n\
+                   main () =\n\
+                   \tlet a = divide 4\n\
+                   \tdivide (a - 25)\n";
+        match eval_string(src) {
+            // `100 / x` in divide
+            Err(crate::Error::Runtime(e)) => {
+                assert_eq!(format!("{:?}", e.span().unwrap()), "Span [2:11, 2:18]")
+            }
+            r => panic!("expected a runtime error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn dir_read_lists_sorted_names() {
+        let dir = temp_dir("dir_read");
+        std::fs::write(dir.join("b.txt"), "").unwrap();
+        std::fs::write(dir.join("a.ml"), "").unwrap();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+
+        let src = format!("main () = Dir_read {:?}\n", dir.to_str().unwrap());
+        assert_eq!(eval(&src).to_string(), "[a.ml, b.txt, sub]");
+
+        let program = crate::typecheck_source(&src);
+        assert!(interpret_with_capabilities(program, Capabilities::none()).is_err());
+    }
+
+    #[test]
+    fn closures_outlive_the_call_that_made_them() {
+        let src = "add :: Int -> Int -> Int\nadd a b = a + b\n\n\
+                   main () =\n\tlet n = 40\n\t\\x -> add n x\n";
+        let mut interpreter = Interpreter::new(crate::typecheck_source(src));
+        interpreter.call_fn("main").unwrap();
+        let f = interpreter.pop_val().unwrap();
+
+        for (arg, expected) in &[(2, 42), (10, 50)] {
+            let v = interpreter.apply_function(f.clone(), Value::Integer(*arg)).unwrap();
+            assert!(matches!(v, Value::Integer(i) if i == *expected), "{:?}", v);
+        }
+    }
+
+    #[test]
+    fn closures_capture_only_what_they_reference() {
+        let src = "main () =\n\tlet unused = 42\n\tlet used = 1\n\tlet shadowed = 2\n\
+                   \t\\shadowed -> (used, shadowed)\n";
+        match eval(src) {
+            Value::Function(_, captured, _) => {
+                let names: Vec<_> = captured.iter().map(|(name, _)| name.as_str()).collect();
+                assert_eq!(names, vec!["used"]);
+            }
+            v => panic!("expected a closure, got {}", v),
+        }
+    }
+
+    #[test]
+    fn dir_make_creates_missing_parents() {
+        let dir = temp_dir("dir_make");
+        let nested = dir.join("a").join("b");
+
+        // making it a second time is fine too
+        let src = format!(
+            "main () =\n\tlet u = Dir_make {:?}\n\tDir_make {:?}\n",
+            nested.to_str().unwrap(),
+            nested.to_str().unwrap()
+        );
+        eval(&src);
+        assert!(nested.is_dir());
+    }
+
+    #[test]
+    fn operands_keep_their_order() {
+        let src = "main () =\n\
+                   \t(10 - 3 == 7, 10 / 2 == 5, 10 - 3, 10 / 2, 10 % 3, 2 < 3,\n\
+                   \t \"a\" < \"b\", 1 - 2 - 3)\n";
+        assert_eq!(eval(src).to_string(), "(1, 1, 7, 5, 1, 1, 1, -4)");
+    }
+
+    #[test]
+    fn file_delete_removes_the_file() {
+        let path = temp_dir("file_delete").join("doomed.txt");
+        std::fs::write(&path, "bye").unwrap();
+
+        let src = format!(
+            "main () =\n\tlet before = File_exists {0:?}\n\tlet u = File_delete {0:?}\n\
+             \t(before, File_exists {0:?})\n",
+            path.to_str().unwrap()
+        );
+        assert_eq!(eval(&src).to_string(), "(1, 0)");
+        assert!(!path.exists());
+
+        // deleting it again is an io error rather than a panic
+        match eval_err(&format!("main () = File_delete {:?}\n", path.to_str().unwrap())) {
+            RuntimeError::IoError(..) => {}
+            e => panic!("expected an io error, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn refs_count_in_a_loop() {
+        let src = "get_int :: Ref Int -> Int\n\
+                   get_int r = Ref_get r\n\
+                   \n\
+                   add_up :: Ref Int -> Int -> Int\n\
+                   add_up total i =\n\
+                   \tif i == 5 then i else\n\
+                   \tlet u = Ref_set (total, get_int total + i)\n\
+                   \tadd_up total (i + 1)\n\
+                   \n\
+                   main () =\n\
+                   \tlet total = Ref_new 0\n\
+                   \tlet i = add_up total 0\n\
+                   \t(i, get_int total)\n";
+        assert_eq!(eval(src).to_string(), "(5, 10)");
+
+        // copies of a ref share the cell
+        let src = "main () =\n\
+                   \tlet r = Ref_new \"a\"\n\
+                   \tlet alias = r\n\
+                   \tlet u = Ref_set (alias, \"b\")\n\
+                   \t(Ref_get r, r)\n";
+        assert_eq!(eval(src).to_string(), "(b, ref b)");
+    }
+
+    #[test]
+    fn file_copy_and_move() {
+        let dir = temp_dir("file_copy_move");
+        let src_path = dir.join("src.txt");
+        let copy = dir.join("copy.txt");
+        let moved = dir.join("moved.txt");
+        std::fs::write(&src_path, "contents").unwrap();
+        // overwriting an existing destination is allowed
+        std::fs::write(&copy, "old").unwrap();
+
+        let src = format!(
+            "main () =\n\tlet u = File_copy ({:?}, {:?})\n\tFile_move ({:?}, {:?})\n",
+            src_path.to_str().unwrap(),
+            copy.to_str().unwrap(),
+            src_path.to_str().unwrap(),
+            moved.to_str().unwrap()
+        );
+        eval(&src);
+
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "contents");
+        assert_eq!(std::fs::read_to_string(&moved).unwrap(), "contents");
+        assert!(!src_path.exists());
+    }
+
+    #[test]
+    fn while_counts_a_ref_down() {
+        let src = "get_int :: Ref Int -> Int\n\
+                   get_int r = Ref_get r\n\
+                   \n\
+                   step :: Ref Int -> Ref Int -> ()\n\
+                   step r seen =\n\
+                   \tlet _ = Ref_set (seen, get_int seen * 10 + get_int r)\n\
+                   \tRef_set (r, get_int r - 1)\n\
+                   \n\
+                   main () =\n\
+                   \tlet counter = Ref_new 3\n\
+                   \tlet seen = Ref_new 0\n\
+                   \tlet _ = while get_int counter > 0 do step counter seen\n\
+                   \t(get_int counter, get_int seen)\n";
+        // the body ran for 3, 2 and 1
+        assert_eq!(eval(src).to_string(), "(0, 321)");
+    }
+
+    #[test]
+    fn string_rolling_hash() {
+        let src = "main () =\n\
+                   \t(String_rolling_hash (\"abcab\", 2),\n\
+                   \t String_rolling_hash (\"ab\", 3), String_rolling_hash (\"abc\", 3))\n";
+        assert_eq!(eval(src).to_string(), "([25027, 25285, 25540, 25027], [], [6432038])");
+    }
+
+    #[test]
+    fn sequences_keep_the_last_value() {
+        let src = "main () =\n\
+                   \tprint \"one\\n\"; print \"two\\n\"; print \"three\\n\"; 42\n";
+        assert_eq!(eval(src).to_string(), "42");
+
+        // each step runs in order
+        let src = "get_int :: Ref Int -> Int\n\
+                   get_int r = Ref_get r\n\
+                   \n\
+                   main () =\n\
+                   \tlet log = Ref_new 0\n\
+                   \tRef_set (log, get_int log * 10 + 1);\n\
+                   \tRef_set (log, get_int log * 10 + 2);\n\
+                   \tRef_set (log, get_int log * 10 + 3);\n\
+                   \tget_int log\n";
+        assert_eq!(eval(src).to_string(), "123");
+    }
+
+    #[test]
+    fn rle_round_trips() {
+        let src = "main () =\n\
+                   \tlet runs = String_rle_encode \"aaabbc\"\n\
+                   \t(runs, String_rle_decode runs == \"aaabbc\", String_rle_encode \"\")\n";
+        assert_eq!(eval(src).to_string(), "([(a, 3), (b, 2), (c, 1)], 1, [])");
+
+        let v = eval("main () = String_rle_decode [(\"\", 9223372036854775807), (\"x\", 2)]\n");
+        assert_eq!(v.to_string(), "xx");
+
+        // one run too long by itself, and runs only too long together
+        let runs = [
+            "[(\"a\", 9223372036854775807)]",
+            "[(\"ab\", 536870912), (\"c\", 1)]",
+        ];
+        for runs in &runs {
+            match eval_err(&format!("main () = String_rle_decode {}\n", runs)) {
+                RuntimeError::InvalidArgument(BuiltInFn::RleDecode, msg) => {
+                    assert!(msg.ends_with("is too long"), "{}", msg)
+                }
+                e => panic!("expected decoding {} to fail, got {:?}", runs, e),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_int_rejects_non_numbers_cleanly() {
+        match eval_err("main () = String_parse_int \"abc\"\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::StringParseInt, msg) => {
+                assert_eq!(msg, "\"abc\" is not an integer")
+            }
+            e => panic!("expected an invalid argument, got {:?}", e),
+        }
+
+        let src = "main () = (String_try_parse_int \"42\", String_try_parse_int \"abc\")\n";
+        assert_eq!(eval(src).to_string(), "(Some 42, None)");
+    }
+
+    #[test]
+    fn list_stats() {
+        let src = "main () =\n\
+                   \tlet s = List_stats [1, 2, 3, 4]\n\
+                   \t(s.0 == 4, s.1 == 10, s.2 == 1, s.3 == 4, s.4, s)\n";
+        assert_eq!(eval(src).to_string(), "(1, 1, 1, 1, 2.5, (4, 10, 1, 4, 2.5))");
+
+        match eval_err("main () = List_stats []\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::ListStats, _) => {}
+            e => panic!("expected an empty list to fail, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn get_first_on_empty_and_multibyte_strings() {
+        match eval_err("main () = String_get_first \"\"\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::StringGetFirst, _) => {}
+            e => panic!("expected an empty string to fail, got {:?}", e),
+        }
+
+        let src = "main () = (String_get_first \"✓x\", String_try_get_first \"\")\n";
+        assert_eq!(eval(src).to_string(), "((✓, x), None)");
+    }
+
+    #[test]
+    fn parse_float_and_float_literals() {
+        let src = "main () = (String_parse_float \"3.14\", String_parse_float \"2\", 3.14)\n";
+        assert_eq!(eval(src).to_string(), "(3.14, 2.0, 3.14)");
+
+        match eval_err("main () = String_parse_float \"3.1.4\"\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::StringParseFloat, _) => {}
+            e => panic!("expected an invalid float to fail, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn mod_is_euclidean() {
+        let src = "main () = (-1 % 5, -7 % 3, 7 % -3, -7 % -3, 7 % 3)\n";
+        assert_eq!(eval(src).to_string(), "(4, 2, 1, 2, 1)");
+
+        match eval_err("main () = 1 % 0\n") {
+            RuntimeError::ArithmeticError(_) => {}
+            e => panic!("expected a division by zero, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn integer_pow() {
+        assert_eq!(eval_int("main () = 2 ^ 10\n"), 1024);
+        assert_eq!(eval_int("main () = 7 ^ 0\n"), 1);
+
+        match eval_err("main () = 2 ^ 63\n") {
+            RuntimeError::ArithmeticError(_) => {}
+            e => panic!("expected an overflow, got {:?}", e),
+        }
+        match eval_err("main () = 2 ^ (0 - 1)\n") {
+            RuntimeError::ArithmeticError(_) => {}
+            e => panic!("expected a negative exponent error, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn float_operators() {
+        let v = eval("main () = (2.0 ^ 10.0, 4.0 ^ 0.5, 1.5 + 2.0, 1.0 - 0.5, 3.0 * 0.5)\n");
+        assert_eq!(v.to_string(), "(1024.0, 2.0, 3.5, 0.5, 1.5)");
+        assert_eq!(eval("main () = 1.0 / 4.0\n").to_string(), "0.25");
+
+        let v = eval("main () = (1.5 < 2.0, 2.0 <= 2.0, 1.5 > 2.0, 2.5 >= 3.0, 0.5 == 0.5)\n");
+        assert_eq!(v.to_string(), "(1, 1, 0, 0, 1)");
+
+        let v = eval("main () = Float_lerp (0.0, 10.0, 0.25) * 2.0 < 5.5\n");
+        assert_eq!(v.to_string(), "1");
+    }
+
+    #[test]
+    fn shifts() {
+        let v = eval("main () = (1 << 4, 48 >> 4, 0 - 16 >> 2, 1 << 63)\n");
+        assert_eq!(v.to_string(), format!("(16, 3, -4, {})", i64::MIN));
+
+        for amount in &["64", "100", "(0 - 1)"] {
+            match eval_err(&format!("main () = 1 << {}\n", amount)) {
+                RuntimeError::ArithmeticError(_) => {}
+                e => panic!("expected shifting by {} to fail, got {:?}", amount, e),
+            }
+        }
+    }
+
+    #[test]
+    fn structural_equality_of_tuples_and_records() {
+        let src = "type Point = { x: Int, y: Int }\n\
+                   \n\
+                   point :: Int -> Point\n\
+                   point y = { x: 0, y: y }\n\
+                   \n\
+                   main () =\n\
+                   \tlet a = (1, (\"x\", 2))\n\
+                   \t(a == (1, (\"x\", 2)), a == (1, (\"x\", 3)), [1, 2] == [1, 2, 3],\n\
+                   \t point 1 == point 1, point 1 == point 2)\n";
+        assert_eq!(eval(src).to_string(), "(1, 0, 0, 1, 0)");
+    }
+
+    #[test]
+    fn structural_equality_of_variants() {
+        let src = "main () =\n\
+                   \t(Option.Some 1 == Option.Some 1, Option.Some 1 == Option.None,\n\
+                   \t Option.Some (1, \"a\") == Option.Some (1, \"b\"),\n\
+                   \t Option.None == Option.None)\n";
+        assert_eq!(eval(src).to_string(), "(1, 0, 0, 1)");
+    }
+
+    #[test]
+    fn map_insert_overwrite_and_get_missing() {
+        let src = "main () =\n\
+                   \tlet m = Map_new ()\n\
+                   \tMap_insert (m, \"a\", 1);\n\
+                   \tMap_insert (m, \"b\", 2);\n\
+                   \tMap_insert (m, \"a\", 3);\n\
+                   \tlet removed = Map_remove (m, \"b\")\n\
+                   \t(Map_get (m, \"a\"), Map_get (m, \"missing\"), removed, m)\n";
+        assert_eq!(eval(src).to_string(), "(Some 3, None, Some 2, map { a: 3 })");
+
+        let src = "main () =\n\
+                   \tlet m = Map_new ()\n\
+                   \tMap_insert (m, \\x -> x, 1)\n";
+        match eval_err(src) {
+            RuntimeError::InvalidArgument(BuiltInFn::MapInsert, _) => {}
+            e => panic!("expected a function key to be rejected, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn memoize_saves_calls() {
+        let src = "get_int :: Ref Int -> Int\n\
+                   get_int r = Ref_get r\n\
+                   \n\
+                   counted :: Ref Int -> Int -> Int\n\
+                   counted calls n =\n\
+                   \tRef_set (calls, get_int calls + 1);\n\
+                   \tn * n\n\
+                   \n\
+                   main () =\n\
+                   \tlet plain_calls = Ref_new 0\n\
+                   \tlet memo_calls = Ref_new 0\n\
+                   \tlet plain = counted plain_calls\n\
+                   \tlet memo = memoize (counted memo_calls)\n\
+                   \tlet _ = List_map (plain, [1, 2, 1, 2, 1])\n\
+                   \tlet results = List_map (memo, [1, 2, 1, 2, 1])\n\
+                   \t(results, get_int plain_calls, get_int memo_calls)\n";
+        assert_eq!(eval(src).to_string(), "([1, 4, 1, 4, 1], 5, 2)");
+
+        // only hashable arguments can be memoized
+        let src = "apply :: (Int -> Int) -> Int\napply f = f 1\n\n\
+                   id :: Int -> Int\nid x = x\n\n\
+                   main () =\n\tlet m = memoize apply\n\tm id\n";
+        match eval_err(src) {
+            RuntimeError::InvalidArgument(BuiltInFn::Memoize, _) => {}
+            e => panic!("expected a function argument to be rejected, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn trace_sees_every_expression_in_order() {
+        let kinds = Rc::new(RefCell::new(Vec::new()));
+        let seen = kinds.clone();
+        let record = move |(expr, _): &TypedExpr, _: &[Value]| {
+            seen.borrow_mut().push(expr.kind_name());
+        };
+        let src = "double :: Int -> Int\ndouble x = x * 2\n\nmain () = double (1 + 2)\n";
+        let result = interpret_with_trace(crate::typecheck_source(src), box record);
+
+        assert_eq!(result.unwrap().to_string(), "6");
+        assert_eq!(
+            *kinds.borrow(),
+            vec![
+                "Application",
+                "Symbol",
+                "BinaryOp",
+                "IntegerLiteral",
+                "IntegerLiteral",
+                "BinaryOp",
+                "Symbol",
+                "IntegerLiteral",
+            ]
+        );
+    }
+
+    #[test]
+    fn breakpoint_hook_fires_at_its_span() {
+        use crate::parser::{Position, Span};
+
+        let src = "double :: Int -> Int\ndouble x = x * 2\n\nmain () = double 1 + double 2\n";
+        // `x * 2` in the body of double
+        let body = Span(Position(2, 11), Position(2, 16));
+        let breakpoints = Breakpoints::default();
+        breakpoints.add(body);
+        // a span no expression has never fires
+        breakpoints.add(Span(Position(3, 0), Position(3, 1)));
+
+        let hits = Rc::new(RefCell::new(Vec::new()));
+        let seen = hits.clone();
+        let hook = move |span: Span, bindings: &[(String, Value)], _: &[Value]| {
+            seen.borrow_mut().push((span, format!("{:?}", bindings)));
+        };
+        let result =
+            interpret_with_breakpoints(crate::typecheck_source(src), breakpoints.clone(), box hook);
+
+        assert_eq!(result.unwrap().to_string(), "6");
+        assert_eq!(
+            *hits.borrow(),
+            vec![
+                (body, format!("{:?}", [("x".to_owned(), Value::Integer(1))])),
+                (body, format!("{:?}", [("x".to_owned(), Value::Integer(2))])),
+            ]
+        );
+
+        // removing the breakpoint from the hook stops it firing for the second call
+        let hits = Rc::new(RefCell::new(0));
+        let seen = hits.clone();
+        let removing = breakpoints.clone();
+        let hook = move |span: Span, _: &[(String, Value)], _: &[Value]| {
+            *seen.borrow_mut() += 1;
+            removing.remove(span);
+        };
+        let result =
+            interpret_with_breakpoints(crate::typecheck_source(src), breakpoints.clone(), box hook);
+
+        assert_eq!(result.unwrap().to_string(), "6");
+        assert_eq!(*hits.borrow(), 1);
+        assert!(!breakpoints.contains(body));
+    }
+
+    #[test]
+    fn runtime_errors_carry_the_calls_that_led_to_them() {
+        let src = "inner :: Int -> Int\n\
+                   inner x = 10 / x\n\
+                   \n\
+                   middle :: Int -> Int\n\
+                   middle x = inner (x - 1) + 1\n\
+                   \n\
+                   outer :: Int -> Int\n\
+                   outer x = middle x * 2\n\
+                   \n\
+                   main () = outer 1\n";
+        let (frames, error) = match eval_string(src) {
+            Err(crate::Error::Runtime(RuntimeError::Traced(frames, error))) => (frames, error),
+            r => panic!("expected a traced error, got {:?}", r),
+        };
+        let names: Vec<_> = frames.iter().map(|f| f.function.as_str()).collect();
+
+        assert_eq!(names, ["main", "outer", "middle", "inner"]);
+        assert!(frames[0].call_site.is_none());
+        assert!(frames[1..].iter().all(|f| f.call_site.is_some()));
+        assert_eq!(error.to_string(), "2:10: arithmetic error: division by zero");
+    }
+
+    #[test]
+    fn calls_reuse_the_binding_stack() {
+        // many locals in scope at every one of many calls, each of which used
+        // to copy all of them
+        let locals: String = (0..50).map(|i| format!("\tlet local{} = {}\n", i, i)).collect();
+        let src = format!(
+            "get_int :: Ref Int -> Int\nget_int r = Ref_get r\n\n\
+             step :: Ref Int -> ()\nstep r = Ref_set (r, get_int r - 1)\n\n\
+             main () =\n{}\tlet counter = Ref_new 10000\n\
+             \tlet _ = while get_int counter > 0 do step counter\n\tlocal49\n",
+            locals
+        );
+        let mut interpreter = Interpreter::new(crate::typecheck_source(&src));
+        let result = interpreter.run(|i| i.call_fn("main"));
+
+        assert_eq!(result.unwrap().to_string(), "49");
+        // every call pushed its bindings onto the one stack and truncated them
+        // again, so it never held much more than what was in scope at once
+        assert!(interpreter.bindings.is_empty());
+        assert!(interpreter.frames.is_empty());
+        assert!(interpreter.bindings.capacity() < 128);
+    }
+
+    #[test]
+    fn format_fills_placeholders_in_order() {
+        let src = "main () =\n\
+                   \t(format (\"plain {{braces}}\", ()), format (\"x = {}\", 5),\n\
+                   \t format (\"{} + {} = {} {}\", (1, 2, 3, ())),\n\
+                   \t format (\"{}\", \"s\"))\n";
+        assert_eq!(eval(src).to_string(), "(plain {braces}, x = 5, 1 + 2 = 3 (), s)");
+
+        match eval_err("main () = format (\"{} {}\", 1)\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::Format, msg) => {
+                assert_eq!(msg, "more placeholders than the 1 values given")
+            }
+            e => panic!("expected too few values to be rejected, got {:?}", e),
+        }
+        match eval_err("main () = format (\"{}\", (1, 2))\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::Format, msg) => {
+                assert_eq!(msg, "1 placeholders but 2 values given")
+            }
+            e => panic!("expected too many values to be rejected, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn assert_passes_or_fails_with_its_message() {
+        let src = "main () = assert (1 + 1 == 2); assert (true, \"never shown\"); 1\n";
+        assert_eq!(eval_int(src), 1);
+
+        let src = "main () = assert (1 == 1); assert (2 < 1, \"two is not less than one\")\n";
+        match eval_err(src) {
+            RuntimeError::AssertionFailed(Some(msg)) => assert_eq!(msg, "two is not less than one"),
+            e => panic!("expected a failed assertion, got {:?}", e),
+        }
+        match eval_err("main () = assert false\n") {
+            RuntimeError::AssertionFailed(None) => {}
+            e => panic!("expected a failed assertion, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn exit_stops_with_its_code() {
+        let src = "main () = if 3 > 2 then exit 3 else 0\n";
+        assert!(matches!(eval_string(src), Err(crate::Error::Runtime(RuntimeError::Exit(3)))));
+
+        // from inside another call, without any frames or location attached
+        let src = "stop :: Int -> Int\nstop n = exit (n * 2)\nmain () = (1, stop 21)\n";
+        assert!(matches!(eval_string(src), Err(crate::Error::Runtime(RuntimeError::Exit(42)))));
+    }
+
+    #[test]
+    fn exit_code_comes_from_main_or_exit() {
+        let code = |src: &str| exit_code(&interpret(crate::typecheck_source(src)));
+
+        assert_eq!(code("main () = 1\n"), 1);
+        assert_eq!(code("main () = 0\n"), 0);
+        assert_eq!(code("main () = ()\n"), 0);
+        assert_eq!(code("main () = \"done\"\n"), 0);
+        assert_eq!(code("main () = exit 3; 0\n"), 3);
+        assert_eq!(code("main () = 1 / 0\n"), 1);
+    }
+
+    #[test]
+    fn variants_carry_several_fields_or_none() {
+        let src = "type Shape =\n\
+                   \t| Rect of (Int, Int)\n\
+                   \t| Circle of Int\n\
+                   \t| Empty\n\
+                   \n\
+                   area :: Shape -> Int\n\
+                   area s = match s with\n\
+                   \t| Rect (w, h) -> w * h\n\
+                   \t| Circle r -> 3 * r * r\n\
+                   \t| Empty -> 0\n\
+                   \n\
+                   pair :: Shape -> (Int, Int)\n\
+                   pair s = match s with\n\
+                   \t| Rect p -> p\n\
+                   \t| _ -> (0, 0)\n\
+                   \n\
+                   main () =\n\
+                   \t(area (Shape.Rect (2, 3)), area (Shape.Circle 2), area Shape.Empty,\n\
+                   \t pair (Shape.Rect (4, 5)), Shape.Rect (1, 2), Shape.Empty,\n\
+                   \t Shape.Rect (1, 2) == Shape.Rect (1, 2),\n\
+                   \t Shape.Empty == Shape.Empty, Option.Some (1, 2))\n";
+        assert_eq!(
+            eval(src).to_string(),
+            "(6, 12, 0, (4, 5), Rect (1, 2), Empty, 1, 1, Some (1, 2))"
+        );
+
+        // the fields are stored directly rather than as one tuple or unit
+        let values = match eval(src) {
+            Value::Tuple(values) => values,
+            v => panic!("expected a tuple, got {}", v),
+        };
+        match (&values[4], &values[5]) {
+            (Value::Variant(_, _, rect), Value::Variant(_, _, empty)) => {
+                assert_eq!(rect.len(), 2);
+                assert!(empty.is_empty());
+            }
+            vs => panic!("expected two variants, got {:?}", vs),
+        }
+    }
+
+    #[test]
+    fn char_at_indexes_by_character() {
+        let src = "main () =\n\
+                   \t(String_char_at (\"hello\", 0), String_char_at (\"hello\", 4),\n\
+                   \t String_char_at (\"héllo wörld\", 7), String_char_at (\"日本語\", 2))\n";
+        assert_eq!(eval(src).to_string(), "(h, o, ö, 語)");
+
+        match eval_err("main () = String_char_at (\"日本語\", 3)\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::StringCharAt, msg) => {
+                assert_eq!(msg, "index 3 out of bounds for a string of 3 chars")
+            }
+            e => panic!("expected the index to be out of bounds, got {:?}", e),
+        }
+        match eval_err("main () = String_char_at (\"abc\", 0 - 1)\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::StringCharAt, msg) => {
+                assert_eq!(msg, "index -1 out of bounds for a string of 3 chars")
+            }
+            e => panic!("expected the index to be out of bounds, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn to_chars_round_trips() {
+        let src = "main () =\n\
+                   \t(String_to_chars \"abc\", String_to_chars \"\",\n\
+                   \t String_to_chars \"aé日\",\n\
+                   \t List_map (Char_to_string, String_to_chars \"héllo\"))\n";
+        assert_eq!(eval(src).to_string(), "([a, b, c], [], [a, é, 日], [h, é, l, l, o])");
+
+        // joining the characters back up gives the string it started as
+        let src = "join :: String -> Char -> String\n\
+                   join s c = format (\"{}{}\", (s, c))\n\
+                   \n\
+                   round_trip :: String -> String\n\
+                   round_trip s = List_fold (join, \"\", String_to_chars s)\n\
+                   \n\
+                   main () =\n\
+                   \tlet texts = [\"abc\", \"\", \"héllo wörld\", \"日本語😀\"]\n\
+                   \tList_map (round_trip, texts) == texts\n";
+        assert_eq!(eval(src).to_string(), "1");
+    }
+
+    #[test]
+    fn int_abs_min_max() {
+        let src = "main () =\n\
+                   \t(Int_abs (0 - 5), Int_abs 7, Int_abs 0, Int_min (3, 0 - 2),\n\
+                   \t Int_max (3, 0 - 2), Int_min (4, 4),\n\
+                   \t Int_abs (0 - 9223372036854775807))\n";
+        assert_eq!(eval(src).to_string(), "(5, 7, 0, -2, 3, 4, 9223372036854775807)");
+
+        match eval_err("main () = Int_abs (0 - 9223372036854775807 - 1)\n") {
+            RuntimeError::ArithmeticError(msg) => {
+                assert_eq!(msg, "abs of -9223372036854775808 overflows")
+            }
+            e => panic!("expected abs of the minimum to overflow, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn float_math_builtins() {
+        let src = "main () =\n\
+                   \t(Float_sqrt 16.0, Float_sqrt 2.0, Float_sqrt (-1.0),\n\
+                   \t Float_floor 2.7, Float_floor (-2.5), Float_ceil 2.1,\n\
+                   \t Float_ceil (-2.5), Float_round 2.5, Float_round (-2.5),\n\
+                   \t Float_round 2.4)\n";
+        assert_eq!(
+            eval(src).to_string(),
+            "(4.0, 1.4142135623730951, NaN, 2.0, -3.0, 3.0, -2.0, 3.0, -3.0, 2.0)"
+        );
+    }
+
+    #[test]
+    fn partial_application_and_too_many_arguments() {
+        let src = "add :: Int -> Int -> Int\n\
+                   add a b = a + b\n\
+                   \n\
+                   add3 :: Int -> Int -> Int -> Int\n\
+                   add3 a b c = a + b + c\n\
+                   \n\
+                   twice :: (Int -> Int) -> Int -> Int\n\
+                   twice f x = f (f x)\n\
+                   \n\
+                   main () =\n\
+                   \tlet inc = add 1\n\
+                   \tlet add5 = add3 2 3\n\
+                   \t(inc 41, twice (add 3) 0, add5 10, List_map (add 10, [1, 2]),\n\
+                   \t (add3 1) 2 3)\n";
+        assert_eq!(eval(src).to_string(), "(42, 6, 15, [11, 12], 6)");
+
+        let src = "add :: Int -> Int -> Int\nadd a b = a + b\n\nmain () = add 1 2 3\n";
+        match eval_string(src) {
+            Err(crate::Error::TypeChecking(errs)) => match errs.as_slice() {
+                [crate::typecheck::TypeCheckingError::GenericError(msg, _)] => {
+                    assert_eq!(msg, "too many arguments in function application")
+                }
+                errs => panic!("expected one arity error, got {:?}", errs),
+            },
+            r => panic!("expected over-application to be rejected, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn matching_on_a_map_get_miss() {
+        let src = "lookup :: Map String Int -> String -> Int\n\
+                   lookup m k = match Map_get (m, k) with\n\
+                   \t| Some v -> v\n\
+                   \t| None -> 0 - 1\n\
+                   \n\
+                   main () =\n\
+                   \tlet m = Map_new ()\n\
+                   \tlet _ = Map_insert (m, \"a\", 1)\n\
+                   \t(lookup m \"a\", lookup m \"missing\", Some 3 == Option.Some 3,\n\
+                   \t None == Option.None, Some \"x\")\n";
+        assert_eq!(eval(src).to_string(), "(1, -1, 1, 1, Some x)");
+    }
+
+    #[test]
+    fn try_passes_ok_through_and_returns_err() {
+        let src = "parse :: String -> Result Int String\n\
+                   parse s = match String_try_parse_int s with\n\
+                   \t| Some i -> Ok i\n\
+                   \t| None -> Err (format (\"not a number: {}\", s))\n\
+                   \n\
+                   sum :: String -> String -> Result Int String\n\
+                   sum a b = Ok (parse a? + parse b?)\n\
+                   \n\
+                   half :: Int -> Result Int String\n\
+                   half n = if n % 2 == 0 then Ok (n / 2) else Err \"odd\"\n\
+                   \n\
+                   quarter :: Int -> Result Int String\n\
+                   quarter n =\n\
+                   \tlet h = half n?\n\
+                   \thalf h\n\
+                   \n\
+                   main () =\n\
+                   \t(sum \"1\" \"2\", sum \"1\" \"x\", sum \"y\" \"x\", quarter 8,\n\
+                   \t quarter 6, quarter 3, Result.Ok 1 == Ok 1)\n";
+        assert_eq!(
+            eval(src).to_string(),
+            "(Ok 3, Err not a number: x, Err not a number: y, Ok 2, Err odd, Err odd, 1)"
+        );
+    }
+
+    #[test]
+    fn file_read_lines_splits_on_newlines() {
+        let dir = temp_dir("read_lines");
+        std::fs::write(dir.join("lines.txt"), "first line\nsecond\r\n\nlast\n").unwrap();
+        std::fs::write(dir.join("one_line.txt"), "no trailing newline").unwrap();
+        std::fs::write(dir.join("empty.txt"), "").unwrap();
+
+        let read = |name: &str| {
+            let path = dir.join(name);
+            eval(&format!("main () = File_read_lines {:?}\n", path.to_str().unwrap())).to_string()
+        };
+        assert_eq!(read("lines.txt"), "[first line, second, , last]");
+        assert_eq!(read("one_line.txt"), "[no trailing newline]");
+        assert_eq!(read("empty.txt"), "[]");
+
+        let path = dir.join("missing.txt");
+        match eval_err(&format!("main () = File_read_lines {:?}\n", path.to_str().unwrap())) {
+            RuntimeError::IoError(..) => {}
+            e => panic!("expected reading a missing file to fail, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn unit_equals_unit() {
+        let src = "main () = (() == (), (1, ()) == (1, ()), Some () == Some ())\n";
+        assert_eq!(eval(src).to_string(), "(1, 1, 1)");
+    }
+
+    #[test]
+    fn fuel_halts_an_endless_loop() {
+        let src = "main () =\n\tlet _ = while true do ()\n\t0\n";
+        match interpret_with_fuel(crate::typecheck_source(src), 10_000).map_err(innermost) {
+            Err(RuntimeError::OutOfFuel) => {}
+            r => panic!("expected to run out of fuel, got {:?}", r),
+        }
+
+        // a program that finishes within its budget isn't affected
+        let src = "main () = 1 + 2\n";
+        let result = interpret_with_fuel(crate::typecheck_source(src), 10);
+        assert_eq!(result.unwrap().to_string(), "3");
+    }
+
+    #[test]
+    fn panics_become_internal_errors() {
+        // stands in for any case evaluation still panics on
+        let mut interpreter = Interpreter::new(crate::typecheck_source("main () = 0\n"));
+        let result = interpreter.run(|_| unimplemented!("evaluating {}", "something new"));
+
+        match result.map_err(innermost) {
+            Err(RuntimeError::InternalError(msg)) => {
+                assert_eq!(msg, "not implemented: evaluating something new")
+            }
+            r => panic!("expected the panic to be caught, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn functions_display_concisely() {
+        let src = "add :: Int -> Int -> Int\n\
+                   add a b = a + b\n\
+                   \n\
+                   main () = (add 1, List_map, \\x -> x)\n";
+        assert_eq!(eval(src).to_string(), "(<function/b>, <builtin:ListMap>, <function/x>)");
+        // without the captured bindings in debug output either
+        assert_eq!(
+            format!("{:?}", eval(src)),
+            "Tuple([<function/b>, <builtin:ListMap>, <function/x>])"
+        );
+    }
+
+    #[test]
+    fn deep_copy_detaches_refs_and_maps() {
+        let src = "main () =\n\
+                   \tlet r = Ref_new 1\n\
+                   \tlet m = Map_new ()\n\
+                   \tMap_insert (m, \"a\", 1);\n\
+                   \tlet state = (r, m, (Ref_new \"x\", 5))\n\
+                   \tlet snapshot = deep_copy state\n\
+                   \tlet shallow = state\n\
+                   \tRef_set (r, 2);\n\
+                   \tMap_insert (m, \"b\", 2);\n\
+                   \tlet inner = state.2\n\
+                   \tRef_set (inner.0, \"y\");\n\
+                   \t(state, shallow, snapshot)\n";
+        assert_eq!(
+            eval(src).to_string(),
+            "((ref 2, map { a: 1, b: 2 }, (ref y, 5)), (ref 2, map { a: 1, b: 2 }, (ref y, 5)), \
+             (ref 1, map { a: 1 }, (ref x, 5)))"
+        );
+    }
+
+    #[test]
+    fn equals_compares_every_kind() {
+        let src = "type Shape =\n\
+                   \t| Circle of Int\n\
+                   \t| Rect of (Int, Int)\n\
+                   \n\
+                   type Point = { x: Int, y: Int }\n\
+                   \n\
+                   point :: Int -> Point\n\
+                   point y = { x: 1, y: y }\n\
+                   \n\
+                   int_ref :: Int -> Ref Int\n\
+                   int_ref i = Ref_new i\n\
+                   \n\
+                   main () =\n\
+                   \t(equals (1, 1), equals (1, 2), equals (\"a\", \"a\"),\n\
+                   \t equals (true, false), equals ((), ()),\n\
+                   \t equals ((1, \"x\"), (1, \"x\")), equals (point 2, point 3),\n\
+                   \t equals (Shape.Rect (1, 2), Shape.Rect (1, 2)),\n\
+                   \t equals (Shape.Circle 1, Shape.Rect (1, 1)),\n\
+                   \t equals (int_ref 3, int_ref 3))\n";
+        assert_eq!(eval(src).to_string(), "(1, 0, 1, 0, 1, 1, 0, 1, 0, 1)");
+        let v = eval_mistyped("main () = equals (one, \"a\")\n").unwrap();
+        assert!(matches!(v, Value::Integer(0)), "{:?}", v);
+
+        let src = "inc :: Int -> Int\ninc x = x + 1\n\nmain () = equals (inc, inc)\n";
+        match eval_err(src) {
+            RuntimeError::TypeMismatch(msg) => {
+                assert_eq!(msg, "cannot compare <function/x> and <function/x> for equality")
+            }
+            e => panic!("expected functions to be incomparable, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn timeout_stops_a_tight_loop() {
+        let src = "main () =\n\tlet _ = while true do ()\n\t0\n";
+        let started = Instant::now();
+        let timeout = Duration::from_millis(50);
+        let result = interpret_with_timeout(crate::typecheck_source(src), timeout);
+
+        match result.map_err(innermost) {
+            Err(RuntimeError::Timeout) => {}
+            r => panic!("expected to time out, got {:?}", r),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let src = "main () = 1 + 2\n";
+        let result = interpret_with_timeout(crate::typecheck_source(src), Duration::from_secs(5));
+        assert_eq!(result.unwrap().to_string(), "3");
+    }
+
+    #[test]
+    fn match_arm_destructures_a_triple() {
+        let src = "main () =\n\tlet triple = (1, \"two\", 3)\n\tmatch triple with\n\
+                   \t| (a, b, c) -> (c, b, a)\n";
+        assert_eq!(eval(src).to_string(), "(3, two, 1)");
+
+        // the type of the match comes from its first arm
+        let src = "main () =\n\tmatch Some 1 with\n\t| Some x -> x\n\t| None -> 0\n";
+        assert_eq!(eval_int(src), 1);
+    }
+
+    #[test]
+    fn run_function_applies_its_arguments() {
+        let src = "clamp :: Int -> Int -> Int\nclamp limit x = if x > limit then limit else x\n\n\
+                   answer = 42\n\nmain () = 0\n";
+        let run = |name: &str, args: Vec<Value>| {
+            run_function(crate::typecheck_source(src), name, args).map_err(innermost)
+        };
+
+        let v = run("clamp", vec![Value::Integer(10), Value::Integer(25)]);
+        assert_eq!(v.unwrap().to_string(), "10");
+        // fewer arguments leave a function waiting for the rest
+        let v = run("clamp", vec![Value::Integer(10)]);
+        assert_eq!(v.unwrap().to_string(), "<function/x>");
+
+        let args = vec![Value::Integer(1), Value::Integer(2), Value::Integer(3)];
+        match run("clamp", args) {
+            Err(RuntimeError::TooManyArguments(name, 2)) => assert_eq!(name, "clamp"),
+            r => panic!("expected too many arguments, got {:?}", r),
+        }
+        match run("answer", vec![]) {
+            Err(RuntimeError::NotAFunction(name)) => assert_eq!(name, "answer"),
+            r => panic!("expected answer not to be callable, got {:?}", r),
+        }
+        match run("clampp", vec![]) {
+            Err(RuntimeError::UnboundSymbol(name, similar)) => {
+                assert_eq!((name.as_str(), similar.as_deref()), ("clampp", Some("clamp")))
+            }
+            r => panic!("expected clampp to be unbound, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn type_of_names_each_kind() {
+        let src = "type Point = { x: Int, y: Int }\n\
+                   \n\
+                   origin :: () -> Point\n\
+                   origin u = { x: 0, y: 0 }\n\
+                   \n\
+                   main () =\n\
+                   \t[type_of (), type_of 1, type_of 1.5, type_of \"s\", type_of (1, 2),\n\
+                   \t type_of [1], type_of (origin ()), type_of (Some 1), type_of List_map,\n\
+                   \t type_of (Ref_new 1), type_of (Map_new ()), type_of true]\n";
+        assert_eq!(
+            eval(src).to_string(),
+            "[unit, integer, float, string, tuple, list, record, variant Option, function, ref, \
+             map, integer]"
+        );
+    }
+
+    #[test]
+    fn index_of_counts_characters() {
+        let src = "main () =\n\
+                   \t(String_index_of (\"hello world\", \"world\"),\n\
+                   \t String_index_of (\"hello\", \"xyz\"),\n\
+                   \t String_index_of (\"abc\", \"\"),\n\
+                   \t String_index_of (\"äöü!\", \"!\"))\n";
+        // the last is a character index, as a byte index it would be 6
+        assert_eq!(eval(src).to_string(), "(Some 6, None, Some 0, Some 3)");
+    }
+
+    #[test]
+    fn substring_by_character_range() {
+        let src = "main () =\n\
+                   \t(String_substring (\"hello world\", 6, 11),\n\
+                   \t String_substring (\"äöü\", 0, 3),\n\
+                   \t String_substring (\"äöü\", 1, 2),\n\
+                   \t String_substring (\"abc\", 2, 2))\n";
+        assert_eq!(eval(src).to_string(), "(world, äöü, ö, )");
+
+        for (range, msg) in &[
+            ("1, 4", "range 1..4 is invalid for a string of 3 chars"),
+            ("2, 1", "range 2..1 is invalid for a string of 3 chars"),
+        ] {
+            match eval_err(&format!("main () = String_substring (\"abc\", {})\n", range)) {
+                RuntimeError::InvalidArgument(BuiltInFn::Substring, m) => assert_eq!(m, *msg),
+                e => panic!("expected {} to be rejected, got {:?}", range, e),
+            }
+        }
+    }
+
+    #[test]
+    fn config_sets_seed_output_and_fuel_together() {
+        let printed = Rc::new(RefCell::new(String::new()));
+        let sink = printed.clone();
+        let config = InterpreterConfig {
+            seed: 7,
+            fuel: Some(1000),
+            output: Some(box move |s: &str| sink.borrow_mut().push_str(s)),
+            ..InterpreterConfig::default()
+        };
+        let program = crate::typecheck_source("main () = print (uuid ()); print \"\\n\"; 1\n");
+        let mut interpreter = Interpreter::with_config(program, config);
+        let result = interpreter.run(|i| i.call_fn("main"));
+
+        assert_eq!(result.unwrap().to_string(), "1");
+        assert_eq!(*printed.borrow(), "63cbe1e4-5932-4dd7-844c-3cd7f43c661c\n");
+        assert!(matches!(interpreter.fuel, Some(left) if left < 1000));
+    }
+
+    #[test]
+    fn inner_let_restores_the_shadowed_binding() {
+        let src = "main () =\n\
+                   \tlet x = 1\n\
+                   \tlet inner = (let x = 2\n\
+                   \tx * 10)\n\
+                   \t(inner, x)\n";
+        assert_eq!(eval(src).to_string(), "(20, 1)");
+
+        // a local can shadow a top level function too
+        let src = "double :: Int -> Int\ndouble x = x * 2\n\n\
+                   main () =\n\tlet a = double 2\n\tlet double = 5\n\t(a, double + 1)\n";
+        assert_eq!(eval(src).to_string(), "(4, 6)");
+    }
+
+    #[test]
+    fn match_arm_restores_the_shadowed_binding() {
+        let src = "pick :: Option Int -> Int -> (Int, Int)\n\
+                   pick o x =\n\
+                   \tlet from_arm = (match o with\n\
+                   \t| Some x -> x\n\
+                   \t| None -> 0)\n\
+                   \t(from_arm, x)\n\
+                   \n\
+                   main () = (pick (Some 5) 1, pick None 2)\n";
+        assert_eq!(eval(src).to_string(), "((5, 1), (0, 2))");
+    }
+
+    #[test]
+    fn restoring_a_snapshot_undoes_later_mutation() {
+        let src = "get_int :: Ref Int -> Int\nget_int r = Ref_get r\n\n\
+                   make :: () -> Ref Int\nmake u = Ref_new 1\n\n\
+                   bump :: Ref Int -> ()\nbump r = Ref_set (r, get_int r + 1)\n\nmain () = 0\n";
+        let program = crate::typecheck_source(src);
+        let mut interpreter = Interpreter::new(program);
+
+        interpreter.call_with_args("make", vec![Value::Unit]).unwrap();
+        let snapshot = interpreter.snapshot();
+        let r = interpreter.stack.last().cloned().unwrap();
+        interpreter.call_with_args("bump", vec![r.clone()]).unwrap();
+        interpreter.call_with_args("bump", vec![r.clone()]).unwrap();
+        assert_eq!(r.to_string(), "ref 3");
+
+        // restoring copies the snapshot again, so it can be done more than once
+        for _ in 0..2 {
+            interpreter.restore(&snapshot).unwrap();
+            let restored = interpreter.stack.last().cloned().unwrap();
+            assert_eq!(interpreter.stack.len(), 1);
+            assert_eq!(restored.to_string(), "ref 1");
+            interpreter.call_with_args("bump", vec![restored]).unwrap();
+        }
+
+        let other = Interpreter::new(crate::typecheck_source(src)).snapshot();
+        assert!(interpreter.restore(&other).is_err());
+    }
+
+    #[test]
+    fn tuples_and_lists_round_trip() {
+        let src = "main () =\n\
+                   \tlet l = Tuple_to_list (1, 2)\n\
+                   \tlet halves = Tuple_to_list (String_split (\"a,b\", \",\"))\n\
+                   \t(l, List_to_tuple l, Tuple_to_list (List_to_tuple [4, 5]), halves)\n";
+        assert_eq!(eval(src).to_string(), "([1, 2], (1, 2), [4, 5], [a, b])");
+
+        // only a pair of elements of the same type fits
+        for arg in &["(1, 2, 3)", "(1, \"a\")"] {
+            let v = eval_string(&format!("main () = Tuple_to_list {}\n", arg));
+            assert!(matches!(v, Err(crate::Error::TypeChecking(_))), "{:?}", v);
+        }
+
+        for list in &["[]", "[1, 2, 3]"] {
+            match eval_err(&format!("main () = List_to_tuple {}\n", list)) {
+                RuntimeError::InvalidArgument(BuiltInFn::ListToTuple, msg) => {
+                    assert!(msg.starts_with("a pair needs 2 elements"), "{}", msg)
+                }
+                e => panic!("expected {} to be rejected, got {:?}", list, e),
+            }
+        }
+    }
+
+    #[test]
+    fn list_length_get_and_append() {
+        let src = "main () =\n\
+                   \tlet l = [\"a\", \"b\", \"c\"]\n\
+                   \t(List_length l, List_length [], List_get (l, 0), List_get (l, 2),\n\
+                   \t List_get (l, 3), List_get (l, -1), List_append (l, \"d\"))\n";
+        assert_eq!(eval(src).to_string(), "(3, 0, Some a, Some c, None, None, [a, b, c, d])");
+    }
+
+    #[test]
+    fn range_fold_threads_the_accumulator() {
+        let src = "add :: Int -> Int -> Int\n\
+                   add acc i = acc + i\n\
+                   \n\
+                   count :: Int -> Int -> Int\n\
+                   count acc i = acc + 1\n\
+                   \n\
+                   main () =\n\
+                   \t(Range_fold (1, 101, 0, add), Range_fold (5, 5, 7, add),\n\
+                   \t Range_fold (3, 1, 0, count), Range_fold (0, 100000, 0, count))\n";
+        assert_eq!(eval(src).to_string(), "(5050, 7, 0, 100000)");
+    }
+
+    #[test]
+    fn wrongly_typed_builtin_arguments_are_described() {
+        let e = eval_mistyped_err("main () = print one\n");
+        assert!(matches!(e, RuntimeError::ArgumentMismatch(BuiltInFn::Print, ..)));
+        assert_eq!(
+            e.to_string(),
+            "builtin Print expects an argument of type String but got integer"
+        );
+
+        let e = eval_mistyped_err("main () = String_parse_int one\n");
+        assert!(matches!(e, RuntimeError::ArgumentMismatch(BuiltInFn::StringParseInt, ..)));
+        assert_eq!(
+            e.to_string(),
+            "builtin StringParseInt expects an argument of type String but got integer"
+        );
+    }
+
+    #[test]
+    fn read_all_drains_the_injected_input() {
+        let config = InterpreterConfig {
+            input: Some(box std::io::Cursor::new("first line\nsecond line\n\nlast")),
+            ..InterpreterConfig::default()
+        };
+        // once everything is read there is nothing left for a second read
+        let src = "main () = (Stdin_read_all (), Stdin_read_all ())\n";
+        let result = interpret_with_config(crate::typecheck_source(src), config);
+
+        assert_eq!(result.unwrap().to_string(), "(first line\nsecond line\n\nlast, )");
+    }
+
+    #[test]
+    fn error_output_is_captured_apart_from_output() {
+        let out = Rc::new(RefCell::new(String::new()));
+        let err = Rc::new(RefCell::new(String::new()));
+        let (out_sink, err_sink) = (out.clone(), err.clone());
+        let config = InterpreterConfig {
+            output: Some(box move |s: &str| out_sink.borrow_mut().push_str(s)),
+            error_output: Some(box move |s: &str| err_sink.borrow_mut().push_str(s)),
+            ..InterpreterConfig::default()
+        };
+        let src = "main () =\n\
+                   \tprint \"data\"; eprint \"warning: \"; eprintln \"slow\"; print \"more\"\n";
+        interpret_with_config(crate::typecheck_source(src), config).unwrap();
+
+        assert_eq!(*out.borrow(), "datamore");
+        assert_eq!(*err.borrow(), "warning: slow\n");
+    }
+
+    #[test]
+    fn adding_an_int_to_a_string_names_both_operands() {
+        // the typechecker catches it in source
+        let v = eval_string("main () = 1 + \"one\"\n");
+        assert!(matches!(v, Err(crate::Error::TypeChecking(_))), "{:?}", v);
+
+        // a string that only turns up at runtime
+        let e = eval_mistyped_err("main () = 1 + word\n");
+        assert!(matches!(e, RuntimeError::OperandMismatch(..)), "{:?}", e);
+        assert_eq!(e.to_string(), "cannot apply + to integer and string");
+    }
+
+    #[test]
+    fn if_then_runs_its_branch_only_when_the_condition_holds() {
+        let src = "report :: Int -> ()\n\
+                   report n =\n\
+                   \tif n > 2 then printi n;\n\
+                   \tif n == 0 then print \"zero\"\n\
+                   \n\
+                   main () =\n\
+                   \treport 0;\n\
+                   \treport 1;\n\
+                   \treport 5;\n\
+                   \tif false then print \"never\"\n";
+        let (value, printed) = eval_printing(src);
+        assert_eq!(value.to_string(), "()");
+        assert_eq!(printed, "zero5");
+    }
+
+    #[test]
+    fn variant_tag_and_name() {
+        let src = "type Shape =\n\
+                   \t| Circle of Int\n\
+                   \t| Rect of (Int, Int)\n\
+                   \t| Empty\n\
+                   \n\
+                   main () =\n\
+                   \tprinti (variant_tag (Shape.Rect (1, 2)));\n\
+                   \tprinti (variant_tag Shape.Empty);\n\
+                   \tprint (variant_name (Shape.Circle 3));\n\
+                   \tprint (variant_name (Some 1))\n";
+        assert_eq!(eval_printing(src).1, "12CircleSome");
+
+        match eval_err("main () = variant_tag 5\n") {
+            RuntimeError::TypeMismatch(_) => {}
+            e => panic!("expected an integer to have no tag, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn show_prints_any_value() {
+        let src = "type Shape =\n\
+                   \t| Circle of Int\n\
+                   \t| Rect of (Int, Int)\n\
+                   \n\
+                   main () =\n\
+                   \tshow (1, \"two\", [3, 4]);\n\
+                   \tprint \"\\n\";\n\
+                   \tshow (Shape.Rect (5, 6));\n\
+                   \tprint \"\\n\";\n\
+                   \tshow (Some (Shape.Circle 1));\n\
+                   \tprint \"\\n\";\n\
+                   \tshow \"plain\"\n";
+        assert_eq!(
+            eval_printing(src).1,
+            "(1, two, [3, 4])\nRect (5, 6)\nSome (Circle 1)\nplain"
+        );
+    }
+
+    #[test]
+    fn chars_compare_by_code_point() {
+        let src = "first :: String -> Char\nfirst s = String_char_at (s, 0)\n\n\
+                   is_lower :: Char -> Bool\nis_lower c = first \"a\" <= c and c <= first \"z\"\n\n\
+                   classify :: Int -> ()\n\
+                   classify i =\n\
+                   \tlet c = String_char_at (\"aZq{0\", i)\n\
+                   \tprint (if is_lower c then \"y\" else \"n\")\n\n\
+                   main () =\n\
+                   \tclassify 0; classify 1; classify 2; classify 3; classify 4;\n\
+                   \tprint (if first \"b\" > first \"a\" then \"y\" else \"n\")\n";
+        // whether each of a, Z, q, { and 0 is lowercase, then whether b > a
+        assert_eq!(eval_printing(src).1, "ynynny");
+    }
+
+    #[test]
+    fn ranges_as_lists() {
+        let src = "main () =\n\
+                   \t(Range_list (0, 5), Range_list (3, 3), Range_list (5, 2),\n\
+                   \t Range_list (-2, 1), Range_step (0, 10, 3), Range_step (1, 2, 5))\n";
+        assert_eq!(
+            eval(src).to_string(),
+            "([0, 1, 2, 3, 4], [], [], [-2, -1, 0], [0, 3, 6, 9], [1])"
+        );
+
+        match eval_err("main () = Range_step (0, 4, 0)\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::RangeStep, msg) => {
+                assert_eq!(msg, "step must be positive, got 0")
+            }
+            e => panic!("expected a zero step to be rejected, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn map_keys_keep_insertion_order() {
+        let src = "fill :: Map String Int -> ()\n\
+                   fill m =\n\
+                   \tMap_insert (m, \"zebra\", 1);\n\
+                   \tMap_insert (m, \"apple\", 2);\n\
+                   \tMap_insert (m, \"mango\", 3);\n\
+                   \tMap_insert (m, \"kiwi\", 4);\n\
+                   \tMap_insert (m, \"apple\", 5);\n\
+                   \tMap_remove (m, \"mango\");\n\
+                   \t()\n\
+                   \n\
+                   main () =\n\
+                   \tlet m = Map_new ()\n\
+                   \tfill m;\n\
+                   \t(Map_keys m, Map_values m, m)\n";
+        // overwriting keeps a key's place, removing closes the gap
+        let first = eval(src).to_string();
+        assert_eq!(first, "([zebra, apple, kiwi], [1, 5, 4], map { zebra: 1, apple: 5, kiwi: 4 })");
+        for _ in 0..5 {
+            assert_eq!(eval(src).to_string(), first);
+        }
+    }
+
+    #[test]
+    fn string_fold_counts_vowels() {
+        let src = "is_vowel :: Char -> Bool\n\
+                   is_vowel c =\n\
+                   \tmatch String_index_of (\"aeiouAEIOU\", Char_to_string c) with\n\
+                   \t| Some _ -> true\n\
+                   \t| None -> false\n\
+                   \n\
+                   count_vowel :: Int -> Char -> Int\n\
+                   count_vowel n c = if is_vowel c then n + 1 else n\n\
+                   \n\
+                   main () =\n\
+                   \t(String_fold (\"Hello, wörld of Äpples\", 0, count_vowel),\n\
+                   \t String_fold (\"\", 7, count_vowel))\n";
+        // only the ASCII vowels count, so not ö or Ä
+        assert_eq!(eval(src).to_string(), "(4, 7)");
+    }
+
+    #[test]
+    fn reading_a_missing_file_fails_cleanly() {
+        let path = temp_dir("read_missing").join("missing.txt");
+        let path = path.to_str().unwrap();
+
+        match eval_err(&format!("main () = File_read {:?}\n", path)) {
+            RuntimeError::IoError(p, _) => assert_eq!(p, path),
+            e => panic!("expected an io error, got {:?}", e),
+        }
+
+        // or as an Err the program can handle
+        let src = format!(
+            "main () = match File_try_read {:?} with\n\
+             \t| Ok _ -> \"read\"\n\
+             \t| Err _ -> \"failed\"\n",
+            path
+        );
+        assert_eq!(eval(&src).to_string(), "failed");
+    }
+
+    #[test]
+    fn profile_counts_each_kind_of_expression() {
+        let src = "double :: Int -> Int\ndouble x = x * 2\n\nmain () = double (1 + 2)\n";
+        let (result, profile) = interpret_with_profile(crate::typecheck_source(src));
+
+        assert_eq!(result.unwrap().to_string(), "6");
+        let counts: Vec<_> = profile.evaluations.iter().map(|(k, n)| (*k, *n)).collect();
+        assert_eq!(
+            counts,
+            [("Application", 1), ("BinaryOp", 2), ("IntegerLiteral", 3), ("Symbol", 2)]
+        );
+        assert_eq!(profile.applications, 1);
+        assert_eq!(profile.total_evaluations(), 8);
+    }
+
+    #[test]
+    fn raised_values_are_the_error_payload() {
+        let src = "check :: Int -> Int\ncheck n = if n > 10 then raise \"too big\" else n\n\n\
+                   main () = check 5 + check 50\n";
+        match eval_err(src) {
+            RuntimeError::Raised(Value::String(msg)) => assert_eq!(*msg, "too big"),
+            e => panic!("expected a raised string, got {:?}", e),
+        }
+
+        match eval_err("main () = raise (1, Some \"x\")\n") {
+            RuntimeError::Raised(v) => assert_eq!(v.to_string(), "(1, Some x)"),
+            e => panic!("expected a raised tuple, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn catch_recovers_from_raise_and_continues() {
+        let src = "check :: Int -> Int\ncheck n = if n > 10 then raise \"too big\" else n\n\n\
+                   risky :: Int -> () -> Int\nrisky n u = check n\n\n\
+                   recover :: Failure String -> Int\nrecover f =\n\
+                   \tmatch f with\n\t| Raised msg -> print msg; 0\n\t| Failed msg -> 1\n\n\
+                   main () =\n\tlet a = catch (risky 50, recover)\n\
+                   \tlet b = catch (risky 5, recover)\n\t(a, b)\n";
+        let (value, printed) = eval_printing(src);
+        assert_eq!(value.to_string(), "(0, 5)");
+        assert_eq!(printed, "too big");
+
+        // the handler gets the raised value itself rather than its text
+        let src = "type Problem = | TooBig of Int | Negative\n\n\
+                   check :: Int -> Int\n\
+                   check n = if n > 10 then raise (Problem.TooBig n) else n\n\n\
+                   risky :: Int -> () -> Int\nrisky n u = check n\n\n\
+                   recover :: Failure Problem -> Int\nrecover f =\n\
+                   \tmatch f with\n\t| Raised (TooBig n) -> 10 - n\n\t| _ -> 0\n\n\
+                   main () = (catch (risky 50, recover), catch (risky 5, recover))\n";
+        assert_eq!(eval(src).to_string(), "(-40, 5)");
+
+        // any other recoverable error is failed with its message
+        let src = "risky :: () -> String\nrisky u = if 1 / 0 > 0 then \"big\" else \"small\"\n\n\
+                   recover :: Failure Int -> String\nrecover f =\n\
+                   \tmatch f with\n\t| Raised n -> \"raised\"\n\t| Failed msg -> msg\n\n\
+                   main () = catch (risky, recover)\n";
+        let v = eval(src).to_string();
+        assert!(v.ends_with("arithmetic error: division by zero"), "{}", v);
+    }
+
+    #[test]
+    fn try_binds_the_failure_for_its_handler() {
+        let src = "check :: Int -> Int\ncheck n = if n > 10 then raise (n, \"too big\") else n\n\n\
+                   handle :: Int -> Failure (Int, String) -> Int\n\
+                   handle fallback f = match f with\n\
+                   \t| Raised (m, why) -> print why; m * fallback\n\
+                   \t| Failed msg -> fallback\n\n\
+                   guarded :: Int -> Int\n\
+                   guarded n =\n\
+                   \tlet fallback = 0 - 1\n\
+                   \ttry check n + 1 with f -> handle fallback f\n\n\
+                   main () = (guarded 3, guarded 50)\n";
+        let (value, printed) = eval_printing(src);
+        assert_eq!(value.to_string(), "(4, -50)");
+        assert_eq!(printed, "too big");
+
+        // the handler has to give the same type as the body
+        let src = "main () = try 1 with f -> \"one\"\n";
+        let v = eval_string(src);
+        assert!(matches!(v, Err(crate::Error::TypeChecking(_))), "{:?}", v);
+    }
+
+    #[test]
+    fn running_out_of_fuel_cant_be_caught() {
+        let src = "spin :: () -> Int\nspin u =\n\tlet _ = while true do ()\n\t0\n\n\
+                   recover :: Failure Int -> Int\nrecover f = 0\n\n\
+                   main () = catch (spin, recover)\n";
+        match interpret_with_fuel(crate::typecheck_source(src), 1000) {
+            Err(e) => assert!(!e.is_recoverable(), "{:?}", e),
+            Ok(v) => panic!("expected to run out of fuel, got {}", v),
+        }
+
+        let src = "spin :: () -> Int\nspin u =\n\tlet _ = while true do ()\n\t0\n\n\
+                   main () = try spin () with f -> 0\n";
+        match interpret_with_fuel(crate::typecheck_source(src), 1000) {
+            Err(e) => assert!(!e.is_recoverable(), "{:?}", e),
+            Ok(v) => panic!("expected to run out of fuel, got {}", v),
+        }
+    }
+
+    #[test]
+    fn three_parameter_functions_applied_fully_and_partially() {
+        let src = "combine :: Int -> Int -> Int -> Int\n\
+                   combine a b c = a * 100 + b * 10 + c\n\
+                   \n\
+                   apply_all :: (Int -> Int) -> Int\n\
+                   apply_all f = f 9\n\
+                   \n\
+                   apply3 :: (Int -> Int -> Int -> Int) -> Int\n\
+                   apply3 f = f 2 3 4\n\
+                   \n\
+                   apply_partial :: (Int -> Int -> Int -> Int) -> Int\n\
+                   apply_partial f = apply_all (f 1 2)\n\
+                   \n\
+                   main () =\n\
+                   \tlet weigh x y z = combine z y x\n\
+                   \tprinti (apply3 (\\w h d -> w * h * d));\n\
+                   \tprint \" \";\n\
+                   \tprinti (apply_partial (\\w h d -> w * h * d));\n\
+                   \tprint \" \";\n\
+                   \tprinti (weigh 1 2 3);\n\
+                   \tprint \" \";\n\
+                   \tprinti (apply_all (weigh 1 2));\n\
+                   \tprint \" \";\n\
+                   \tlet partial = combine 1\n\
+                   \tprinti (apply_all (partial 2))\n";
+        assert_eq!(eval_printing(src).1, "24 18 321 921 129");
+    }
+
+    #[test]
+    fn char_codes_round_trip() {
+        let src = "first :: String -> Char\nfirst s = String_char_at (s, 0)\n\n\
+                   shift :: Char -> Char\nshift c = Char_from_code (Char_code c + 1)\n\n\
+                   main () =\n\
+                   \t(Char_code (first \"A\"), Char_code (first \"€\"), Char_from_code 955,\n\
+                   \t shift (first \"H\"))\n";
+        assert_eq!(eval(src).to_string(), "(65, 8364, λ, I)");
+
+        // surrogates and anything past the last code point
+        for code in &[55296, 1114112, -1] {
+            match eval_err(&format!("main () = Char_from_code ({})\n", code)) {
+                RuntimeError::InvalidArgument(BuiltInFn::CharFromCode, _) => {}
+                e => panic!("expected {} to be rejected, got {:?}", code, e),
+            }
+        }
+    }
+
+    #[test]
+    fn time_it_measures_with_the_configured_clock() {
+        // every look at the clock moves it on by a quarter second
+        let mut now = Duration::from_secs(0);
+        let config = InterpreterConfig {
+            clock: Some(box move || {
+                now += Duration::from_millis(250);
+                now
+            }),
+            ..InterpreterConfig::default()
+        };
+        let src = "work :: () -> Int\nwork u = 6 * 7\n\n\
+                   timed :: () -> (Int, Int)\ntimed u = time_it work\n\n\
+                   main () = time_it timed\n";
+        let result = interpret_with_config(crate::typecheck_source(src), config);
+
+        assert_eq!(result.unwrap().to_string(), "((42, 250), 750)");
+    }
+
+    #[test]
+    fn tuples_are_not_records() {
+        let src = "type Point = { x: Int, y: Int }\n\n\
+                   get_x :: Point -> Int\nget_x p = p.x\n\n\
+                   point :: () -> Point\npoint u = { x: 1, y: 2 }\n\n\
+                   main () = (point (), (1, 2), get_x (point ()))\n";
+        assert_eq!(eval(src).to_string(), "({ x: 1, y: 2 }, (1, 2), 1)");
+
+        let v = eval_string(&src.replace("get_x (point ())", "get_x (1, 2)"));
+        assert!(matches!(v, Err(crate::Error::TypeChecking(_))), "{:?}", v);
+
+        // a tuple from the host doesn't get past field access either
+        let tuple = Value::Tuple(vec![Value::Integer(1), Value::Integer(2)]);
+        let result = run_function(crate::typecheck_source(src), "get_x", vec![tuple]);
+        assert!(matches!(result.map_err(innermost), Err(RuntimeError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn string_count_counts_separate_occurrences() {
+        let src = "main () =\n\
+                   \t(String_count (\"the cat sat on the mat\", \"at\"),\n\
+                   \t String_count (\"aaaaa\", \"aa\"), String_count (\"abc\", \"x\"),\n\
+                   \t String_count (\"äöäöä\", \"ä\"))\n";
+        // overlapping matches only count once, so "aa" is in "aaaaa" twice
+        assert_eq!(eval(src).to_string(), "(3, 2, 0, 3)");
+
+        match eval_err("main () = String_count (\"abc\", \"\")\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::StringCount, msg) => {
+                assert_eq!(msg, "cannot count occurrences of an empty string")
+            }
+            e => panic!("expected an empty needle to be rejected, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn stack_depth_grows_with_each_call() {
+        let src = "nest :: Int -> Int\nnest n = if n == 0 then stack_depth () else nest (n - 1)\n\n\
+                   main () = (stack_depth (), nest 0, nest 3)\n";
+        // main is the first frame
+        assert_eq!(eval(src).to_string(), "(1, 2, 5)");
+    }
+
+    #[test]
+    fn string_words_splits_on_runs_of_whitespace() {
+        let src = "main () =\n\
+                   \t(String_words \"one  two\\tthree\\n\\nfour\",\n\
+                   \t String_words \"   padded words   \",\n\
+                   \t String_words \"\", String_words \" \\t\\n \")\n";
+        assert_eq!(eval(src).to_string(), "([one, two, three, four], [padded, words], [], [])");
+    }
+
+    #[test]
+    fn conditions_must_be_bools() {
+        let src = "pick :: Bool -> String\npick b = if b then \"yes\" else \"no\"\n\n\
+                   main () = (pick true, pick false, pick (1 < 2))\n";
+        assert_eq!(eval(src).to_string(), "(yes, no, yes)");
+
+        let v = eval_string("main () = if 1 then \"yes\" else \"no\"\n");
+        assert!(matches!(v, Err(crate::Error::TypeChecking(_))), "{:?}", v);
+
+        // bools are integers at runtime, but anything else from the host isn't a condition
+        let arg = Value::String(Rc::new("true".to_owned()));
+        let result = run_function(crate::typecheck_source(src), "pick", vec![arg]);
+        match result.map_err(innermost) {
+            Err(RuntimeError::ConditionNotBool(kind)) => assert_eq!(kind, "string"),
+            r => panic!("expected a string condition to be rejected, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn string_repeat_counts() {
+        let src = "main () =\n\
+                   \t(String_repeat (\"ab\", 3), String_repeat (\"ab\", 0),\n\
+                   \t String_repeat (\"\", 1000000), String_repeat (\"-=\", 5))\n";
+        assert_eq!(eval(src).to_string(), "(ababab, , , -=-=-=-=-=)");
+
+        for (count, msg) in &[
+            ("-2", "negative count -2"),
+            ("9223372036854775807", "repeating 9223372036854775807 times is too long"),
+        ] {
+            match eval_err(&format!("main () = String_repeat (\"abc\", {})\n", count)) {
+                RuntimeError::InvalidArgument(BuiltInFn::StringRepeat, m) => assert_eq!(m, *msg),
+                e => panic!("expected repeating {} times to fail, got {:?}", count, e),
+            }
+        }
+    }
+
+    #[test]
+    fn padding_to_a_width_in_characters() {
+        let src = "row :: String -> Int -> ()\n\
+                   row name n =\n\
+                   \tlet dot = String_char_at (\".\", 0)\n\
+                   \tlet space = String_char_at (\" \", 0)\n\
+                   \tprint (String_pad_right (name, 8, dot));\n\
+                   \tprint (String_pad_left (format (\"{}\", n), 5, space));\n\
+                   \tprint \"|\\n\"\n\
+                   \n\
+                   main () =\n\
+                   \trow \"apples\" 3;\n\
+                   \trow \"kiwi\" 120;\n\
+                   \trow \"äpfel\" 7;\n\
+                   \trow \"exactly8\" 0;\n\
+                   \trow \"much too long\" 123456\n";
+        // äpfel is five characters, so it gets three dots like apples gets two
+        assert_eq!(
+            eval_printing(src).1,
+            "apples..    3|\n\
+             kiwi....  120|\n\
+             äpfel...    7|\n\
+             exactly8    0|\n\
+             much too long123456|\n"
+        );
+    }
+
+    #[test]
+    fn discarded_values_leave_only_unit() {
+        let printed = Rc::new(RefCell::new(String::new()));
+        let sink = printed.clone();
+        let config = InterpreterConfig {
+            output: Some(box move |s: &str| sink.borrow_mut().push_str(s)),
+            ..InterpreterConfig::default()
+        };
+
+        let src = "main () =\n\tInt_popcount 7;\n\tprinti 1;\n\tignore (printi 2);\n\
+                   \tignore (Int_popcount 3)\n";
+        let mut interpreter = Interpreter::with_config(crate::typecheck_source(src), config);
+        interpreter.call_fn("main").unwrap();
+
+        assert_eq!(interpreter.stack.len(), 1);
+        assert!(matches!(interpreter.pop_val(), Some(Value::Unit)));
+        assert_eq!(*printed.borrow(), "12");
+    }
+
+    #[test]
+    fn bools_convert_to_strings_and_back() {
+        let src = "main () =\n\
+                   \tprint (Bool_to_string true);\n\
+                   \tprint \" \";\n\
+                   \tprint (Bool_to_string (1 > 2));\n\
+                   \tprint \" \";\n\
+                   \tprint (if String_parse_bool \"TRUE\" then \"yes\" else \"no\");\n\
+                   \tprint \" \";\n\
+                   \tprint (if String_parse_bool \"False\" then \"yes\" else \"no\")\n";
+        assert_eq!(eval_printing(src).1, "true false yes no");
+
+        match eval_err("main () = String_parse_bool \"yes\"\n") {
+            RuntimeError::InvalidArgument(BuiltInFn::StringParseBool, msg) => {
+                assert_eq!(msg, "\"yes\" is neither true nor false")
+            }
+            e => panic!("expected an unparsable bool to be rejected, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn min_and_max_of_a_list() {
+        let src = "main () =\n\
+                   \t(List_min [5, -3, 8, 0], List_max [5, -3, 8, 0],\n\
+                   \t List_min [\"pear\", \"apple\", \"fig\"],\n\
+                   \t List_max [\"pear\", \"apple\", \"fig\"],\n\
+                   \t List_max (String_to_chars \"hello\"), List_min (Range_list (0, 0)))\n";
+        // an empty list has no minimum rather than failing
+        assert_eq!(
+            eval(src).to_string(),
+            "(Some -3, Some 8, Some apple, Some pear, Some o, None)"
+        );
+
+        let e = eval_err("main () = List_max [[1], [2]]\n");
+        assert!(matches!(e, RuntimeError::OperandMismatch(..)), "{:?}", e);
+        assert_eq!(e.to_string(), "cannot apply < to list and list");
+    }
+
+    #[test]
+    fn sorting_lists_keeps_equal_elements_in_order() {
+        let src = "main () =\n\
+                   \t(List_sort [5, -3, 8, 0, 5, 1],\n\
+                   \t List_sort [\"pear\", \"apple\", \"fig\", \"banana\"],\n\
+                   \t List_sort (Range_list (0, 0)))\n";
+        assert_eq!(
+            eval(src).to_string(),
+            "([-3, 0, 1, 5, 5, 8], [apple, banana, fig, pear], [])"
+        );
+
+        let src = "by_first :: (Int, String) -> (Int, String) -> Bool\n\
+                   by_first a b = a.0 < b.0\n\
+                   \n\
+                   main () =\n\
+                   \tList_sort_by ([(2, \"b\"), (1, \"x\"), (2, \"a\"), (1, \"y\"), (0, \"z\")],\n\
+                   \t\tby_first)\n";
+        // only the numbers are compared, so b stays before a and x before y
+        assert_eq!(eval(src).to_string(), "[(0, z), (1, x), (1, y), (2, b), (2, a)]");
+
+        let e = eval_err("main () = List_sort [[2], [1]]\n");
+        assert!(matches!(e, RuntimeError::OperandMismatch(..)), "{:?}", e);
+        assert_eq!(e.to_string(), "cannot apply < to list and list");
+    }
+
+    #[test]
+    fn capturing_returns_output_with_the_result() {
+        let src = "main () =\n\tprint \"a\";\n\tprinti 1;\n\t42\n";
+        let (result, output) = interpret_capturing(crate::typecheck_source(src));
+        assert!(matches!(result, Ok(Value::Integer(42))), "{:?}", result);
+        assert_eq!(output, "a1");
+
+        // what was printed before the failure is kept
+        let src = "main () =\n\tprint \"before\";\n\tprinti (1 / 0);\n\tprint \"after\"\n";
+        let (result, output) = interpret_capturing(crate::typecheck_source(src));
+        assert!(result.is_err());
+        assert_eq!(output, "before");
+    }
 }
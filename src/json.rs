@@ -0,0 +1,483 @@
+use std::{fmt::Write, iter::Peekable, rc::Rc, str::CharIndices};
+
+use crate::{ast::typed::TypeHandle, interpret::Value};
+
+#[derive(Debug, Clone)]
+pub enum JsonError {
+    UnserializableValue(Value),
+    /// the byte offset the input stopped making sense at, and what was expected there
+    Malformed(usize, String),
+}
+
+/// Renders `value` as JSON. Variants become `{ "tag": "Name", "value": ... }`,
+/// with the name found through the variant's own type handle, except for those
+/// of the prelude's `Json` type which are written as the json they stand for.
+/// Functions and floats json has no number for are an `UnserializableValue` error.
+pub fn value_to_json(value: &Value) -> Result<String, JsonError> {
+    let mut out = String::new();
+    write_value(&mut out, value)?;
+    Ok(out)
+}
+
+fn write_value(out: &mut String, value: &Value) -> Result<(), JsonError> {
+    match value {
+        Value::Unit => out.push_str("null"),
+        Value::Integer(i) => write!(out, "{}", i).unwrap(),
+        // json has no representation for nan or the infinities
+        Value::Float(f) if !f.is_finite() => {
+            return Err(JsonError::UnserializableValue(value.clone()))
+        }
+        Value::Float(f) => write!(out, "{:?}", f).unwrap(),
+        Value::String(s) => write_string(out, s),
+        Value::Char(c) => write_string(out, &c.to_string()),
+        Value::Tuple(values) | Value::List(values) => {
+            out.push('[');
+            for (i, v) in values.iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                write_value(out, v)?;
+            }
+            out.push(']');
+        }
+        Value::Record(fields) => {
+            out.push('{');
+            for (i, (n, v)) in fields.iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                write_string(out, n);
+                out.push(':');
+                write_value(out, v)?;
+            }
+            out.push('}');
+        }
+        Value::Variant(th, _, fields) if is_json(th) => {
+            let payload = Value::variant_payload(fields);
+            match (value.variant_name().unwrap().as_str(), payload) {
+                ("Null", _) => out.push_str("null"),
+                ("Boolean", Value::Integer(0)) => out.push_str("false"),
+                ("Boolean", _) => out.push_str("true"),
+                ("Object", Value::List(fields)) => {
+                    out.push('{');
+                    for (i, field) in fields.iter().enumerate() {
+                        if i != 0 {
+                            out.push(',');
+                        }
+                        match field {
+                            Value::Tuple(pair) if pair.len() == 2 => {
+                                write_value(out, &pair[0])?;
+                                out.push(':');
+                                write_value(out, &pair[1])?;
+                            }
+                            _ => return Err(JsonError::UnserializableValue(field.clone())),
+                        }
+                    }
+                    out.push('}');
+                }
+                (_, payload) => write_value(out, &payload)?,
+            }
+        }
+        Value::Variant(_, _, fields) => {
+            out.push_str("{\"tag\":");
+            write_string(out, &value.variant_name().unwrap());
+            out.push_str(",\"value\":");
+            write_value(out, &Value::variant_payload(fields))?;
+            out.push('}');
+        }
+        Value::Ref(cell) => write_value(out, &cell.borrow())?,
+        // keys aren't necessarily strings, so maps become a list of pairs
+        Value::Map(map) => {
+            out.push('[');
+            for (i, (k, v)) in map.borrow().values().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                out.push('[');
+                write_value(out, k)?;
+                out.push(',');
+                write_value(out, v)?;
+                out.push(']');
+            }
+            out.push(']');
+        }
+        Value::Function(..)
+        | Value::VariantConstructorFn(..)
+        | Value::BuiltInFn(_)
+        | Value::Memoized(_) => return Err(JsonError::UnserializableValue(value.clone())),
+    }
+
+    Ok(())
+}
+
+/// Whether `th` is the prelude's `Json` type
+fn is_json(th: &TypeHandle) -> bool {
+    th.index == TypeHandle::prelude(&th.environment, "Json").index
+}
+
+/// Parses `src` into a value of the prelude's `Json` type, which `json` is the
+/// handle of. Numbers with a fraction or exponent become `Number`s, all others
+/// `Integer`s.
+pub fn json_to_value(src: &str, json: &TypeHandle) -> Result<Value, JsonError> {
+    let mut parser = JsonParser {
+        chars: src.char_indices().peekable(),
+        len: src.len(),
+        json,
+    };
+
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    match parser.chars.peek() {
+        None => Ok(value),
+        Some(&(at, _)) => Err(JsonError::Malformed(at, "end of input".to_owned())),
+    }
+}
+
+struct JsonParser<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    len: usize,
+    json: &'a TypeHandle,
+}
+
+impl<'a> JsonParser<'a> {
+    fn node(&self, variant: &str, payload: Value) -> Value {
+        Value::variant(self.json.clone(), self.json.variant_index(variant), payload)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.len, |&(at, _)| at)
+    }
+
+    fn error<T>(&mut self, expected: &str) -> Result<T, JsonError> {
+        Err(JsonError::Malformed(self.offset(), expected.to_owned()))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some((_, ' ')) | Some((_, '\n')) | Some((_, '\r')) | Some((_, '\t')) =
+            self.chars.peek()
+        {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some(&(_, next)) if next == c => {
+                self.chars.next();
+                Ok(())
+            }
+            _ => self.error(&format!("'{}'", c)),
+        }
+    }
+
+    fn expect_word(&mut self, word: &str, value: Value) -> Result<Value, JsonError> {
+        for c in word.chars() {
+            match self.chars.peek() {
+                Some(&(_, next)) if next == c => {
+                    self.chars.next();
+                }
+                _ => return self.error(word),
+            }
+        }
+
+        Ok(value)
+    }
+
+    fn parse_value(&mut self) -> Result<Value, JsonError> {
+        self.skip_whitespace();
+
+        match self.chars.peek() {
+            Some((_, '{')) => {
+                self.chars.next();
+                let mut fields = Vec::new();
+
+                self.skip_whitespace();
+                if let Some((_, '}')) = self.chars.peek() {
+                    self.chars.next();
+                    return Ok(self.node("Object", Value::List(fields)));
+                }
+
+                loop {
+                    self.skip_whitespace();
+                    let name = self.parse_string()?;
+                    self.expect(':')?;
+                    let value = self.parse_value()?;
+                    fields.push(Value::Tuple(vec![Value::String(Rc::new(name)), value]));
+
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some((_, ',')) => (),
+                        Some((_, '}')) => return Ok(self.node("Object", Value::List(fields))),
+                        Some((at, _)) => {
+                            return Err(JsonError::Malformed(at, "',' or '}'".to_owned()))
+                        }
+                        None => return self.error("',' or '}'"),
+                    }
+                }
+            }
+            Some((_, '[')) => {
+                self.chars.next();
+                let mut values = Vec::new();
+
+                self.skip_whitespace();
+                if let Some((_, ']')) = self.chars.peek() {
+                    self.chars.next();
+                    return Ok(self.node("Array", Value::List(values)));
+                }
+
+                loop {
+                    values.push(self.parse_value()?);
+
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some((_, ',')) => (),
+                        Some((_, ']')) => return Ok(self.node("Array", Value::List(values))),
+                        Some((at, _)) => {
+                            return Err(JsonError::Malformed(at, "',' or ']'".to_owned()))
+                        }
+                        None => return self.error("',' or ']'"),
+                    }
+                }
+            }
+            Some((_, '"')) => {
+                let s = self.parse_string()?;
+                Ok(self.node("Text", Value::String(Rc::new(s))))
+            }
+            Some((_, 't')) => {
+                let value = self.node("Boolean", Value::Integer(1));
+                self.expect_word("true", value)
+            }
+            Some((_, 'f')) => {
+                let value = self.node("Boolean", Value::Integer(0));
+                self.expect_word("false", value)
+            }
+            Some((_, 'n')) => {
+                let value = self.node("Null", Value::Unit);
+                self.expect_word("null", value)
+            }
+            Some((_, c)) if *c == '-' || c.is_ascii_digit() => self.parse_number(),
+            _ => self.error("a value"),
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        match self.chars.next() {
+            Some((_, '"')) => (),
+            Some((at, _)) => return Err(JsonError::Malformed(at, "a string".to_owned())),
+            None => return self.error("a string"),
+        }
+
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(s),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, '"')) => s.push('"'),
+                    Some((_, '\\')) => s.push('\\'),
+                    Some((_, '/')) => s.push('/'),
+                    Some((_, 'b')) => s.push('\u{8}'),
+                    Some((_, 'f')) => s.push('\u{c}'),
+                    Some((_, 'n')) => s.push('\n'),
+                    Some((_, 'r')) => s.push('\r'),
+                    Some((_, 't')) => s.push('\t'),
+                    Some((at, 'u')) => {
+                        let hex = (0..4)
+                            .filter_map(|_| self.chars.next().map(|(_, c)| c))
+                            .collect::<String>();
+                        // surrogate pairs aren't combined, a lone half is malformed
+                        match u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(std::char::from_u32)
+                        {
+                            Some(c) => s.push(c),
+                            None => {
+                                return Err(JsonError::Malformed(
+                                    at,
+                                    "four hex digits naming a char".to_owned(),
+                                ))
+                            }
+                        }
+                    }
+                    Some((at, _)) => {
+                        return Err(JsonError::Malformed(at, "an escape sequence".to_owned()))
+                    }
+                    None => return self.error("an escape sequence"),
+                },
+                Some((at, c)) if (c as u32) < 0x20 => {
+                    return Err(JsonError::Malformed(
+                        at,
+                        "an escaped control character".to_owned(),
+                    ))
+                }
+                Some((_, c)) => s.push(c),
+                None => return self.error("'\"'"),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Value, JsonError> {
+        let start = self.offset();
+        let mut number = String::new();
+        let mut is_float = false;
+
+        while let Some(&(_, c)) = self.chars.peek() {
+            match c {
+                '0'..='9' | '-' | '+' => (),
+                '.' | 'e' | 'E' => is_float = true,
+                _ => break,
+            }
+            number.push(c);
+            self.chars.next();
+        }
+
+        let malformed = || JsonError::Malformed(start, "a number".to_owned());
+        if is_float {
+            let f = number.parse().map_err(|_| malformed())?;
+            Ok(self.node("Number", Value::Float(f)))
+        } else {
+            let i = number.parse().map_err(|_| malformed())?;
+            Ok(self.node("Integer", Value::Integer(i)))
+        }
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_of(src: &str) -> Result<String, JsonError> {
+        let value = crate::eval_string(src).unwrap_or_else(|e| panic!("{} failed: {:?}", src, e));
+        value_to_json(&value)
+    }
+
+    fn parse(src: &str) -> Result<Value, JsonError> {
+        let program = crate::typecheck_source("main () = ()\n");
+        json_to_value(src, &TypeHandle::prelude(&program.environment, "Json"))
+    }
+
+    /// `src` parsed and written back out
+    fn parsed(src: &str) -> String {
+        let value = parse(src).unwrap_or_else(|e| panic!("{} failed to parse: {:?}", src, e));
+        value_to_json(&value).unwrap()
+    }
+
+    #[test]
+    fn serializes_plain_values() {
+        let src = "type Point = { x: Int, label: String }\n\n\
+                   point :: () -> Point\npoint u = { x: 3, label: \"p\" }\n\n\
+                   main () = ((), 1, \"a \\\"b\\\"\", [1, 2], point ())\n";
+        assert_eq!(
+            json_of(src).unwrap(),
+            r#"[null,1,"a \"b\"",[1,2],{"x":3,"label":"p"}]"#
+        );
+    }
+
+    #[test]
+    fn serializes_variants_as_tagged_objects() {
+        let src = "type Shape =\n\t| Circle of Int\n\t| Rect of (Int, Int)\n\t| Dot\n\n\
+                   main () = [Shape.Circle 2, Shape.Rect (3, 4), Shape.Dot]\n";
+        assert_eq!(
+            json_of(src).unwrap(),
+            concat!(
+                r#"[{"tag":"Circle","value":2},{"tag":"Rect","value":[3,4]},"#,
+                r#"{"tag":"Dot","value":null}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn floats_keep_their_fraction() {
+        assert_eq!(json_of("main () = (1.5, 2.0, 0.0 - 0.25)\n").unwrap(), "[1.5,2.0,-0.25]");
+
+        for src in &["main () = 0.0 / 0.0\n", "main () = (1, 1.0 / 0.0)\n"] {
+            match json_of(src) {
+                Err(JsonError::UnserializableValue(Value::Float(f))) => assert!(!f.is_finite()),
+                r => panic!("expected {:?} to be unserializable, got {:?}", src, r),
+            }
+        }
+    }
+
+    #[test]
+    fn functions_are_an_error() {
+        let srcs = [
+            "double :: Int -> Int\ndouble x = x * 2\n\nmain () = [double]\n",
+            "main () = (1, \\x -> x)\n",
+            "main () = Int_popcount\n",
+        ];
+        for src in &srcs {
+            match json_of(src) {
+                Err(JsonError::UnserializableValue(_)) => (),
+                r => panic!("expected {:?} to be unserializable, got {:?}", src, r),
+            }
+        }
+    }
+
+    #[test]
+    fn parses_nested_objects_and_arrays() {
+        let src = r#"{"name": "x", "tags": [1, 2.5, [true, null]], "inner": {"deep": {"n": -3}}}"#;
+        assert_eq!(
+            parsed(src),
+            r#"{"name":"x","tags":[1,2.5,[true,null]],"inner":{"deep":{"n":-3}}}"#
+        );
+        assert_eq!(parsed("[[], [{}], [[1]]]"), "[[],[{}],[[1]]]");
+        assert_eq!(parsed(r#"[false, "a\nb", 1e2]"#), r#"[false,"a\nb",100.0]"#);
+    }
+
+    #[test]
+    fn parsed_json_can_be_matched_on() {
+        let src = "describe :: Json -> String\n\
+                   describe j = match j with\n\
+                   \t| Null -> \"null\"\n\
+                   \t| Boolean b -> if b then \"yes\" else \"no\"\n\
+                   \t| Integer i -> \"int\"\n\
+                   \t| Number f -> \"float\"\n\
+                   \t| Text s -> s\n\
+                   \t| Array items -> \"array\"\n\
+                   \t| Object fields -> \"object\"\n\
+                   \n\
+                   main () =\n\
+                   \tList_map (describe, [Json_parse \"null\", Json_parse \"true\", \
+                   Json_parse \"1\", Json_parse \"1.5\", Json_parse \"\\\"s\\\"\", \
+                   Json_parse \"[]\", Json_parse \"{}\"])\n";
+        let v = crate::eval_string(src).unwrap_or_else(|e| panic!("{} failed: {:?}", src, e));
+        assert_eq!(v.to_string(), "[null, yes, int, float, s, array, object]");
+
+        // Json_parse isn't a way to get a value of any type past the typechecker
+        let v = crate::eval_string("main () = 1 + Json_parse \"1\"\n");
+        assert!(matches!(v, Err(crate::Error::TypeChecking(_))), "{:?}", v);
+    }
+
+    #[test]
+    fn malformed_input_is_an_error() {
+        for src in &["{\"a\": [1, }", "[1 2]", "{\"a\" 1}", "\"unterminated", "[1] 2", ""] {
+            match parse(src) {
+                Err(JsonError::Malformed(at, _)) => assert!(at <= src.len()),
+                r => panic!("expected {:?} to be malformed, got {:?}", src, r),
+            }
+        }
+
+        match crate::eval_string("main () = Json_parse \"[1,\"\n") {
+            Err(crate::Error::Runtime(e)) => {
+                assert!(format!("{:?}", e).contains("malformed json at byte 3"), "{:?}", e)
+            }
+            r => panic!("expected a runtime error, got {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+#![feature(box_syntax)]
+#![feature(let_chains)]
+#![feature(iterator_fold_self)]
+#![feature(try_trait)]
+
+pub mod parser;
+
+use parser::{recdec::ParsingError, scanner::ScanningError, Scanner};
+
+pub mod ast;
+
+pub mod builtins;
+pub mod bytecode;
+pub mod interpret;
+pub mod json;
+pub mod typecheck;
+
+use ast::untyped::Declaration;
+use builtins::BuiltinRegistry;
+use interpret::{RuntimeError, Value};
+use typecheck::TypeCheckingError;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    Scanning(ScanningError),
+    Parsing(ParsingError),
+    TypeChecking(Vec<TypeCheckingError>),
+    Runtime(RuntimeError),
+    MissingEntryPoint,
+}
+
+/// Runs the whole pipeline on `src` and returns the value of `main`,
+/// or of the last top level binding if the program has no `main`.
+pub fn eval_string(src: &str) -> Result<Value, Error> {
+    eval_string_with_builtins(src, BuiltinRegistry::default())
+}
+
+/// Like `eval_string`, with `builtins` bound instead of the default ones
+pub fn eval_string_with_builtins(src: &str, builtins: BuiltinRegistry) -> Result<Value, Error> {
+    let tokens = Scanner::new(src.chars())
+        .scan_all()
+        .map_err(Error::Scanning)?;
+
+    let ast = parser::Parser::new(&tokens)
+        .parse()
+        .map_err(Error::Parsing)?;
+
+    let bindings = ast
+        .declarations
+        .iter()
+        .filter_map(|d| match d {
+            Declaration::Binding(ident, _) => Some(ident.0.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let typechecked = typecheck::typecheck_with_builtins(ast, builtins).map_err(Error::TypeChecking)?;
+
+    if bindings.iter().any(|b| b == "main") {
+        interpret::interpret(typechecked).map_err(Error::Runtime)
+    } else if let Some(last) = bindings.last() {
+        interpret::interpret_binding(typechecked, last).map_err(Error::Runtime)
+    } else {
+        Err(Error::MissingEntryPoint)
+    }
+}
+
+/// Scans, parses and typechecks `src`, for tests which run the program
+/// some other way than `eval_string` does
+#[cfg(test)]
+pub(crate) fn typecheck_source(src: &str) -> typecheck::TypeChecked {
+    let tokens = Scanner::new(src.chars()).scan_all().expect("scanning failed");
+    let ast = parser::Parser::new(&tokens).parse().expect("parsing failed");
+    typecheck::typecheck(ast).expect("typechecking failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval_string_runs_main() {
+        let v = eval_string("double :: Int -> Int\ndouble x = x + x\n\nmain () = double 21\n");
+        assert!(matches!(v, Ok(Value::Integer(42))), "{:?}", v);
+    }
+
+    #[test]
+    fn eval_string_without_main_runs_the_last_binding() {
+        let v = eval_string("unused () = 1\n\nanswer = 40 + 2\n");
+        assert!(matches!(v, Ok(Value::Integer(42))), "{:?}", v);
+
+        let v = eval_string("type Unused = | Unused\n");
+        assert!(matches!(v, Err(Error::MissingEntryPoint)), "{:?}", v);
+    }
+
+    #[test]
+    fn eval_string_reports_each_stage() {
+        let v = eval_string("main () = \"unterminated\n");
+        assert!(matches!(v, Err(Error::Scanning(_))), "{:?}", v);
+
+        let v = eval_string("main () = (1, \n");
+        assert!(matches!(v, Err(Error::Parsing(_))), "{:?}", v);
+
+        let v = eval_string("main () = 1 + \"one\"\n");
+        assert!(matches!(v, Err(Error::TypeChecking(_))), "{:?}", v);
+
+        let v = eval_string("main () = 1 / 0\n");
+        assert!(matches!(v, Err(Error::Runtime(_))), "{:?}", v);
+    }
+
+    #[test]
+    fn typos_suggest_the_name_that_was_meant() {
+        fn unknown_symbols(src: &str) -> Vec<(String, Option<String>)> {
+            fn collect(e: TypeCheckingError, into: &mut Vec<(String, Option<String>)>) {
+                match e {
+                    TypeCheckingError::UnknownSymbol(s, similar) => into.push((s.0, similar)),
+                    TypeCheckingError::CompoundError(errs) => {
+                        errs.into_iter().for_each(|e| collect(e, into))
+                    }
+                    _ => {}
+                }
+            }
+
+            let mut symbols = Vec::new();
+            match eval_string(src) {
+                Err(Error::TypeChecking(errs)) => {
+                    errs.into_iter().for_each(|e| collect(e, &mut symbols))
+                }
+                v => panic!("{} typechecked: {:?}", src, v),
+            }
+            symbols
+        }
+
+        let src = "square :: Int -> Int\nsquare x = x * x\n\nmain () = (squre 2, frobnicate 1)\n";
+        assert_eq!(
+            unknown_symbols(src),
+            [
+                ("squre".to_owned(), Some("square".to_owned())),
+                ("frobnicate".to_owned(), None),
+            ]
+        );
+
+        let src = "main () =\n\tlet total = 4\n\ttotl + 1\n";
+        assert_eq!(unknown_symbols(src), [("totl".to_owned(), Some("total".to_owned()))]);
+    }
+}
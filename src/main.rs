@@ -1,16 +1,9 @@
-#![feature(box_syntax)]
-#![feature(let_chains)]
-#![feature(iterator_fold_self)]
-#![feature(try_trait)]
-
-pub mod parser;
-
-use parser::Scanner;
-
-pub mod ast;
-
-pub mod interpret;
-pub mod typecheck;
+use experimental_lang::{
+    interpret::{self, RuntimeError, Value},
+    parser,
+    parser::Scanner,
+    typecheck,
+};
 
 fn main() {
     // let file = std::fs::read_to_string("examples/aoc2020/day3/main.ml").unwrap();
@@ -32,15 +25,22 @@ fn main() {
                 Ok(t) => t,
                 Err(errs) => {
                     dbg!(errs);
-                    return;
+                    return 1;
                 }
             };
 
             std::fs::write("typed_ast.ron", format!("{:#?}", &typechecked)).ok();
 
-            interpret::interpret(typechecked);
+            let result = interpret::interpret(typechecked);
+            match &result {
+                Ok(Value::Integer(_)) | Ok(Value::Unit) | Err(RuntimeError::Exit(_)) => {}
+                Ok(v) => println!("{}", v),
+                Err(e) => eprintln!("runtime error: {}", e),
+            }
+            interpret::exit_code(&result)
         })
         .unwrap();
 
-    runner.join().unwrap()
+    let code = runner.join().unwrap();
+    std::process::exit(code)
 }
@@ -6,10 +6,10 @@ pub use recdec::Parser;
 pub use scanner::Scanner;
 pub use token::Token;
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub struct Position(pub u32, pub u32);
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Span(pub Position, pub Position);
 
 impl Span {
@@ -6,6 +6,8 @@ pub enum ParsingError {
     UnexpectedEndOfInput,
     UnexpectedToken(Spanned<Token>, Option<Token>),
     ExpectedFunctionType(Ty),
+    /// an integer literal too large for an Int, at least without a minus before it
+    IntegerOutOfRange(Span),
 }
 
 #[derive(Debug)]
@@ -357,8 +359,20 @@ impl Parser<'_> {
         }
     }
 
+    /// `a; b; c` binds looser than anything else and evaluates to `c`
     pub fn parse_expr(&mut self) -> Result<Expr, ParsingError> {
-        self.parse_expr_bp(0)
+        let first = self.parse_expr_bp(0)?;
+
+        if let Some(Spanned(Token::Semicolon, _)) = self.peek() {
+            let mut exprs = vec![first];
+            while self.maybe_expect(&Token::Semicolon).is_some() {
+                exprs.push(self.parse_expr_bp(0)?);
+            }
+
+            Ok(Expr::Sequence(exprs))
+        } else {
+            Ok(first)
+        }
     }
 
     pub fn parse_expr_bp(&mut self, min_bp: u8) -> Result<Expr, ParsingError> {
@@ -366,49 +380,143 @@ impl Parser<'_> {
         let mut lhs = match self.expect_next()? {
             Spanned(Token::Identifier(i), span) => Expr::Symbol(Spanned(i.clone(), *span)),
             Spanned(Token::Let, span) => {
-                let ident = self.expect_identifier()?;
+                let pattern = self.parse_pattern()?;
+                // `let f a b = e` binds f to `\a b -> e`
+                let mut params = Vec::new();
+                if let Pattern::Binding(_) = pattern {
+                    while let Some(p) = self.maybe_expect_identifier() {
+                        params.push(p);
+                    }
+                }
                 self.expect_token(Token::Equals)?;
-                let bind_val = self.parse_expr()?;
+                let mut bind_val = self.parse_expr()?;
+                for p in params.into_iter().rev() {
+                    bind_val = Expr::Lambda(p, box bind_val);
+                }
                 let body = self.parse_expr()?;
-                return Ok(Expr::LetBinding(ident, box bind_val, box body));
+                return Ok(Expr::LetBinding(pattern, box bind_val, box body));
             }
             Spanned(Token::If, _) => {
                 let cond = self.parse_expr()?;
                 self.expect_token(Token::Then)?;
                 let consequent = self.parse_expr()?;
-                self.expect_token(Token::Else)?;
+                if self.maybe_expect(&Token::Else).is_none() {
+                    // without an else, `if c then a; b` runs `b` either way
+                    return Ok(match consequent {
+                        Expr::Sequence(mut exprs) => {
+                            let rest = exprs.split_off(1);
+                            let if_then = Expr::IfThen(box cond, box exprs.pop().unwrap());
+                            Expr::Sequence(std::iter::once(if_then).chain(rest).collect())
+                        }
+                        consequent => Expr::IfThen(box cond, box consequent),
+                    });
+                }
                 let alternate = self.parse_expr()?;
 
                 Expr::Conditional(box cond, box consequent, box alternate)
             }
+            Spanned(Token::While, _) => {
+                let cond = self.parse_expr()?;
+                self.expect_token(Token::Do)?;
+                let body = self.parse_expr()?;
+
+                Expr::While(box cond, box body)
+            }
             Spanned(Token::Match, _) => {
                 let expr = self.parse_expr()?;
                 self.expect_token(Token::With)?;
 
                 let mut arms = Vec::new();
                 while let Some(tpipe) = self.maybe_expect(&Token::Pipe) {
-                    let variant = self.expect_identifier()?;
-                    let binding = self.maybe_expect_identifier();
+                    let pattern = self.parse_pattern()?;
 
                     self.expect_token(Token::Minus)?;
                     self.expect_token(Token::Greater)?;
 
                     let body = self.parse_expr()?;
-                    arms.push((variant, binding, body));
+                    arms.push((pattern, body));
                 }
                 self.maybe_expect(&Token::End);
 
                 return Ok(Expr::Match(box expr, arms));
             }
+            Spanned(Token::Try, _) => {
+                let body = self.parse_expr()?;
+                self.expect_token(Token::With)?;
+                let failure = self.expect_identifier()?;
+                self.expect_token(Token::Minus)?;
+                self.expect_token(Token::Greater)?;
+                let handler = self.parse_expr()?;
+
+                return Ok(Expr::Try(box body, box Expr::Lambda(failure, box handler)));
+            }
+            // `-5` is a negation like any other, except for i64::MIN whose
+            // magnitude only exists as the scanner's wrapped 2^63
+            Spanned(Token::Minus, span) => {
+                let span = *span;
+
+                match self.peek() {
+                    Some(Spanned(Token::IntegerLiteral(i64::MIN), literal_span)) => {
+                        let literal_span = *literal_span;
+                        self.expect_next()?;
+                        Expr::IntegerLiteral(Spanned(i64::MIN, span.encompass(literal_span)))
+                    }
+                    _ => {
+                        let op = Spanned(UnaryOperator::UnOpNeg, span);
+                        let operand = self.parse_expr_bp(Self::PREFIX_BINDING_POWER)?;
+
+                        Expr::UnaryOp(op, box operand)
+                    }
+                }
+            }
+            Spanned(Token::Bang, span) => {
+                let op = Spanned(UnaryOperator::UnOpNot, *span);
+                let operand = self.parse_expr_bp(Self::PREFIX_BINDING_POWER)?;
+
+                Expr::UnaryOp(op, box operand)
+            }
             Spanned(Token::BackSlash, _) => {
-                let p = self.expect_identifier()?;
+                // `\a b -> e` is curried into `\a -> \b -> e`
+                let mut params = vec![self.expect_identifier()?];
+                while let Some(p) = self.maybe_expect_identifier() {
+                    params.push(p);
+                }
                 self.expect_token(Token::Minus)?;
                 self.expect_token(Token::Greater)?;
-                let body = self.parse_expr()?;
 
-                return Ok(Expr::Lambda(p, box body));
+                let mut body = self.parse_expr()?;
+                for p in params.into_iter().rev() {
+                    body = Expr::Lambda(p, box body);
+                }
+
+                return Ok(body);
             }
             Spanned(Token::LeftBrace, _) => {
+                let is_literal = match (self.peek(), self.peek2()) {
+                    (Some(Spanned(Token::Identifier(_), _)), Some(Spanned(Token::Colon, _))) => {
+                        true
+                    }
+                    _ => false,
+                };
+
+                if !is_literal {
+                    // { base with field = value, ... }
+                    let base = self.parse_expr()?;
+                    self.expect_token(Token::With)?;
+                    let updates = self.parse_punctuated_list(
+                        |p| {
+                            let i = p.expect_identifier()?;
+                            p.expect_token(Token::Equals)?;
+                            let e = p.parse_expr()?;
+                            Ok((i, e))
+                        },
+                        Token::Comma,
+                    )?;
+                    self.expect_token(Token::RightBrace)?;
+
+                    return Ok(Expr::RecordUpdate(box base, updates));
+                }
+
                 let fields = self.parse_punctuated_list(
                     |p| {
                         let i = p.expect_identifier()?;
@@ -425,14 +533,25 @@ impl Parser<'_> {
             Spanned(Token::StringLiteral(i), span) => {
                 Expr::StringLiteral(Spanned(i.clone(), *span))
             }
+            // only a minus in front makes the wrapped 2^63 from the scanner valid
+            Spanned(Token::IntegerLiteral(i64::MIN), span) => {
+                return Err(ParsingError::IntegerOutOfRange(*span))
+            }
             Spanned(Token::IntegerLiteral(i), span) => {
                 Expr::IntegerLiteral(Spanned(i.clone(), *span))
             }
+            Spanned(Token::FloatLiteral(f), span) => Expr::FloatLiteral(Spanned(*f, *span)),
             Spanned(Token::True, span) => Expr::BooleanLiteral(Spanned(true, *span)),
             Spanned(Token::False, span) => Expr::BooleanLiteral(Spanned(false, *span)),
-            Spanned(Token::LeftBracket, _) => {
-                self.expect_token(Token::RightBracket)?;
-                Expr::ListConstructor()
+            Spanned(Token::LeftBracket, span) => {
+                let span = *span;
+                if let Some(Spanned(_, end_span)) = self.maybe_expect(&Token::RightBracket) {
+                    Expr::ListConstructor(Spanned(vec![], span.encompass(*end_span)))
+                } else {
+                    let exprs = self.parse_punctuated_list(|p| p.parse_expr(), Token::Comma)?;
+                    let end_span = self.expect_token(Token::RightBracket)?.1;
+                    Expr::ListConstructor(Spanned(exprs, span.encompass(end_span)))
+                }
             }
             Spanned(Token::LeftParen, span) => {
                 let span = *span;
@@ -466,10 +585,23 @@ impl Parser<'_> {
                     lhs = Expr::FieldAccess(box lhs, field);
                     continue;
                 }
+                // binds looser than application, so `f x?` is `(f x)?`
+                Some(Spanned(Token::Question, span)) => {
+                    if min_bp > 10 {
+                        break;
+                    }
+
+                    let span = *span;
+                    self.expect_next()?;
+                    lhs = Expr::Propagate(box lhs, span);
+                    continue;
+                }
                 Some(Spanned(Token::LeftParen, span))
+                | Some(Spanned(Token::LeftBracket, span))
                 | Some(Spanned(Token::Identifier(_), span))
                 | Some(Spanned(Token::StringLiteral(_), span))
                 | Some(Spanned(Token::IntegerLiteral(_), span))
+                | Some(Spanned(Token::FloatLiteral(_), span))
                 | Some(Spanned(Token::True, span))
                 | Some(Spanned(Token::False, span)) => {
                     if min_bp > 10 || self.last_consumed.unwrap().1 .0 .0 != span.0 .0 {
@@ -511,6 +643,9 @@ impl Parser<'_> {
                     Token::And => Operator::BinOpAnd,
                     Token::Or => Operator::BinOpOr,
                     Token::Percent => Operator::BinOpMod,
+                    Token::Caret => Operator::BinOpPow,
+                    Token::ShiftLeft => Operator::BinOpShl,
+                    Token::ShiftRight => Operator::BinOpShr,
                     _ => Err(ParsingError::UnexpectedToken(t, None))?,
                 },
                 box lhs,
@@ -553,6 +688,7 @@ impl Parser<'_> {
             Spanned(Token::Float, _) => Ok(Ty::Float),
             Spanned(Token::String, _) => Ok(Ty::String),
             Spanned(Token::Bool, _) => Ok(Ty::Bool),
+            Spanned(Token::Char, _) => Ok(Ty::Char),
             Spanned(Token::Self_, span) => Ok(Ty::TypeRef(Spanned("Self".into(), span), None)),
             Spanned(Token::Identifier(i), span) => {
                 let attr = if self.maybe_expect(&Token::Less).is_some() {
@@ -568,6 +704,7 @@ impl Parser<'_> {
                     match self.peek() {
                         Some(Spanned(Token::Minus, _))
                         | Some(Spanned(Token::Comma, _))
+                        | Some(Spanned(Token::LeftBracket, _))
                         | Some(Spanned(Token::RightParen, _)) => break,
                         Some(Spanned(_, next_span))
                             if self.last_consumed.unwrap().1 .0 .0 == next_span.0 .0 =>
@@ -589,25 +726,84 @@ impl Parser<'_> {
     }
 
     pub fn parse_type(&mut self) -> Result<Ty, ParsingError> {
-        let lhs = self.parse_type_atom()?;
+        let mut lhs = self.parse_type_atom()?;
+
+        while let (Some(Spanned(Token::LeftBracket, _)), Some(Spanned(Token::RightBracket, _))) =
+            (self.peek(), self.peek2())
+        {
+            self.expect_token(Token::LeftBracket)?;
+            self.expect_token(Token::RightBracket)?;
+            lhs = Ty::List(box lhs);
+        }
 
         if self.maybe_expect(&Token::Minus).is_some() {
             self.expect_token(Token::Greater)?;
 
             Ok(Ty::Func(box lhs, box self.parse_type()?))
-        } else if let Some(Spanned(Token::LeftBracket, _)) = self.peek() {
-            if let Some(Spanned(Token::RightBracket, _)) = self.peek2() {
-                self.expect_token(Token::LeftBracket)?;
-                self.expect_token(Token::RightBracket)?;
-                Ok(Ty::List(box lhs))
-            } else {
-                Ok(lhs)
-            }
         } else {
             Ok(lhs)
         }
     }
 
+    /// `_` and lowercase names match anything, capitalized names are variants
+    /// which may be followed by a pattern for their payload
+    pub fn parse_pattern(&mut self) -> Result<Pattern, ParsingError> {
+        match self.peek() {
+            Some(Spanned(Token::Identifier(i), _)) if i.starts_with(char::is_uppercase) => {
+                let variant = self.expect_identifier()?;
+
+                let payload = match self.peek() {
+                    Some(Spanned(Token::Identifier(_), _)) | Some(Spanned(Token::LeftParen, _)) => {
+                        Some(box self.parse_pattern_atom()?)
+                    }
+                    _ => None,
+                };
+
+                Ok(Pattern::Variant(variant, payload))
+            }
+            _ => self.parse_pattern_atom(),
+        }
+    }
+
+    pub fn parse_pattern_atom(&mut self) -> Result<Pattern, ParsingError> {
+        match self.expect_next()?.clone() {
+            Spanned(Token::Identifier(i), span) if i == "_" => Ok(Pattern::Wildcard(span)),
+            Spanned(Token::Identifier(i), span) if i.starts_with(char::is_lowercase) => {
+                Ok(Pattern::Binding(Spanned(i, span)))
+            }
+            Spanned(Token::Identifier(i), span) => Ok(Pattern::Variant(Spanned(i, span), None)),
+            Spanned(Token::LeftParen, _) => {
+                let members = self.parse_punctuated_list(|p| p.parse_pattern(), Token::Comma)?;
+                self.expect_token(Token::RightParen)?;
+
+                if members.len() == 1 {
+                    Ok(members[0].clone())
+                } else {
+                    Ok(Pattern::Tuple(members))
+                }
+            }
+            Spanned(Token::LeftBrace, from) => {
+                // { field: pattern, field } where a lone field binds its own name
+                let fields = self.parse_punctuated_list(
+                    |p| {
+                        let field = p.expect_identifier()?;
+                        let pattern = if p.maybe_expect(&Token::Colon).is_some() {
+                            p.parse_pattern()?
+                        } else {
+                            Pattern::Binding(field.clone())
+                        };
+                        Ok((field, pattern))
+                    },
+                    Token::Comma,
+                )?;
+                let to = self.expect_token(Token::RightBrace)?.1;
+
+                Ok(Pattern::Record(from.encompass(to), fields))
+            }
+            t => Err(ParsingError::UnexpectedToken(t, None)),
+        }
+    }
+
     pub fn parse_punctuated_list<T>(
         &mut self,
         f: ParseFn<T>,
@@ -626,14 +822,22 @@ impl Parser<'_> {
         Ok(out)
     }
 
+    /// binds tighter than any infix operator, but looser than application
+    const PREFIX_BINDING_POWER: u8 = 9;
+
     pub fn infix_binding_power(t: &Token) -> Option<(u8, u8)> {
         match t {
+            // right associative and tighter than a prefix minus, so -2 ^ 2 == -4
+            &Token::Caret => Some((10, 9)),
             &Token::Star => Some((6, 7)),
             &Token::Slash => Some((6, 7)),
             &Token::Plus => Some((4, 5)),
             &Token::Minus => Some((4, 5)),
             &Token::Percent => Some((4, 5)),
 
+            &Token::ShiftLeft => Some((3, 4)),
+            &Token::ShiftRight => Some((3, 4)),
+
             &Token::Less => Some((2, 3)),
             &Token::LessEq => Some((2, 3)),
             &Token::Greater => Some((2, 3)),
@@ -646,3 +850,33 @@ impl Parser<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval_string, interpret::Value, Error};
+
+    fn eval_int(src: &str) -> i64 {
+        match eval_string(src) {
+            Ok(Value::Integer(i)) => i,
+            r => panic!("{} didn't evaluate to an integer: {:?}", src, r),
+        }
+    }
+
+    #[test]
+    fn negative_literals() {
+        assert_eq!(eval_int("main () = -5\n"), -5);
+        assert_eq!(eval_int("main () = 0 - 5\n"), -5);
+        assert_eq!(eval_int("main () = 3 - -5\n"), 8);
+        assert_eq!(eval_int("main () = -9223372036854775808\n"), i64::MIN);
+        assert_eq!(eval_int("main () = 9223372036854775807\n"), i64::MAX);
+    }
+
+    #[test]
+    fn out_of_range_literals_are_rejected() {
+        match eval_string("main () = 9223372036854775808\n") {
+            Err(Error::Parsing(ParsingError::IntegerOutOfRange(_))) => (),
+            r => panic!("expected the literal to be out of range, got {:?}", r),
+        }
+    }
+}
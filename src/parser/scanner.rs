@@ -11,6 +11,7 @@ pub enum ScanningProduct {
 pub enum ScanningError {
     UnexpectedCharacter(Spanned<char>),
     InvalidLiteral(Spanned<()>),
+    InvalidEscape(Spanned<char>),
     UnexpectedEndOfFile,
 }
 
@@ -85,14 +86,18 @@ impl<I: Iterator<Item = char>> Scanner<I> {
             "Self" => Some(Token::Self_),
             "impl" => Some(Token::Impl),
             "match" => Some(Token::Match),
+            "try" => Some(Token::Try),
             "with" => Some(Token::With),
             "Int" => Some(Token::Int),
             "Float" => Some(Token::Float),
             "Bool" => Some(Token::Bool),
+            "Char" => Some(Token::Char),
             "true" => Some(Token::True),
             "false" => Some(Token::False),
             "String" => Some(Token::String),
             "for" => Some(Token::For),
+            "while" => Some(Token::While),
+            "do" => Some(Token::Do),
             "and" => Some(Token::And),
             "or" => Some(Token::Or),
             _ => None,
@@ -142,19 +147,28 @@ impl<I: Iterator<Item = char>> Scanner<I> {
             '"' => {
                 let mut string = String::new();
                 loop {
+                    let escape_from = self.position();
                     match self.advance() {
                         Some('"') => break,
                         Some('\\') => match self.advance() {
                             Some('\\') => string.push('\\'),
+                            Some('"') => string.push('"'),
                             Some('r') => string.push('\r'),
                             Some('n') => string.push('\n'),
                             Some('t') => string.push('\t'),
-                            Some(c) => Err(ScanningError::UnexpectedCharacter(Spanned(
+                            Some('0') => string.push('\0'),
+                            Some(c) => Err(ScanningError::InvalidEscape(Spanned(
                                 c,
-                                Span(from, to),
+                                Span(escape_from, self.position()),
                             )))?,
                             None => Err(ScanningError::UnexpectedEndOfFile)?,
                         },
+                        // string literals may span several lines
+                        Some('\n') => {
+                            string.push('\n');
+                            self.line += 1;
+                            self.offset = 0;
+                        }
                         Some(c) => string.push(c),
                         None => Err(ScanningError::UnexpectedEndOfFile)?,
                     }
@@ -167,7 +181,16 @@ impl<I: Iterator<Item = char>> Scanner<I> {
             }
             '*' => tok(Token::Star),
             ',' => tok(Token::Comma),
+            ';' => tok(Token::Semicolon),
             '.' => tok(Token::Dot),
+            '<' if peeked == Some('<') => {
+                self.advance();
+                tok(Token::ShiftLeft)
+            }
+            '>' if peeked == Some('>') => {
+                self.advance();
+                tok(Token::ShiftRight)
+            }
             '<' if peeked == Some('=') => {
                 self.advance();
                 tok(Token::LessEq)
@@ -184,6 +207,9 @@ impl<I: Iterator<Item = char>> Scanner<I> {
             '>' => tok(Token::Greater),
             '=' => tok(Token::Equals),
             '%' => tok(Token::Percent),
+            '^' => tok(Token::Caret),
+            '!' => tok(Token::Bang),
+            '?' => tok(Token::Question),
 
             '\n' => {
                 self.line += 1;
@@ -253,13 +279,51 @@ impl<I: Iterator<Item = char>> Scanner<I> {
             }
         } else {
             let to = self.position();
-            match text.parse::<i64>() {
-                Ok(i) => Ok(ScanningProduct::Token(Spanned(
-                    Token::IntegerLiteral(i),
+            // literals are never negative, but 2^63 has to get through to be
+            // negated into i64::MIN, the parser rejects it anywhere else
+            match text.parse::<u64>() {
+                Ok(i) if i <= 1 << 63 => Ok(ScanningProduct::Token(Spanned(
+                    Token::IntegerLiteral(i as i64),
                     Span(from, to),
                 ))),
-                Err(_) => Err(ScanningError::InvalidLiteral(Spanned((), Span(from, to)))),
+                _ => Err(ScanningError::InvalidLiteral(Spanned((), Span(from, to)))),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval_string, interpret::Value};
+
+    /// the string `src`'s main returns
+    fn string(src: &str) -> String {
+        match eval_string(src) {
+            Ok(Value::String(s)) => s.to_string(),
+            r => panic!("{} didn't return a string: {:?}", src, r),
+        }
+    }
+
+    #[test]
+    fn escapes_are_decoded() {
+        let s = string("main () = \"a\\nb\"\n");
+        assert_eq!(s.as_bytes(), b"a\nb");
+
+        let s = string("main () = \"\\t\\\"q\\\"\\\\\"\n");
+        assert_eq!(s, "\t\"q\"\\");
+    }
+
+    #[test]
+    fn strings_may_span_lines() {
+        assert_eq!(string("main () = \"one\ntwo\"\n"), "one\ntwo");
+    }
+
+    #[test]
+    fn invalid_escapes_are_rejected() {
+        match Scanner::new("\"a\\qb\"".chars()).scan_all() {
+            Err(ScanningError::InvalidEscape(Spanned('q', _))) => (),
+            r => panic!("expected an invalid escape, got {:?}", r),
+        }
+    }
+}
@@ -11,11 +11,14 @@ pub enum Token {
     End,
     Impl,
     Match,
+    Try,
     With,
     Of,
     TypeClass,
     Self_,
     For,
+    While,
+    Do,
     String,
 
     Int,
@@ -31,6 +34,7 @@ pub enum Token {
     Equals,
     EqualsEquals,
     Comma,
+    Semicolon,
 
     Tick,
     Plus,
@@ -46,12 +50,18 @@ pub enum Token {
     And,
     Or,
     Percent,
+    Caret,
+    ShiftLeft,
+    ShiftRight,
+    Bang,
+    Question,
 
     Void,
     Return,
     True,
     False,
     Bool,
+    Char,
 
     Identifier(String),
     FloatLiteral(f64),
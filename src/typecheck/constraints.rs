@@ -82,7 +82,7 @@ impl TypeSet {
         }
     }
 
-    pub fn union(&mut self, a: usize, b: usize) {
+    pub fn union(&mut self, a: usize, b: usize) -> Result<(), TypeCheckingError> {
         if self.elements.len() <= a.max(b) {
             self.elements.resize(a.max(b) + 1, None);
         }
@@ -92,15 +92,20 @@ impl TypeSet {
 
         // a and b are already the same set
         if a == b {
-            return;
+            return Ok(());
         }
 
         let ae = self.elements[a].as_ref().unwrap();
         let be = self.elements[b].as_ref().unwrap();
 
         let t = match (&ae.ty, &be.ty) {
+            (Some(t1), Some(t2)) if t1 == t2 => Some(t1.clone()),
             (Some(t1), Some(t2)) => {
-                panic!()
+                return Err(TypeCheckingError::TypeMismatch(
+                    Span::empty(),
+                    t1.clone(),
+                    Some(t2.clone()),
+                ))
             }
             (Some(e), None) | (None, Some(e)) => Some(e.clone()),
             (None, None) => None,
@@ -109,12 +114,13 @@ impl TypeSet {
         self.elements[a].as_mut().unwrap().ty = t;
         self.elements[b].as_mut().unwrap().ty = None;
         self.elements[b].as_mut().unwrap().parent = Some(a);
+        Ok(())
     }
 
     fn c_param_is_type(&mut self, p: usize, t: Type) -> Result<(), TypeCheckingError> {
         match &t {
             Type::TypeVariable(v) => {
-                self.union(p, *v as usize);
+                self.union(p, *v as usize)?;
             }
             Type::ErrType => {
                 panic!()
@@ -4,8 +4,8 @@ use crate::{
         typed::*,
         untyped::{self, Declaration, Ty},
     },
-    parser::Span,
-    parser::Spanned,
+    builtins::{Builtin, BuiltinRegistry},
+    parser::{Parser, Position, Scanner, Span, Spanned},
 };
 
 use std::cell::RefCell;
@@ -24,7 +24,8 @@ pub enum TypeCheckingError {
     TypeMismatch(Span, Type, Option<Type>),
     IllegalFieldAccess(Spanned<String>, String),
     ExpectedFunctionType(Span),
-    UnknownSymbol(Spanned<String>),
+    /// a symbol nothing defines, with the closest name that is defined if any is close
+    UnknownSymbol(Spanned<String>, Option<String>),
     IllegalAttributeLocation(Span),
     GenericError(String, Span),
     CompoundError(Vec<TypeCheckingError>),
@@ -62,6 +63,9 @@ pub struct TypecheckingContext {
     pub environment: Rc<RefCell<TypeEnvironment>>,
     pub symbols: HashMap<String, Type>,
     variant: Option<String>,
+    /// the return types of the functions being checked, innermost last,
+    /// `ErrType` where it is still being inferred
+    return_types: Vec<Type>,
 }
 
 impl TypecheckingContext {
@@ -70,6 +74,7 @@ impl TypecheckingContext {
             environment: Rc::new(RefCell::new(TypeEnvironment::default())),
             symbols: HashMap::new(),
             variant: None,
+            return_types: Vec::new(),
         }
     }
 
@@ -105,6 +110,38 @@ impl TypecheckingContext {
 #[derive(Debug, Clone)]
 pub struct TypeChecked {
     pub environment: Rc<RefCell<TypeEnvironment>>,
+    pub builtins: Rc<BuiltinRegistry>,
+}
+
+/// The candidate with the smallest edit distance to `name`, if it is close enough
+/// to plausibly be what was meant. Ties go to the alphabetically first name.
+pub fn closest_name<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<String> {
+    fn edit_distance(a: &str, b: &str) -> usize {
+        let b = b.chars().collect::<Vec<_>>();
+        let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+        for (i, ca) in a.chars().enumerate() {
+            let mut diagonal = row[0];
+            row[0] = i + 1;
+            for (j, cb) in b.iter().enumerate() {
+                let substitution = diagonal + (ca != *cb) as usize;
+                diagonal = row[j + 1];
+                row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+            }
+        }
+
+        row[b.len()]
+    }
+
+    // short names are too close to everything for a suggestion to mean much
+    let max_distance = name.chars().count() / 3;
+
+    candidates
+        .filter(|c| *c != name && c.starts_with(char::is_alphabetic))
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .min()
+        .map(|(_, c)| c.to_owned())
 }
 
 fn check_type(
@@ -130,8 +167,21 @@ fn check_type(
             .map(|((cond, cons), alt)| {
                 (ExprT::Conditional(box cond, box cons, box alt), ty.clone())
             }),
+        Expr::Sequence(exprs) => {
+            let (last, effects) = exprs.split_last().unwrap();
+
+            effects
+                .iter()
+                .map(|e| infer_type(ctx, e))
+                .collect::<TypeJudgement<Vec<_>>>()
+                .and_still(|| check_type(ctx, last, ty))
+                .map(|(mut exprs, last)| {
+                    exprs.push(last);
+                    (ExprT::Sequence(exprs), ty.clone())
+                })
+        }
         Expr::Tuple(exprs) => match tc {
-            TypeConstructor::Tuple(n) if ty_params.len() == *n => {
+            TypeConstructor::Tuple(n) if ty_params.len() == *n && exprs.len() == *n => {
                 let exprs = exprs
                     .iter()
                     .zip(ty_params.iter())
@@ -146,83 +196,29 @@ fn check_type(
                 infer_type(ctx, expr).map(|(_, t)| t).ok(),
             )),
         },
+        Expr::ListConstructor(elems) => match tc {
+            TypeConstructor::List if ty_params.len() == 1 => elems
+                .iter()
+                .map(|e| check_type(ctx, e, &ty_params[0]))
+                .collect::<TypeJudgement<_>>()
+                .map(|elems| (ExprT::ListLiteral(elems), ty.clone())),
+            _ => TypeJudgement::Error(TypeCheckingError::TypeMismatch(
+                expr.span(),
+                ty.clone(),
+                infer_type(ctx, expr).map(|(_, t)| t).ok(),
+            )),
+        },
         Expr::Match(matchee, arms) => {
             // introducing new constraints here is illegal
             // EDIT: I don't think this is true?
-            let ((matchee_te, matched_ty), _) = infer_type(ctx, matchee)?;
+            let ((matchee_te, matched_ty), _) = infer_matchee(ctx, matchee)?;
 
-            let ((matched_tc, matched_ty_params), _) = match matched_ty {
-                Type::ErrType => TypeCheckingError::ExprHasErrorType(matchee.span()).as_judgement(),
-                Type::TypeVariable(_) => TypeCheckingError::GenericError(
-                    "cannot match on generic type parameter".to_owned(),
-                    matchee.span(),
+            check_match_arms(ctx, arms, &matched_ty, ty).map(|t_arms| {
+                (
+                    ExprT::MatchSum(box (matchee_te, matched_ty), t_arms, expr.span()),
+                    ty.clone(),
                 )
-                .as_judgement(),
-                Type::ConstructedType(ref mtc, ref mtys) => {
-                    TypeJudgement::new((mtc.clone(), mtys.clone()))
-                }
-            }?;
-
-            match &matched_tc {
-                TypeConstructor::UserType(i) => {
-                    let t = ctx.environment.borrow().types[i.index].clone();
-                    if let TypeDefinition::Sum {
-                        variants,
-                        qualified_name,
-                        ..
-                    } = t
-                    {
-                        let mut t_arms = Vec::new();
-                        for (variant, binding, body) in arms {
-                            if let Some((i, (vn, vt))) = variants
-                                .iter()
-                                .enumerate()
-                                .find(|(_, (vn, _))| vn == &variant.0)
-                            {
-                                binding.iter().for_each(|binding| {
-                                    ctx.symbols.insert(binding.0.clone(), vt.clone());
-                                });
-
-                                t_arms.push(
-                                    check_type(ctx, body, ty)
-                                        .map(|t| (i, binding.clone().map(|s| s.0), t)),
-                                );
-
-                                binding.iter().for_each(|binding| {
-                                    ctx.symbols.remove(&binding.0.clone());
-                                });
-                            } else {
-                                return TypeJudgement::Error(TypeCheckingError::GenericError(
-                                    format!(
-                                        "variant {} does not exist on type {}",
-                                        &variant.0, qualified_name
-                                    ),
-                                    variant.1,
-                                ));
-                            }
-                        }
-
-                        t_arms
-                            .into_iter()
-                            .collect::<TypeJudgement<_>>()
-                            .map(|t_arms| {
-                                (
-                                    ExprT::MatchSum(box (matchee_te, matched_ty), t_arms),
-                                    ty.clone(),
-                                )
-                            })
-                    } else {
-                        TypeJudgement::Error(TypeCheckingError::GenericError(
-                            "matching on this type is not possible".into(),
-                            matchee.span(),
-                        ))
-                    }
-                }
-                _ => TypeJudgement::Error(TypeCheckingError::GenericError(
-                    "matching on this type is not possible".into(),
-                    matchee.span(),
-                )),
-            }
+            })
         }
         Expr::Application(lhs, exprs) => infer_application(ctx, (lhs, exprs))
             .then(|(e, t)| unify_types(expr.span(), t.clone(), ty.clone()))
@@ -233,13 +229,15 @@ fn check_type(
                 let (a, b) = (&ty_params[0], &ty_params[1]);
 
                 ctx.symbols.insert(p.0.clone(), a.clone());
+                ctx.return_types.push(b.clone());
                 let rhs = check_type(ctx, e, b);
+                ctx.return_types.pop();
                 ctx.symbols.remove(&p.0);
-                rhs.map(|rhs| (ExprT::Lambda(p.0.clone(), box rhs), ty.clone()))
+                rhs.map(|rhs| (ExprT::lambda(p.0.clone(), rhs), ty.clone()))
             }
             _ => TypeJudgement::Error(TypeCheckingError::TypeMismatch(e.span(), ty.clone(), None)),
         },
-        Expr::LetBinding(binding, rhs, body) => {
+        Expr::LetBinding(pattern, rhs, body) => {
             let rhs = infer_type(ctx, rhs);
             let mut rhs_t = Type::ErrType;
             let rhs = rhs.map(|(e, t)| {
@@ -247,18 +245,10 @@ fn check_type(
                 (e, t)
             });
 
-            rhs.and_still(|| {
-                ctx.symbols.insert(binding.0.clone(), rhs_t);
-                let body = check_type(ctx, body, ty);
-                ctx.symbols.remove(&binding.0);
-                body
-            })
-            .map(|(rhs, body)| {
-                (
-                    ExprT::LetBinding(binding.0.clone(), box rhs, box body),
-                    ty.clone(),
-                )
-            })
+            rhs.and_still(|| check_let_body(ctx, pattern, &rhs_t, |ctx| check_type(ctx, body, ty)))
+                .map(|(rhs, (pattern, body))| {
+                    (ExprT::LetBinding(pattern, box rhs, box body), ty.clone())
+                })
         }
         Expr::Record(rc) => match tc {
             TypeConstructor::UserType(i) => {
@@ -277,7 +267,10 @@ fn check_type(
                                     if let Some(f) =
                                         rc.iter().find(|(name, _e)| &name.0 == &field.0)
                                     {
-                                        sorted_fields.push(check_type(ctx, &f.1, &field.1));
+                                        sorted_fields.push(
+                                            check_type(ctx, &f.1, &field.1)
+                                                .map(|e| (field.0.clone(), e)),
+                                        );
                                     } else {
                                         continue 'records;
                                     }
@@ -308,7 +301,9 @@ fn check_type(
                         let mut sorted_fields = Vec::new();
                         for field in fields.iter() {
                             if let Some(f) = rc.iter().find(|(name, _e)| &name.0 == &field.0) {
-                                sorted_fields.push(check_type(ctx, &f.1, &field.1));
+                                sorted_fields.push(
+                                    check_type(ctx, &f.1, &field.1).map(|e| (field.0.clone(), e)),
+                                );
                             } else {
                                 return TypeJudgement::Error(TypeCheckingError::GenericError(
                                     format!(
@@ -344,10 +339,254 @@ fn check_type(
     }
 }
 
+/// Checks `pattern` against the type of the value it destructures,
+/// collecting the names it binds along with their types
+fn check_pattern(
+    ctx: &TypecheckingContext,
+    pattern: &untyped::Pattern,
+    ty: &Type,
+    bindings: &mut Vec<(String, Type)>,
+) -> Result<PatternT, TypeCheckingError> {
+    use untyped::Pattern;
+
+    match pattern {
+        Pattern::Wildcard(_) => Ok(PatternT::Wildcard),
+        Pattern::Binding(name) => {
+            if bindings.iter().any(|(n, _)| n == &name.0) {
+                return Err(TypeCheckingError::GenericError(
+                    format!("{} is bound more than once in this pattern", name.0),
+                    name.1,
+                ));
+            }
+
+            bindings.push((name.0.clone(), ty.clone()));
+            Ok(PatternT::Binding(name.0.clone()))
+        }
+        Pattern::Variant(variant, payload) => {
+            let (th, ty_params) = match ty {
+                Type::ConstructedType(TypeConstructor::UserType(th), ty_params) => (th, ty_params),
+                _ => {
+                    return Err(TypeCheckingError::GenericError(
+                        format!("variant pattern cannot match a value of type {:?}", ty),
+                        pattern.span(),
+                    ))
+                }
+            };
+
+            let t = ctx.environment.borrow().types[th.index].clone();
+            if let TypeDefinition::Sum {
+                variants,
+                qualified_name,
+                ..
+            } = t
+            {
+                if let Some((i, (_, vt))) = variants
+                    .iter()
+                    .enumerate()
+                    .find(|(_, (vn, _))| vn == &variant.0)
+                {
+                    let payload = match payload {
+                        Some(p) => Some(box check_pattern(
+                            ctx,
+                            p,
+                            &vt.substitute(ty_params),
+                            bindings,
+                        )?),
+                        None => None,
+                    };
+
+                    Ok(PatternT::Variant(i, payload))
+                } else {
+                    Err(TypeCheckingError::GenericError(
+                        format!(
+                            "variant {} does not exist on type {}",
+                            &variant.0, qualified_name
+                        ),
+                        variant.1,
+                    ))
+                }
+            } else {
+                Err(TypeCheckingError::GenericError(
+                    "matching on this type is not possible".into(),
+                    pattern.span(),
+                ))
+            }
+        }
+        Pattern::Record(span, fields) => {
+            let (th, ty_params) = match ty {
+                Type::ConstructedType(TypeConstructor::UserType(th), ty_params) => (th, ty_params),
+                _ => {
+                    return Err(TypeCheckingError::GenericError(
+                        format!("record pattern cannot match a value of type {:?}", ty),
+                        *span,
+                    ))
+                }
+            };
+
+            let t = ctx.environment.borrow().types[th.index].clone();
+            if let TypeDefinition::Record {
+                fields: record_fields,
+                ..
+            } = t
+            {
+                fields
+                    .iter()
+                    .map(
+                        |(name, p)| match record_fields.iter().find(|(n, _)| n == &name.0) {
+                            Some((_, ft)) => {
+                                check_pattern(ctx, p, &ft.substitute(ty_params), bindings)
+                                    .map(|p| (name.0.clone(), p))
+                            }
+                            None => Err(TypeCheckingError::IllegalFieldAccess(
+                                Spanned("no such field on record".to_owned(), name.1),
+                                name.0.clone(),
+                            )),
+                        },
+                    )
+                    .collect::<Result<_, _>>()
+                    .map(PatternT::Record)
+            } else {
+                Err(TypeCheckingError::GenericError(
+                    format!("record pattern cannot match a value of type {:?}", ty),
+                    *span,
+                ))
+            }
+        }
+        Pattern::Tuple(patterns) => match ty {
+            Type::ConstructedType(TypeConstructor::Tuple(n), ty_params) if *n == patterns.len() => {
+                patterns
+                    .iter()
+                    .zip(ty_params.iter())
+                    .map(|(p, t)| check_pattern(ctx, p, t, bindings))
+                    .collect::<Result<_, _>>()
+                    .map(PatternT::Tuple)
+            }
+            _ => Err(TypeCheckingError::GenericError(
+                format!("tuple pattern cannot match a value of type {:?}", ty),
+                pattern.span(),
+            )),
+        },
+    }
+}
+
+/// Brings the names bound by the irrefutable `pattern` into scope for `body`
+fn check_let_body(
+    ctx: &mut TypecheckingContext,
+    pattern: &untyped::Pattern,
+    rhs_t: &Type,
+    body: impl FnOnce(&mut TypecheckingContext) -> TypeJudgement<TypedExpr>,
+) -> TypeJudgement<(PatternT, TypedExpr)> {
+    if !pattern.is_irrefutable() {
+        return TypeCheckingError::GenericError(
+            "let bindings can only destructure tuples and records, use match instead".to_owned(),
+            pattern.span(),
+        )
+        .as_judgement();
+    }
+
+    let mut bindings = Vec::new();
+    let pattern = match check_pattern(ctx, pattern, rhs_t, &mut bindings) {
+        Ok(p) => p,
+        Err(e) => return e.as_judgement(),
+    };
+
+    with_bindings(ctx, &bindings, body).map(|body| (pattern, body))
+}
+
+/// Runs `f` with `bindings` in scope, restoring whatever they shadowed afterwards
+fn with_bindings<T>(
+    ctx: &mut TypecheckingContext,
+    bindings: &[(String, Type)],
+    f: impl FnOnce(&mut TypecheckingContext) -> T,
+) -> T {
+    let shadowed = bindings
+        .iter()
+        .map(|(name, t)| (name, ctx.symbols.insert(name.clone(), t.clone())))
+        .collect::<Vec<_>>();
+
+    let result = f(ctx);
+
+    for (name, previous) in shadowed {
+        match previous {
+            Some(t) => ctx.symbols.insert(name.clone(), t),
+            None => ctx.symbols.remove(name),
+        };
+    }
+
+    result
+}
+
+/// Infers the type of the expression a match is on, it has to be known for the
+/// patterns to be checked against it
+fn infer_matchee(
+    ctx: &mut TypecheckingContext,
+    matchee: &untyped::Expr,
+) -> TypeJudgement<TypedExpr> {
+    infer_type(ctx, matchee).map_with_fail(|(matchee_te, matched_ty)| match matched_ty {
+        Type::ErrType => Err(TypeCheckingError::ExprHasErrorType(matchee.span())),
+        Type::TypeVariable(_) => Err(TypeCheckingError::GenericError(
+            "cannot match on generic type parameter".to_owned(),
+            matchee.span(),
+        )),
+        Type::ConstructedType(..) => Ok((matchee_te, matched_ty)),
+    })
+}
+
+/// Checks the patterns of `arms` against the matched type and their bodies
+/// against `ty`
+fn check_match_arms(
+    ctx: &mut TypecheckingContext,
+    arms: &[(untyped::Pattern, untyped::Expr)],
+    matched_ty: &Type,
+    ty: &Type,
+) -> TypeJudgement<Vec<(PatternT, TypedExpr)>> {
+    let mut t_arms = Vec::new();
+    for (pattern, body) in arms {
+        let mut bindings = Vec::new();
+        let pattern = match check_pattern(ctx, pattern, matched_ty, &mut bindings) {
+            Ok(p) => p,
+            Err(e) => return e.as_judgement(),
+        };
+
+        let body = with_bindings(ctx, &bindings, |ctx| check_type(ctx, body, ty));
+        t_arms.push(body.map(|t| (pattern, t)));
+    }
+
+    t_arms.into_iter().collect()
+}
+
+/// Whether values of these types can be compared with `==` element by element
+fn structurally_comparable(a: &Type, b: &Type) -> bool {
+    match (a, b) {
+        (Type::TypeVariable(_), _) | (_, Type::TypeVariable(_)) => true,
+        (Type::ConstructedType(c1, p1), Type::ConstructedType(c2, p2)) if c1 == c2 => {
+            let comparable = match c1 {
+                TypeConstructor::Function => false,
+                TypeConstructor::UserType(th) => matches!(
+                    th.environment.borrow().types[th.index],
+                    TypeDefinition::Record { .. } | TypeDefinition::Sum { .. }
+                ),
+                _ => true,
+            };
+
+            comparable
+                && p1
+                    .iter()
+                    .zip(p2.iter())
+                    .all(|(a, b)| structurally_comparable(a, b))
+        }
+        _ => false,
+    }
+}
+
 fn unify_types(span: Span, a: Type, b: Type) -> TypeJudgement<Type> {
     match (a, b) {
         (Type::ConstructedType(ref c1, ref p1), Type::ConstructedType(ref c2, ref p2)) => {
-            assert_eq!(p1.len(), p2.len());
+            // different constructors may take different numbers of types, that
+            // is reported as a mismatch below
+            if c1 == c2 {
+                assert_eq!(p1.len(), p2.len());
+            }
 
             TypeJudgement::Typed {
                 inner: (),
@@ -389,6 +628,7 @@ fn infer_application(
     (lhs, exprs): (&Expr, &Vec<Expr>),
 ) -> TypeJudgement<TypedExpr> {
     let lspan = lhs.span();
+    let span = exprs.iter().fold(lspan, |s, e| s.encompass(e.span()));
 
     infer_type(ctx, lhs)
         .then(|(e, t)| {
@@ -440,7 +680,7 @@ fn infer_application(
             dbg!(lspan, &constraints);
             ((lhs, (exprs, rt)), constraints)
         })
-        .map(|(lhs, (exprs, rt))| (ExprT::Application(box lhs, exprs), rt.clone()))
+        .map(|(lhs, (exprs, rt))| (ExprT::Application(box lhs, exprs, span), rt.clone()))
 }
 
 fn bump_generic_counters(expr: TypedExpr) -> TypedExpr {
@@ -449,9 +689,9 @@ fn bump_generic_counters(expr: TypedExpr) -> TypedExpr {
     fn bump_in_type(t: &mut Type, v: u32) -> u32 {
         match t {
             Type::TypeVariable(u) => {
-                let o = *u;
                 *u += v;
-                o
+                // the next free counter value
+                *u + 1
             }
             Type::ConstructedType(_, tys) => tys
                 .iter_mut()
@@ -465,10 +705,11 @@ fn bump_generic_counters(expr: TypedExpr) -> TypedExpr {
         use ExprT::*;
 
         match e {
-            Symbol(_)
+            Symbol(..)
             | VariantConstructor(_, _)
             | StringLiteral(_)
             | IntegerLiteral(_)
+            | FloatLiteral(_)
             | BooleanLiteral(_)
             | BuiltInFn(_)
             | Unit => {
@@ -493,6 +734,89 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                 let rt = cons.1.clone();
                 (ExprT::Conditional(box cond, box cons, box alt), rt)
             }),
+        // the first arm decides the type the other arms are checked against
+        Expr::Match(matchee, arms) if !arms.is_empty() => {
+            let ((matchee_te, matched_ty), _) = infer_matchee(ctx, matchee)?;
+
+            let (pattern, body) = &arms[0];
+            let mut bindings = Vec::new();
+            let pattern = match check_pattern(ctx, pattern, &matched_ty, &mut bindings) {
+                Ok(p) => p,
+                Err(e) => return e.as_judgement(),
+            };
+
+            with_bindings(ctx, &bindings, |ctx| infer_type(ctx, body))
+                .then(|(_, t)| check_match_arms(ctx, &arms[1..], &matched_ty, t))
+                .map(|(first, mut t_arms)| {
+                    let t = first.1.clone();
+                    t_arms.insert(0, (pattern, first));
+                    (ExprT::MatchSum(box (matchee_te, matched_ty), t_arms, expr.span()), t)
+                })
+        }
+        Expr::IfThen(cond, cons) => check_type(ctx, cond, &Type::BOOL)
+            .and_still(|| check_type(ctx, cons, &Type::UNIT))
+            .map(|(cond, cons)| (ExprT::IfThen(box cond, box cons), Type::UNIT)),
+        // the body is only run for its effects, so its value is thrown away
+        Expr::While(cond, body) => check_type(ctx, cond, &Type::BOOL)
+            .and_still(|| infer_type(ctx, body))
+            .map(|(cond, body)| (ExprT::While(box cond, box body), Type::UNIT)),
+        Expr::Propagate(operand, span) => infer_type(ctx, operand)
+            .then(|(_, t)| {
+                let result = TypeHandle::prelude(&ctx.environment, "Result");
+                let result_params = |t: &Type| match t {
+                    Type::ConstructedType(TypeConstructor::UserType(th), params)
+                        if *th == result =>
+                    {
+                        Some(params.clone())
+                    }
+                    _ => None,
+                };
+
+                let operand_params = match result_params(t) {
+                    Some(params) => params,
+                    None => {
+                        return TypeCheckingError::GenericError(
+                            format!("? needs a Result, found {:?}", t),
+                            operand.span(),
+                        )
+                        .as_judgement()
+                    }
+                };
+
+                // an Err is returned as is, so the error types have to agree
+                match ctx.return_types.last().and_then(result_params) {
+                    Some(return_params) => {
+                        unify_types(*span, operand_params[1].clone(), return_params[1].clone())
+                            .map(|_| operand_params[0].clone())
+                    }
+                    None => TypeCheckingError::GenericError(
+                        "? can only be used in a function annotated to return a Result".to_owned(),
+                        *span,
+                    )
+                    .as_judgement(),
+                }
+            })
+            .map(|(operand, ok_t)| (ExprT::Propagate(box operand, *span), ok_t)),
+        // the handler gets a Failure, and gives a value in place of the body's
+        Expr::Try(body, handler) => infer_type(ctx, body)
+            .then(|(_, t)| {
+                let raised = Type::TypeVariable(TYPE_GLOBAL_COUNTER.fetch_add(1, Ordering::SeqCst));
+                let failure =
+                    Type::user_type(TypeHandle::prelude(&ctx.environment, "Failure"), vec![raised]);
+                check_type(ctx, handler, &Type::function(failure, t.clone()))
+            })
+            .map(|(body, handler)| {
+                let t = body.1.clone();
+                (ExprT::Try(box body, box handler), t)
+            }),
+        Expr::Sequence(exprs) => exprs
+            .iter()
+            .map(|e| infer_type(ctx, e))
+            .collect::<TypeJudgement<Vec<_>>>()
+            .map(|exprs| {
+                let t = exprs.last().unwrap().1.clone();
+                (ExprT::Sequence(exprs), t)
+            }),
         Expr::Tuple(exprs) => {
             let typed_exprs = exprs
                 .into_iter()
@@ -504,6 +828,25 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                 (ExprT::Tuple(typed_exprs), tuple_type)
             })
         }
+        Expr::ListConstructor(elems) => match elems.split_first() {
+            Some((first, rest)) => infer_type(ctx, first)
+                .then(|(_, t)| {
+                    rest.iter()
+                        .map(|e| check_type(ctx, e, t))
+                        .collect::<TypeJudgement<Vec<_>>>()
+                })
+                .map(|(first, rest)| {
+                    let list_type = Type::list(first.1.clone());
+                    let elems = std::iter::once(first).chain(rest).collect();
+                    (ExprT::ListLiteral(elems), list_type)
+                }),
+            None => TypeJudgement::new((
+                ExprT::ListLiteral(vec![]),
+                Type::list(Type::TypeVariable(
+                    TYPE_GLOBAL_COUNTER.fetch_add(1, Ordering::SeqCst),
+                )),
+            )),
+        },
         Expr::BinaryOp(op, lhs, rhs) => {
             use untyped::Operator;
             infer_type(ctx, lhs)
@@ -516,16 +859,46 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                             | Operator::BinOpAdd
                             | Operator::BinOpSub
                             | Operator::BinOpDiv
-                            | Operator::BinOpMod => {
-                                Ok((ExprT::BinaryOp(*op, box lhs, box rhs), Type::INT))
-                            }
+                            | Operator::BinOpMod
+                            | Operator::BinOpPow
+                            | Operator::BinOpShl
+                            | Operator::BinOpShr => Ok((
+                                ExprT::BinaryOp(*op, box lhs, box rhs, expr.span()),
+                                Type::INT,
+                            )),
                             Operator::BinOpLess
                             | Operator::BinOpLessEq
                             | Operator::BinOpGreater
                             | Operator::BinOpGreaterEq
-                            | Operator::BinOpEquals => {
-                                Ok((ExprT::BinaryOp(*op, box lhs, box rhs), Type::BOOL))
-                            }
+                            | Operator::BinOpEquals => Ok((
+                                ExprT::BinaryOp(*op, box lhs, box rhs, expr.span()),
+                                Type::BOOL,
+                            )),
+                            _ => Err(TypeCheckingError::GenericError(
+                                format!(
+                                    "Binary Operator {:?} is not defined for types {:?}, {:?}",
+                                    *op, lhs.1, rhs.1
+                                ),
+                                expr.span(),
+                            )),
+                        },
+                        Some((TypeConstructor::Float, TypeConstructor::Float)) => match op {
+                            Operator::BinOpMul
+                            | Operator::BinOpAdd
+                            | Operator::BinOpSub
+                            | Operator::BinOpDiv
+                            | Operator::BinOpPow => Ok((
+                                ExprT::BinaryOp(*op, box lhs, box rhs, expr.span()),
+                                Type::FLOAT,
+                            )),
+                            Operator::BinOpLess
+                            | Operator::BinOpLessEq
+                            | Operator::BinOpGreater
+                            | Operator::BinOpGreaterEq
+                            | Operator::BinOpEquals => Ok((
+                                ExprT::BinaryOp(*op, box lhs, box rhs, expr.span()),
+                                Type::BOOL,
+                            )),
                             _ => Err(TypeCheckingError::GenericError(
                                 format!(
                                     "Binary Operator {:?} is not defined for types {:?}, {:?}",
@@ -535,9 +908,31 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                             )),
                         },
                         Some((TypeConstructor::String, TypeConstructor::String)) => match op {
-                            Operator::BinOpEquals => {
-                                Ok((ExprT::BinaryOp(*op, box lhs, box rhs), Type::BOOL))
-                            }
+                            Operator::BinOpLess
+                            | Operator::BinOpLessEq
+                            | Operator::BinOpGreater
+                            | Operator::BinOpGreaterEq
+                            | Operator::BinOpEquals => Ok((
+                                ExprT::BinaryOp(*op, box lhs, box rhs, expr.span()),
+                                Type::BOOL,
+                            )),
+                            _ => Err(TypeCheckingError::GenericError(
+                                format!(
+                                    "Binary Operator {:?} is not defined for types {:?}, {:?}",
+                                    *op, lhs.1, rhs.1
+                                ),
+                                expr.span(),
+                            )),
+                        },
+                        Some((TypeConstructor::Char, TypeConstructor::Char)) => match op {
+                            Operator::BinOpLess
+                            | Operator::BinOpLessEq
+                            | Operator::BinOpGreater
+                            | Operator::BinOpGreaterEq
+                            | Operator::BinOpEquals => Ok((
+                                ExprT::BinaryOp(*op, box lhs, box rhs, expr.span()),
+                                Type::BOOL,
+                            )),
                             _ => Err(TypeCheckingError::GenericError(
                                 format!(
                                     "Binary Operator {:?} is not defined for types {:?}, {:?}",
@@ -547,9 +942,10 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                             )),
                         },
                         Some((TypeConstructor::Bool, TypeConstructor::Bool)) => match op {
-                            Operator::BinOpAnd | Operator::BinOpOr => {
-                                Ok((ExprT::BinaryOp(*op, box lhs, box rhs), Type::BOOL))
-                            }
+                            Operator::BinOpAnd | Operator::BinOpOr => Ok((
+                                ExprT::BinaryOp(*op, box lhs, box rhs, expr.span()),
+                                Type::BOOL,
+                            )),
                             _ => Err(TypeCheckingError::GenericError(
                                 format!(
                                     "Binary Operator {:?} is not defined for types {:?}, {:?}",
@@ -558,6 +954,14 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                                 expr.span(),
                             )),
                         },
+                        _ if matches!(op, Operator::BinOpEquals)
+                            && structurally_comparable(&lhs.1, &rhs.1) =>
+                        {
+                            Ok((
+                                ExprT::BinaryOp(*op, box lhs, box rhs, expr.span()),
+                                Type::BOOL,
+                            ))
+                        }
                         _ => Err(TypeCheckingError::GenericError(
                             format!(
                                 "Binary Operator {:?} is not defined for types {:?}, {:?}",
@@ -568,7 +972,30 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                     }
                 })
         }
-        Expr::LetBinding(binding, rhs, body) => {
+        Expr::UnaryOp(op, operand) => {
+            use untyped::UnaryOperator;
+            infer_type(ctx, operand).map_with_fail(|operand| {
+                match (op.0, operand.1.type_constructor()) {
+                    (UnaryOperator::UnOpNeg, Some(TypeConstructor::Int)) => {
+                        Ok((ExprT::UnaryOp(op.0, box operand, expr.span()), Type::INT))
+                    }
+                    (UnaryOperator::UnOpNeg, Some(TypeConstructor::Float)) => {
+                        Ok((ExprT::UnaryOp(op.0, box operand, expr.span()), Type::FLOAT))
+                    }
+                    (UnaryOperator::UnOpNot, Some(TypeConstructor::Bool)) => {
+                        Ok((ExprT::UnaryOp(op.0, box operand, expr.span()), Type::BOOL))
+                    }
+                    _ => Err(TypeCheckingError::GenericError(
+                        format!(
+                            "Unary Operator {:?} is not defined for type {:?}",
+                            op.0, operand.1
+                        ),
+                        expr.span(),
+                    )),
+                }
+            })
+        }
+        Expr::LetBinding(pattern, rhs, body) => {
             let rhs = infer_type(ctx, rhs);
             let mut rhs_t = Type::ErrType;
             let rhs = rhs.map(|(e, t)| {
@@ -576,21 +1003,16 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                 (e, t)
             });
 
-            rhs.and_still(|| {
-                ctx.symbols.insert(binding.0.clone(), rhs_t);
-                let body = infer_type(ctx, body);
-                ctx.symbols.remove(&binding.0);
-                body
-            })
-            .map(|(rhs, body)| {
-                let bt = body.1.clone();
-                (ExprT::LetBinding(binding.0.clone(), box rhs, box body), bt)
-            })
+            rhs.and_still(|| check_let_body(ctx, pattern, &rhs_t, |ctx| infer_type(ctx, body)))
+                .map(|(rhs, (pattern, body))| {
+                    let bt = body.1.clone();
+                    (ExprT::LetBinding(pattern, box rhs, box body), bt)
+                })
         }
         Expr::Symbol(s) => {
             if let Some(t) = ctx.symbols.get(&s.0) {
                 TypeJudgement::Typed {
-                    inner: (ExprT::Symbol(s.0.clone()), t.clone()),
+                    inner: (ExprT::Symbol(s.0.clone(), s.1), t.clone()),
                     constraints: Vec::new(),
                 }
             } else if let Some(b) = ctx.environment.borrow().root_scope.bindings.get(&s.0) {
@@ -599,7 +1021,17 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                     constraints: Vec::new(),
                 }
             } else {
-                TypeJudgement::Error(TypeCheckingError::UnknownSymbol(s.clone()))
+                let env = ctx.environment.borrow();
+                let candidates = ctx
+                    .symbols
+                    .keys()
+                    .chain(env.root_scope.bindings.keys())
+                    .map(String::as_str);
+
+                TypeJudgement::Error(TypeCheckingError::UnknownSymbol(
+                    s.clone(),
+                    closest_name(&s.0, candidates),
+                ))
             }
         }
         Expr::Lambda(p, e) => {
@@ -612,11 +1044,13 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                 t
             };
 
+            ctx.return_types.push(Type::ErrType);
             let r = infer_type(ctx, e);
+            ctx.return_types.pop();
 
             ctx.symbols.remove(&p.0);
             let (r, c) = r?;
-            let e = ExprT::Lambda(p.0.clone(), box (r.0, r.1.clone()));
+            let e = ExprT::lambda(p.0.clone(), (r.0, r.1.clone()));
             let t = Type::function(st, r.1);
 
             TypeJudgement::Typed {
@@ -645,18 +1079,19 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                 (te, Type::ConstructedType(TypeConstructor::UserType(th), ty_params)) => {
                     match ctx.environment.borrow().types[th.index].clone() {
                         TypeDefinition::Record { fields, .. } => {
-                            if let Some((i, (_, ft))) =
-                                fields.iter().enumerate().find(|(_i, (s, _))| s == &f.0)
-                            {
+                            if let Some((_, ft)) = fields.iter().find(|(s, _)| s == &f.0) {
                                 Ok((
-                                    ExprT::FieldAccess(
+                                    ExprT::RecordFieldAccess(
                                         box (te, Type::user_type(th.clone(), ty_params)),
-                                        i,
+                                        f.0.clone(),
                                     ),
                                     ft.clone(),
                                 ))
                             } else {
-                                panic!()
+                                Err(TypeCheckingError::IllegalFieldAccess(
+                                    Spanned("no such field on record".to_owned(), e.span()),
+                                    f.0.clone(),
+                                ))
                             }
                         }
                         _ => Err(TypeCheckingError::IllegalFieldAccess(
@@ -701,6 +1136,62 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
                 )),
             })
         }
+        Expr::RecordUpdate(base, updates) => {
+            let ((base, base_t), _) = infer_type(ctx, base)?;
+
+            let (th, ty_params) = match &base_t {
+                Type::ConstructedType(TypeConstructor::UserType(th), ty_params) => {
+                    (th.clone(), ty_params.clone())
+                }
+                _ => {
+                    return TypeCheckingError::GenericError(
+                        format!("cannot update fields of non record type {:?}", base_t),
+                        expr.span(),
+                    )
+                    .as_judgement()
+                }
+            };
+
+            let (fields, qualified_name) = match ctx.environment.borrow().types[th.index].clone() {
+                TypeDefinition::Record {
+                    fields,
+                    qualified_name,
+                    ..
+                } => (fields, qualified_name),
+                _ => {
+                    return TypeCheckingError::GenericError(
+                        format!("cannot update fields of non record type {:?}", base_t),
+                        expr.span(),
+                    )
+                    .as_judgement()
+                }
+            };
+
+            let mut typed_updates = Vec::new();
+            for (name, e) in updates {
+                if let Some((_, ft)) = fields.iter().find(|(n, _)| n == &name.0) {
+                    typed_updates.push(
+                        check_type(ctx, e, &ft.substitute(&ty_params)).map(|e| (name.0.clone(), e)),
+                    );
+                } else {
+                    return TypeCheckingError::GenericError(
+                        format!("Field {} does not exist on type {}", name.0, qualified_name),
+                        name.1,
+                    )
+                    .as_judgement();
+                }
+            }
+
+            typed_updates
+                .into_iter()
+                .collect::<TypeJudgement<_>>()
+                .map(|updates| {
+                    (
+                        ExprT::RecordUpdate(box (base, base_t.clone()), updates),
+                        base_t,
+                    )
+                })
+        }
         Expr::Unit(_) => TypeJudgement::Typed {
             inner: (ExprT::Unit, Type::UNIT),
             constraints: Vec::new(),
@@ -713,6 +1204,10 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
             inner: (ExprT::IntegerLiteral(i.0), Type::INT),
             constraints: Vec::new(),
         },
+        Expr::FloatLiteral(f) => TypeJudgement::Typed {
+            inner: (ExprT::FloatLiteral(f.0), Type::FLOAT),
+            constraints: Vec::new(),
+        },
         Expr::BooleanLiteral(b) => TypeJudgement::Typed {
             inner: (ExprT::BooleanLiteral(b.0), Type::BOOL),
             constraints: Vec::new(),
@@ -723,16 +1218,34 @@ fn infer_type(ctx: &mut TypecheckingContext, expr: &untyped::Expr) -> TypeJudgem
 
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
-static TYPE_LOCAL_COUNTER: AtomicU32 = AtomicU32::new(0);
 static TYPE_GLOBAL_COUNTER: AtomicU32 = AtomicU32::new(0);
 
 fn resolve_type(ctx: &mut TypecheckingContext, ty: &untyped::Ty) -> Type {
-    fn resolve_type_inner(ctx: &mut TypecheckingContext, ty: &untyped::Ty) -> Type {
+    resolve_type_with_params(ctx, ty, &[])
+}
+
+/// Resolves `ty` with the type variables in `params` numbered by their position,
+/// any others are numbered in order of first appearance after them
+fn resolve_type_with_params(
+    ctx: &mut TypecheckingContext,
+    ty: &untyped::Ty,
+    params: &[Spanned<String>],
+) -> Type {
+    fn resolve_type_inner(
+        ctx: &mut TypecheckingContext,
+        ty: &untyped::Ty,
+        vars: &mut Vec<String>,
+    ) -> Type {
         match ty {
-            Ty::Tuple(tys) => Type::tuple(tys.iter().map(|t| resolve_type_inner(ctx, t)).collect()),
-            Ty::Func(a, b) => {
-                Type::function(resolve_type_inner(ctx, &a), resolve_type_inner(ctx, &b))
-            }
+            Ty::Tuple(tys) => Type::tuple(
+                tys.iter()
+                    .map(|t| resolve_type_inner(ctx, t, vars))
+                    .collect(),
+            ),
+            Ty::Func(a, b) => Type::function(
+                resolve_type_inner(ctx, &a, vars),
+                resolve_type_inner(ctx, &b, vars),
+            ),
             Ty::TypeRef(n, p) => {
                 let typename = match p {
                     None => n.0.clone(),
@@ -757,13 +1270,23 @@ fn resolve_type(ctx: &mut TypecheckingContext, ty: &untyped::Ty) -> Type {
                     return Type::ErrType;
                 }
             }
+            Ty::List(t) => Type::list(resolve_type_inner(ctx, t, vars)),
             Ty::Unit => Type::UNIT,
             Ty::Int => Type::INT,
             Ty::Float => Type::FLOAT,
             Ty::String => Type::STRING,
             Ty::Bool => Type::BOOL,
+            Ty::Char => Type::CHAR,
             Ty::TypeVariable(p) => {
-                Type::TypeVariable(TYPE_LOCAL_COUNTER.fetch_add(1, Ordering::SeqCst))
+                let i = match vars.iter().position(|v| v == &p.0) {
+                    Some(i) => i,
+                    None => {
+                        vars.push(p.0.clone());
+                        vars.len() - 1
+                    }
+                };
+
+                Type::TypeVariable(i as u32)
             }
             Ty::ConstructedType(n, p) => {
                 let t = {
@@ -774,21 +1297,17 @@ fn resolve_type(ctx: &mut TypecheckingContext, ty: &untyped::Ty) -> Type {
                 if let Some(t) = t {
                     Type::ConstructedType(
                         t.clone(),
-                        p.iter().map(|t| resolve_type_inner(ctx, t)).collect(),
+                        p.iter().map(|t| resolve_type_inner(ctx, t, vars)).collect(),
                     )
                 } else {
                     panic!("{:?} not found", n)
                 }
             }
-            _ => {
-                eprintln!("Error while trying to resolve type: {:?}", ty);
-                Type::ErrType
-            }
         }
     }
 
-    TYPE_LOCAL_COUNTER.store(0, Ordering::SeqCst);
-    resolve_type_inner(ctx, ty)
+    let mut vars = params.iter().map(|p| p.0.clone()).collect();
+    resolve_type_inner(ctx, ty, &mut vars)
 }
 
 fn typecheck_type_decl(ctx: &mut TypecheckingContext, decl: untyped::TypeDeclaration) {
@@ -811,7 +1330,24 @@ fn typecheck_type_decl(ctx: &mut TypecheckingContext, decl: untyped::TypeDeclara
                 .into_iter()
                 .enumerate()
                 .map(|(i, (v, t))| {
-                    let (v, t) = (v.0.clone(), resolve_type(ctx, t));
+                    let (v, t) = (
+                        v.0.clone(),
+                        resolve_type_with_params(ctx, t, &decl.type_parameters),
+                    );
+                    let sum_type = Type::user_type(
+                        th.clone(),
+                        decl.type_parameters
+                            .iter()
+                            .enumerate()
+                            .map(|(i, s)| Type::TypeVariable(i as u32))
+                            .collect(),
+                    );
+                    // nullary constructors are values rather than functions of unit
+                    let constructor_type = if t == Type::UNIT {
+                        sum_type
+                    } else {
+                        Type::function(t.clone(), sum_type)
+                    };
 
                     ctx.environment
                         .borrow_mut()
@@ -821,20 +1357,7 @@ fn typecheck_type_decl(ctx: &mut TypecheckingContext, decl: untyped::TypeDeclara
                         .bindings
                         .insert(
                             v.clone(),
-                            (
-                                ExprT::VariantConstructor(th.clone(), i),
-                                Type::function(
-                                    t.clone(),
-                                    Type::user_type(
-                                        th.clone(),
-                                        decl.type_parameters
-                                            .iter()
-                                            .enumerate()
-                                            .map(|(i, s)| Type::TypeVariable(i as u32))
-                                            .collect(),
-                                    ),
-                                ),
-                            ),
+                            (ExprT::VariantConstructor(th.clone(), i), constructor_type),
                         );
 
                     (v, t)
@@ -847,13 +1370,18 @@ fn typecheck_type_decl(ctx: &mut TypecheckingContext, decl: untyped::TypeDeclara
                 variants,
             };
         }
-        untyped::TypeDefinition::Record { fields } => {
+        untyped::TypeDefinition::Record { ref fields } => {
             let td = TypeDefinition::Record {
                 qualified_name: ident.clone(),
                 type_parameters: decl.type_parameters.iter().map(|s| s.0.clone()).collect(),
                 fields: fields
                     .iter()
-                    .map(|(n, t, a)| (n.0.clone(), resolve_type(ctx, t)))
+                    .map(|(n, t, a)| {
+                        (
+                            n.0.clone(),
+                            resolve_type_with_params(ctx, t, &decl.type_parameters),
+                        )
+                    })
                     .collect(),
             };
 
@@ -893,7 +1421,7 @@ pub fn generate_closed_typeclass_instance(
 
     for m in tc.typeclass_members.iter() {
         match &m.definition {
-            untyped::TypeDefinition::Record { fields } => {
+            untyped::TypeDefinition::Record { ref fields } => {
                 let qualified_name = Rc::new(format!("{}.{}", qualified_name, m.ident.0));
 
                 let td = TypeDefinition::Record {
@@ -925,30 +1453,84 @@ pub fn generate_closed_typeclass_instance(
     };
 }
 
+fn prelude() -> untyped::Untyped {
+    let tokens = Scanner::new(include_str!("prelude.ml").chars())
+        .scan_all()
+        .expect("failed to scan the prelude");
+
+    Parser::new(&tokens)
+        .parse()
+        .expect("failed to parse the prelude")
+}
+
 pub fn typecheck(ast: untyped::Untyped) -> Result<TypeChecked, Vec<TypeCheckingError>> {
+    typecheck_with_builtins(ast, BuiltinRegistry::default())
+}
+
+pub fn typecheck_with_builtins(
+    ast: untyped::Untyped,
+    builtins: BuiltinRegistry,
+) -> Result<TypeChecked, Vec<TypeCheckingError>> {
     let mut checking_context = TypecheckingContext::new();
 
-    let builtins = &[
-        ("File_read", BuiltInFn::FileRead),
-        ("String_split", BuiltInFn::StringSplit),
-        ("String_parse_int", BuiltInFn::StringParseInt),
-        ("String_get_first", BuiltInFn::StringGetFirst),
-        ("print", BuiltInFn::Print),
-        ("printi", BuiltInFn::Printi),
-    ];
+    // builtins may refer to prelude types, so those are declared first
+    let (prelude_types, prelude): (Vec<_>, Vec<_>) = prelude()
+        .declarations
+        .into_iter()
+        .partition(|d| matches!(d, Declaration::Type(_)));
+
+    for d in prelude_types {
+        if let Declaration::Type(ty) = d {
+            typecheck_type_decl(&mut checking_context, ty);
+        }
+    }
+
+    // Some, None, Ok and Err are common enough to not need qualifying
+    {
+        let mut env = checking_context.environment.borrow_mut();
+        for ty in &["Option", "Result"] {
+            let constructors = env.scopes[*ty].bindings.clone();
+            env.root_scope.bindings.extend(constructors);
+        }
+    }
 
-    for (name, f) in builtins {
+    checking_context
+        .environment
+        .borrow_mut()
+        .root_scope
+        .type_constructors
+        .insert("Ref".to_owned(), TypeConstructor::Ref);
+    checking_context
+        .environment
+        .borrow_mut()
+        .root_scope
+        .type_constructors
+        .insert("Map".to_owned(), TypeConstructor::Map);
+
+    for (index, (name, builtin)) in builtins.entries().enumerate() {
+        let binding = match builtin {
+            Builtin::Native(f) => (
+                ExprT::BuiltInFn(*f),
+                f.resolved_type(&checking_context.environment),
+            ),
+            Builtin::Host(t, _) => (ExprT::BuiltInFn(BuiltInFn::Host(index)), t.clone()),
+            // looked up in the registry again by the interpreter
+            Builtin::Global(t, _) => (
+                ExprT::Symbol(name.to_owned(), Span(Position(0, 0), Position(0, 0))),
+                t.clone(),
+            ),
+        };
         checking_context
             .environment
             .borrow_mut()
             .root_scope
             .bindings
-            .insert(name.to_string(), (ExprT::BuiltInFn(*f), f.resolved_type()));
+            .insert(name.to_owned(), binding);
     }
 
     let mut errors = Vec::new();
 
-    for d in ast.declarations {
+    for d in prelude.into_iter().chain(ast.declarations) {
         match d {
             Declaration::Type(ty) => typecheck_type_decl(&mut checking_context, ty),
             Declaration::ClosedTypeClass(tc) => {
@@ -1019,6 +1601,7 @@ pub fn typecheck(ast: untyped::Untyped) -> Result<TypeChecked, Vec<TypeCheckingE
     } else {
         Ok(TypeChecked {
             environment: checking_context.environment,
+            builtins: Rc::new(builtins),
         })
     }
 }
@@ -0,0 +1,25 @@
+// types every program can use without declaring them
+
+type Option 'a =
+	| Some of 'a
+	| None
+
+type Result 'a 'e =
+	| Ok of 'a
+	| Err of 'e
+
+// what catch and try hand their handler, the value passed to raise or the
+// message of any other error
+type Failure 'e =
+	| Raised of 'e
+	| Failed of String
+
+// what Json_parse makes of a document, objects keep their fields in order
+type Json =
+	| Null
+	| Boolean of Bool
+	| Integer of Int
+	| Number of Float
+	| Text of String
+	| Array of Json[]
+	| Object of (String, Json)[]